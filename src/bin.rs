@@ -3,7 +3,11 @@
 #[global_allocator]
 static ALLOC: jemallocator::Jemalloc = jemallocator::Jemalloc;
 
-use peermaps_ingest::{Ingest,IngestOptions,EDB,Progress};
+use peermaps_ingest::{
+  Ingest,IngestOptions,EDB,Progress,Changeset,
+  Boundary,Filter,FeatureTypes,feature_types,Coastline,coastline,BatchSizes,Duplicates,
+  Source,FileSource,SpoolSource,BBox,stats,geojson,
+};
 use async_std::{prelude::*,fs,sync::{Arc,RwLock},task,stream};
 use std::io::{Write,Read};
 use desert::{ToBytes,FromBytes};
@@ -11,6 +15,8 @@ use desert::{ToBytes,FromBytes};
 type Error = Box<dyn std::error::Error+Send+Sync>;
 use osmpbf_parser::ScanTable;
 
+const CHECKPOINT_FILE: &str = "ingest-checkpoint";
+
 #[async_std::main]
 async fn main() -> Result<(),Error> {
   if let Err(err) = run().await {
@@ -32,7 +38,7 @@ async fn run() -> Result<(),Error> {
       "no-ingest-node","no-ingest-nodes","no_ingest_node","no_ingest_nodes",
       "no-ingest-way","no-ingest-ways","no_ingest_way","no_ingest_ways",
       "no-ingest-relation","no-ingest-relations","no_ingest_relation","no_ingest_relations",
-      "debug",
+      "debug","resume","clip",
     ])
     .parse(std::env::args());
   if argv.contains_key("help") || argv.contains_key("h") {
@@ -43,8 +49,20 @@ async fn run() -> Result<(),Error> {
     println!["{}", get_version()];
     return Ok(());
   }
+  if let Some(Err(err)) = argv.get("progress").and_then(|x| x.first())
+    .map(|x| x.parse::<ProgressFormat>()) {
+    println!["{}\n", err];
+    print!["{}", usage(&args)];
+    std::process::exit(1);
+  }
+  if let Some(Err(err)) = argv.get("coastline_type").or_else(|| argv.get("coastline-type"))
+    .and_then(|x| x.first()).map(|x| feature_types::parse_type(x)) {
+    println!["invalid value for --coastline_type: {}\n", err];
+    print!["{}", usage(&args)];
+    std::process::exit(1);
+  }
   if argv.contains_key("defaults") {
-    print!["{}", get_defaults()];
+    print!["{}", get_defaults(get_budget_batch_sizes(&argv).await?)];
     return Ok(());
   }
 
@@ -67,21 +85,21 @@ async fn run() -> Result<(),Error> {
         })
         .expect("could not infer --scan_file")
       ;
-      let o_pbf_file = argv.get("pbf").or_else(|| argv.get("f"))
-        .and_then(|x| x.first());
-      if o_pbf_file.is_none() {
-        println!["--pbf or -f option required\n"];
+      let o_pbf = get_pbf_file(&argv)?;
+      if o_pbf.is_none() {
+        println!["--pbf or -f option required when stdin is a terminal\n"];
         print!["{}", usage(&args)];
         std::process::exit(1);
       }
-      let pbf_file = o_pbf_file.unwrap();
+      let pbf = o_pbf.unwrap();
+      let pbf_file = pbf.path.as_str();
       let mut ingest = Ingest::new(&["scan"]);
       let scan_table = {
         if argv.contains_key("no-monitor") {
-          ingest.scan(&pbf_file).await
+          ingest.scan(&pbf_file).await?
         } else {
-          let mut p = Monitor::open(ingest.progress.clone());
-          let scan_table = ingest.scan(&pbf_file).await;
+          let mut p = Monitor::open(ingest.progress.clone(), get_progress_format(&argv));
+          let scan_table = ingest.scan(&pbf_file).await?;
           p.end().await;
           scan_table
         }
@@ -110,45 +128,44 @@ async fn run() -> Result<(),Error> {
         file.read_to_end(&mut buf)?;
         ScanTable::from_bytes(&buf)?.1
       };
-      let o_pbf_file = argv.get("pbf").or_else(|| argv.get("f"))
-        .and_then(|x| x.first());
-      if o_pbf_file.is_none() {
-        println!["--pbf or -f option required\n"];
+      let o_pbf = get_pbf_file(&argv)?;
+      if o_pbf.is_none() {
+        println!["--pbf or -f option required when stdin is a terminal\n"];
         print!["{}", usage(&args)];
         std::process::exit(1);
       }
-      let pbf_file = o_pbf_file.unwrap();
+      let pbf = o_pbf.unwrap();
+      let pbf_file = pbf.path.as_str();
       let ingest_options = get_ingest_options(&argv);
       let edb_dir = get_dirs(&argv);
       if edb_dir.is_none() {
         print!["{}", usage(&args)];
         std::process::exit(1);
       }
-      let mut ingest = Ingest::new(&["ingest"]);
+      let mut ingest = get_ingest(&argv, &["ingest"])?;
       if argv.contains_key("no-monitor") {
         ingest.ingest(
           open_eyros(&std::path::Path::new(&edb_dir.unwrap()), &argv).await?,
           &pbf_file, scan_table,
           &ingest_options
-        ).await;
+        ).await?;
       } else {
-        let mut p = Monitor::open(ingest.progress.clone());
+        let mut p = Monitor::open(ingest.progress.clone(), get_progress_format(&argv));
         ingest.ingest(
           open_eyros(&std::path::Path::new(&edb_dir.unwrap()), &argv).await?,
           &pbf_file, scan_table, &ingest_options
-        ).await;
+        ).await?;
         p.end().await;
       }
     },
     Some("ingest") => {
-      let o_pbf_file = argv.get("pbf").or_else(|| argv.get("f"))
-        .and_then(|x| x.first());
-      if o_pbf_file.is_none() {
-        println!["--pbf or -f option required\n"];
+      let pbfs = get_pbf_files(&argv)?;
+      if pbfs.is_empty() {
+        println!["--pbf or -f option required when stdin is a terminal\n"];
         print!["{}", usage(&args)];
         std::process::exit(1);
       }
-      let pbf_file = o_pbf_file.unwrap();
+      let pbf_files = pbfs.iter().map(|pbf| pbf.path.clone()).collect::<Vec<String>>();
       let ingest_options = get_ingest_options(&argv);
       let o_edb_dir = get_dirs(&argv);
       if o_edb_dir.is_none() {
@@ -156,38 +173,47 @@ async fn run() -> Result<(),Error> {
         std::process::exit(1);
       }
       let edb_dir = o_edb_dir.unwrap();
-      let mut ingest = Ingest::new(&["scan","ingest","optimize"]);
+      let stages: &[&str] = if pbf_files.len() > 1 {
+        &["scan","dedup","ingest","optimize"]
+      } else {
+        &["scan","ingest","optimize"]
+      };
+      let mut ingest = get_ingest(&argv, stages)?;
       let in_edb_dir = std::path::Path::new(&edb_dir);
       let out_edb_dir_s = edb_dir.clone() + "_";
       let out_edb_dir = std::path::Path::new(&out_edb_dir_s);
       if argv.contains_key("no-monitor") {
-        let scan_table = ingest.scan(&pbf_file).await;
-        ingest.ingest(
-          open_eyros(&in_edb_dir, &argv).await?,
-          &pbf_file, scan_table, &ingest_options
-        ).await;
+        ingest_pbfs(
+          &mut ingest, open_eyros(&in_edb_dir, &argv).await?, &pbf_files, &ingest_options
+        ).await?;
         if let Some(optimize) = ingest_options.optimize {
+          if ingest_options.resume && out_edb_dir.exists() {
+            fs::remove_dir_all(out_edb_dir).await?;
+          }
           ingest.optimize(
             open_eyros(&in_edb_dir, &argv).await?,
             open_eyros(&out_edb_dir, &argv).await?,
             optimize,
           ).await?;
+          copy_checkpoint(&ingest_options, out_edb_dir).await?;
           fs::remove_dir_all(in_edb_dir).await?;
           fs::rename(out_edb_dir, in_edb_dir).await?;
         }
       } else {
-        let mut p = Monitor::open(ingest.progress.clone());
-        let scan_table = ingest.scan(&pbf_file).await;
-        ingest.ingest(
-          open_eyros(&in_edb_dir, &argv).await?,
-          &pbf_file, scan_table, &ingest_options
-        ).await;
+        let mut p = Monitor::open(ingest.progress.clone(), get_progress_format(&argv));
+        ingest_pbfs(
+          &mut ingest, open_eyros(&in_edb_dir, &argv).await?, &pbf_files, &ingest_options
+        ).await?;
         if let Some(optimize) = ingest_options.optimize {
+          if ingest_options.resume && out_edb_dir.exists() {
+            fs::remove_dir_all(out_edb_dir).await?;
+          }
           ingest.optimize(
             open_eyros(&in_edb_dir, &argv).await?,
             open_eyros(&out_edb_dir, &argv).await?,
             optimize,
           ).await?;
+          copy_checkpoint(&ingest_options, out_edb_dir).await?;
           fs::remove_dir_all(in_edb_dir).await?;
           fs::rename(out_edb_dir, in_edb_dir).await?;
         }
@@ -215,7 +241,7 @@ async fn run() -> Result<(),Error> {
         fs::remove_dir_all(in_edb_dir).await?;
         fs::rename(out_edb_dir, in_edb_dir).await?;
       } else {
-        let mut p = Monitor::open(ingest.progress.clone());
+        let mut p = Monitor::open(ingest.progress.clone(), get_progress_format(&argv));
         ingest.optimize(
           open_eyros(&in_edb_dir, &argv).await?,
           open_eyros(&out_edb_dir, &argv).await?,
@@ -227,7 +253,154 @@ async fn run() -> Result<(),Error> {
       }
     },
     Some("changeset") => {
-      unimplemented![]
+      let osc_files = argv.get("osc").cloned().unwrap_or_default();
+      if osc_files.is_empty() {
+        println!["--osc option required\n"];
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let o_pbf = get_pbf_file(&argv)?;
+      if o_pbf.is_none() {
+        println!["--pbf or -f option required when stdin is a terminal\n"];
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let pbf = o_pbf.unwrap();
+      let pbf_file = pbf.path.as_str();
+      let ingest_options = get_ingest_options(&argv);
+      let o_edb_dir = get_dirs(&argv);
+      if o_edb_dir.is_none() {
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let edb_dir = o_edb_dir.unwrap();
+      let mut changeset = Changeset::default();
+      for osc_file in osc_files.iter() {
+        changeset.read(std::io::BufReader::new(std::fs::File::open(osc_file)?))?;
+      }
+      let o_scan_file = argv.get("scan_file").or_else(|| argv.get("scan-file"))
+        .and_then(|x| x.first());
+      let mut ingest = get_ingest(&argv, &["scan","changeset"])?;
+      let mut p = if argv.contains_key("no-monitor") {
+        None
+      } else {
+        Some(Monitor::open(ingest.progress.clone(), get_progress_format(&argv)))
+      };
+      let scan_table = if let Some(scan_file) = o_scan_file {
+        let mut file = std::fs::File::open(scan_file)?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        ScanTable::from_bytes(&buf)?.1
+      } else {
+        ingest.scan(&pbf_file).await?
+      };
+      ingest.changeset(
+        open_eyros(&std::path::Path::new(&edb_dir), &argv).await?,
+        &pbf_file, scan_table, &changeset, &ingest_options
+      ).await?;
+      if let Some(p) = p.as_mut() {
+        p.end().await;
+      }
+    },
+    Some("verify") => {
+      let o_pbf = get_pbf_file(&argv)?;
+      if o_pbf.is_none() {
+        println!["--pbf or -f option required when stdin is a terminal\n"];
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let pbf = o_pbf.unwrap();
+      let ingest_options = get_ingest_options(&argv);
+      let o_edb_dir = get_dirs(&argv);
+      if o_edb_dir.is_none() {
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let edb_dir = o_edb_dir.unwrap();
+      let o_scan_file = argv.get("scan_file").or_else(|| argv.get("scan-file"))
+        .and_then(|x| x.first());
+      let mut ingest = get_ingest(&argv, &["scan","ingest","verify"])?;
+      let mut p = if argv.contains_key("no-monitor") {
+        None
+      } else {
+        Some(Monitor::open(ingest.progress.clone(), get_progress_format(&argv)))
+      };
+      let source: Arc<dyn Source> = Arc::new(FileSource::new(&pbf.path));
+      let scan_table = if let Some(scan_file) = o_scan_file {
+        let mut file = std::fs::File::open(scan_file)?;
+        let mut buf = vec![];
+        file.read_to_end(&mut buf)?;
+        ScanTable::from_bytes(&buf)?.1
+      } else {
+        ingest.scan_source(source.as_ref()).await?
+      };
+      let report = ingest.verify(
+        open_eyros(&std::path::Path::new(&edb_dir), &argv).await?,
+        source, scan_table, &ingest_options
+      ).await?;
+      if let Some(p) = p.as_mut() {
+        p.end().await;
+      }
+      print!["{}", report];
+      if !report.is_ok() {
+        std::process::exit(1);
+      }
+    },
+    Some("stats") => {
+      let o_edb_dir = get_dirs(&argv);
+      if o_edb_dir.is_none() {
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let edb_dir = o_edb_dir.unwrap();
+      let top = argv.get("top")
+        .and_then(|x| x.first())
+        .map(|x| x.replace("_","").parse().expect("invalid number for --top"))
+        .unwrap_or(10);
+      let json = match argv.get("format").and_then(|x| x.first()).map(|x| x.as_str()) {
+        None | Some("table") => false,
+        Some("json") => true,
+        Some(x) => {
+          eprintln!["unknown format {}. expected table or json", x];
+          std::process::exit(1);
+        },
+      };
+      let progress = Arc::new(RwLock::new(Progress::new(&["stats"])));
+      let mut p = if argv.contains_key("no-monitor") {
+        None
+      } else {
+        Some(Monitor::open(progress.clone(), get_progress_format(&argv)))
+      };
+      let mut db = open_eyros(&std::path::Path::new(&edb_dir), &argv).await?;
+      let stats = stats::collect(progress, &mut db, top).await?;
+      if let Some(p) = p.as_mut() {
+        p.end().await;
+      }
+      if json {
+        println!["{}", stats.to_json()];
+      } else {
+        print!["{}", stats];
+      }
+    },
+    Some("query") => {
+      let o_edb_dir = get_dirs(&argv);
+      if o_edb_dir.is_none() {
+        print!["{}", usage(&args)];
+        std::process::exit(1);
+      }
+      let edb_dir = o_edb_dir.unwrap();
+      let bbox: BBox = match argv.get("bbox").and_then(|x| x.first()) {
+        Some(bbox) => bbox.parse()?,
+        None => "-180,-90,180,90".parse()?,
+      };
+      let format: geojson::Format = match argv.get("format").and_then(|x| x.first()) {
+        Some(format) => format.parse()?,
+        None => geojson::Format::default(),
+      };
+      let mut db = open_eyros(&std::path::Path::new(&edb_dir), &argv).await?;
+      let stdout = std::io::stdout();
+      let mut out = std::io::BufWriter::new(stdout.lock());
+      geojson::write(&mut db, &bbox, format, &mut out).await?;
     },
     Some(cmd) => {
       eprintln!["unrecognized command {}", cmd];
@@ -289,23 +462,49 @@ fn usage(args: &[String]) -> String {
 
     ingest - scans and processes a pbf
       -f, --pbf     osm pbf file to ingest or "-" for stdin (default)
+                    give more than once to merge overlapping extracts
+      --tmpdir      spool stdin to a temp file in this dir
       -e, --edb     eyros db dir to write spatial data
       -o, --outdir  write eyros db in this dir in edb/
 
       --no-ingest-node      skip over processing nodes
       --no-ingest-way       skip over processing ways
       --no-ingest-relation  skip over processing relations
-      --defaults            Print default values for ingest parameters.
+      --resume              save checkpoints in the eyros db dir and continue from
+                            the last one. the db is only synced at checkpoints
+                            and the same pbf files have to be given in order
+      --bbox=W,S,E,N        only keep elements that touch this box
+      --boundary=FILE       only keep elements that touch the polygons in a .poly or
+                            geojson file
+      --clip                cut lines and areas at the edge of the --bbox or --boundary
+      --filter=EXPR         only encode elements whose tags match EXPR,
+                            like 'highway=* and not access=private'
+      --node_filter=EXPR    filter for nodes only. also --way_filter, --relation_filter
+      --feature_types=FILE  json rules that map tags to feature types
+      --coastline=FILL      assemble coastlines into water or land areas
+      --coastline_type=T    feature type name or id of the coastline areas
+      --flat_nodes=FILE     keep node locations in FILE instead of re-reading node blobs
+      --memory_budget=SIZE  size batches and channels to fit in SIZE, like 24G.
+                            replaces --channel_size and the way and relation batch sizes
+      --relation_depth=N    levels of relations inside of relations to resolve
+                            (default 8)
+      --error_policy=P      fail, skip or skip-and-log elements that can't be encoded
+      --rejects=FILE        write skipped elements to FILE as ndjson
+      --defaults            Print default values for ingest parameters. with
+                            --memory_budget and --pbf or --scan_file, print the
+                            sizes picked for that pbf
 
       This step will optimize when --optimize is provided.
 
     scan - scans a pbf, outputting a scan file
       -f, --pbf     osm pbf file to ingest or "-" for stdin (default)
+      --tmpdir      spool stdin to a temp file in this dir
       -o, --outdir  write a scan file in this dir
       --scan_file   write scan file with explicit path
 
     ingest-from-scan - process a pbf from an existing scan
       -f, --pbf     osm pbf file to ingest or "-" for stdin (default)
+      --tmpdir      spool stdin to a temp file in this dir
       -e, --edb     eyros db dir to write spatial data
       -o, --outdir  write eyros db in this dir in edb/ and read scan file
       --scan_file   read scan file with explicit path
@@ -313,13 +512,78 @@ fn usage(args: &[String]) -> String {
       --no-ingest-node      skip over processing nodes
       --no-ingest-way       skip over processing ways
       --no-ingest-relation  skip over processing relations
-      --defaults            Print default values for ingest parameters.
+      --resume              save checkpoints in the eyros db dir and continue from
+                            the last one. the db is only synced at checkpoints
+                            and the same pbf files have to be given in order
+      --bbox=W,S,E,N        only keep elements that touch this box
+      --boundary=FILE       only keep elements that touch the polygons in a .poly or
+                            geojson file
+      --clip                cut lines and areas at the edge of the --bbox or --boundary
+      --filter=EXPR         only encode elements whose tags match EXPR,
+                            like 'highway=* and not access=private'
+      --node_filter=EXPR    filter for nodes only. also --way_filter, --relation_filter
+      --feature_types=FILE  json rules that map tags to feature types
+      --coastline=FILL      assemble coastlines into water or land areas
+      --coastline_type=T    feature type name or id of the coastline areas
+      --flat_nodes=FILE     keep node locations in FILE instead of re-reading node blobs
+      --memory_budget=SIZE  size batches and channels to fit in SIZE, like 24G.
+                            replaces --channel_size and the way and relation batch sizes
+      --relation_depth=N    levels of relations inside of relations to resolve
+                            (default 8)
+      --error_policy=P      fail, skip or skip-and-log elements that can't be encoded
+      --rejects=FILE        write skipped elements to FILE as ndjson
+      --defaults            Print default values for ingest parameters. with
+                            --memory_budget and --pbf or --scan_file, print the
+                            sizes picked for that pbf
+
+    changeset - apply osmChange (.osc) files to an existing eyros db
+      --osc         osmChange file to apply. may be given more than once
+      -f, --pbf     osm pbf file the eyros db was ingested from, with every
+                    earlier changeset applied to it (like with osmium apply-changes)
+      -e, --edb     eyros db dir to update
+      -o, --outdir  update eyros db in this dir in edb/
+      --scan_file   read scan file with explicit path instead of scanning
+      --error_policy=P  fail, skip or skip-and-log elements that can't be encoded
+      --relation_depth=N  levels of relations inside of relations to resolve
+      --rejects=FILE    write skipped elements to FILE as ndjson
+      --filter=EXPR     only encode elements whose tags match EXPR
+      --feature_types=FILE  json rules that map tags to feature types
+
+    verify - compare an eyros db with the pbf it was ingested from. prints missing,
+             duplicated and unexpected elements and exits non-zero if there are any
+             row counts for every element are held in memory, about 20 bytes each
+      -f, --pbf     osm pbf file the eyros db was ingested from
+      -e, --edb     eyros db dir to check
+      -o, --outdir  check eyros db in this dir in edb/
+      --scan_file   read scan file with explicit path instead of scanning
+      --bbox=W,S,E,N        only expect elements that touch this box
+      --boundary=FILE       only expect elements that touch the polygons in FILE
+      --filter=EXPR         only expect elements whose tags match EXPR
+      --feature_types=FILE  json rules that map tags to feature types
+      --no-ingest-node      don't expect nodes. also --no-ingest-way, --no-ingest-relation
+
+    stats - count the rows and bytes of an eyros db by feature type and geometry
+      -e, --edb       eyros db dir to read
+      -o, --outdir    read eyros db in this dir in edb/
+      --format=F      print a table (default) or json
+      --top=N         list the N largest features by payload size (default 10)
+
+    query - print the features of an eyros db in a box as geojson
+      -e, --edb       eyros db dir to read
+      -o, --outdir    read eyros db in this dir in edb/
+      --bbox=W,S,E,N  only print features that touch this box (default the whole world)
+      --format=F      geojson for a FeatureCollection (default) or ndjson for one
+                      feature per line
 
     optimize - recursively rebuild tree sections to improve query performance
       --optimize=X,Y  divide into a grid of X*Y sublevels to rebuild the tree
       -e, --edb       eyros db dir to write spatial data
       -o, --outdir    write eyros db in this dir in edb/ and read scan file
 
+    --no-monitor       don't print progress
+    --progress=FORMAT  print progress as ansi, plain or json lines to stderr.
+                       defaults to ansi when stderr is a terminal, otherwise plain
+
     -h, --help     Print this help message
     -v, --version  Print the version string ({})
 
@@ -331,14 +595,21 @@ fn get_version() -> &'static str {
   VERSION.unwrap_or("unknown")
 }
 
-fn get_defaults() -> String {
+fn get_defaults(o_batch_sizes: Option<BatchSizes>) -> String {
   let efields = eyros::SetupFields::default();
   let ifields = IngestOptions::default();
+  let batch_sizes = o_batch_sizes.unwrap_or(BatchSizes {
+    channel_size: ifields.channel_size,
+    way_batch_size: ifields.way_batch_size,
+    relation_batch_size: ifields.relation_batch_size,
+  });
   format![
     indoc::indoc![r#"
       --channel_size={}
       --way_batch_size={}
       --relation_batch_size={}
+      --relation_depth={}
+      --error_policy={}
       --optimize={}
       --branch_factor={}
       --max_depth={}
@@ -350,9 +621,11 @@ fn get_defaults() -> String {
       --rebuild_depth={}
       --debug={}
     "#],
-    ifields.channel_size,
-    ifields.way_batch_size,
-    ifields.relation_batch_size,
+    batch_sizes.channel_size,
+    batch_sizes.way_batch_size,
+    batch_sizes.relation_batch_size,
+    ifields.relation_depth,
+    ifields.error_policy,
     match ifields.optimize {
       Some((x_divs,y_divs)) => format!["{},{}", x_divs, y_divs],
       None => "false".to_string(),
@@ -369,6 +642,29 @@ fn get_defaults() -> String {
   ]
 }
 
+// with --memory_budget and a --scan_file or --pbf, --defaults shows the sizes
+// that the budget picks for that extract
+async fn get_budget_batch_sizes(argv: &argmap::Map) -> Result<Option<BatchSizes>,Error> {
+  let ingest_options = get_ingest_options(argv);
+  if ingest_options.memory_budget.is_none() { return Ok(None) }
+  let o_scan_file = argv.get("scan_file").or_else(|| argv.get("scan-file"))
+    .and_then(|x| x.first());
+  let scan_table = if let Some(scan_file) = o_scan_file {
+    let mut file = std::fs::File::open(scan_file)?;
+    let mut buf = vec![];
+    file.read_to_end(&mut buf)?;
+    ScanTable::from_bytes(&buf)?.1
+  } else if argv.contains_key("pbf") || argv.contains_key("f") {
+    match get_pbf_file(argv)? {
+      Some(pbf) => Ingest::new(&["scan"]).scan(&pbf.path).await?,
+      None => return Ok(None),
+    }
+  } else {
+    return Ok(None);
+  };
+  Ok(Some(BatchSizes::new(&ingest_options, &scan_table)))
+}
+
 fn get_dirs(argv: &argmap::Map) -> Option<String> {
   let outdir = argv.get("outdir").or_else(|| argv.get("o"))
     .and_then(|x| x.first());
@@ -382,44 +678,155 @@ fn get_dirs(argv: &argmap::Map) -> Option<String> {
   edb_dir
 }
 
+// the pbf is read many times from many offsets, so stdin gets spooled to a
+// temp file that is removed again when the PbfFile is dropped
+struct PbfFile {
+  path: String,
+  _spool: Option<SpoolSource>,
+}
+
+// every --pbf and -f. stdin can only be read when there is one
+fn get_pbf_files(argv: &argmap::Map) -> Result<Vec<PbfFile>,Error> {
+  let files = argv.get("pbf").into_iter().chain(argv.get("f"))
+    .flatten()
+    .cloned()
+    .collect::<Vec<String>>();
+  if files.len() <= 1 {
+    return Ok(get_pbf_file(argv)?.into_iter().collect());
+  }
+  if files.iter().any(|f| f == "-") {
+    return Err("stdin can't be ingested along with other pbf files".into());
+  }
+  Ok(files.into_iter().map(|path| PbfFile { path, _spool: None }).collect())
+}
+
+// scan the pbfs and ingest them into one db. with more than one, elements that are in
+// several of them are found first so that they are only written once.
+async fn ingest_pbfs(
+  ingest: &mut Ingest, db: EDB, pbf_files: &[String], ingest_options: &IngestOptions,
+) -> Result<(),Error> {
+  let mut sources: Vec<(Arc<dyn Source>,ScanTable)> = vec![];
+  for pbf_file in pbf_files.iter() {
+    let pbf: Arc<dyn Source> = Arc::new(FileSource::new(pbf_file));
+    let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+    sources.push((pbf,scan_table));
+  }
+  let duplicates = if sources.len() > 1 {
+    ingest.dedup(&sources).await?
+  } else {
+    Duplicates::default()
+  };
+  ingest.ingest_files(db, sources, duplicates, ingest_options).await?;
+  Ok(())
+}
+
+fn get_pbf_file(argv: &argmap::Map) -> Result<Option<PbfFile>,Error> {
+  let o_pbf_file = argv.get("pbf").or_else(|| argv.get("f"))
+    .and_then(|x| x.first());
+  match o_pbf_file.map(|x| x.as_str()) {
+    Some("-") => {},
+    Some(pbf_file) => {
+      return Ok(Some(PbfFile { path: pbf_file.to_string(), _spool: None }));
+    },
+    None if atty::is(atty::Stream::Stdin) => return Ok(None),
+    None => {},
+  }
+  let tmpdir = argv.get("tmpdir")
+    .and_then(|x| x.first())
+    .map(|x| std::path::PathBuf::from(x))
+    .unwrap_or_else(|| std::env::temp_dir());
+  let spool = SpoolSource::new("stdin", std::io::stdin().lock(), &tmpdir)?;
+  let path = spool.path().to_str().ok_or("temp file path is not valid utf-8")?.to_string();
+  Ok(Some(PbfFile { path, _spool: Some(spool) }))
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ProgressFormat {
+  // redraw the stage lines in place with ansi escapes
+  Ansi,
+  // one line per running stage per tick, for log files
+  Plain,
+  // one ndjson record per stage per tick
+  Json,
+}
+
+impl std::str::FromStr for ProgressFormat {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    match s {
+      "json" => Ok(ProgressFormat::Json),
+      "plain" => Ok(ProgressFormat::Plain),
+      "ansi" => Ok(ProgressFormat::Ansi),
+      x => Err(format!["invalid value for --progress: {}. expected json, plain or ansi", x].into()),
+    }
+  }
+}
+
+// invalid values of --progress are rejected before any command runs
+fn get_progress_format(argv: &argmap::Map) -> ProgressFormat {
+  match argv.get("progress").and_then(|x| x.first()).and_then(|x| x.parse().ok()) {
+    Some(format) => format,
+    None if atty::is(atty::Stream::Stderr) => ProgressFormat::Ansi,
+    None => ProgressFormat::Plain,
+  }
+}
+
 pub struct Monitor {
   stop: Arc<RwLock<bool>>,
 }
 
 impl Monitor {
-  pub fn open(progress: Arc<RwLock<Progress>>) -> Self {
+  pub fn open(progress: Arc<RwLock<Progress>>, format: ProgressFormat) -> Self {
     let p = progress.clone();
     let stop = Arc::new(RwLock::new(false));
     let s = stop.clone();
     task::spawn(async move {
       let mut interval = stream::interval(std::time::Duration::from_secs(1));
       let mut first = true;
+      let mut ended = std::collections::HashSet::new();
       while let Some(_) = interval.next().await {
         {
           let pr = p.read().await;
-          Self::print(&pr, first);
+          Self::print(&pr, format, first, &mut ended);
           first = false;
         }
         p.write().await.tick();
         if *s.read().await {
           let pr = p.read().await;
-          Self::print(&pr, false);
+          Self::print(&pr, format, false, &mut ended);
           break
         }
       }
     });
     Self { stop }
   }
-  fn print(p: &Progress, first: bool) {
-    let n = p.stages.len();
-    if first {
-      eprint!["{}", p];
-    } else {
-      let mut parts = vec!["\x1b[K"];
-      for _ in 0..n {
-        parts.push("\x1b[1A\x1b[K");
-      }
-      eprint!["{}{}", parts.join(""), p];
+  fn print(
+    p: &Progress, format: ProgressFormat, first: bool,
+    ended: &mut std::collections::HashSet<String>,
+  ) {
+    match format {
+      ProgressFormat::Ansi => {
+        let n = p.stages.len();
+        if first {
+          eprint!["{}", p];
+        } else {
+          let mut parts = vec!["\x1b[K"];
+          for _ in 0..n {
+            parts.push("\x1b[1A\x1b[K");
+          }
+          eprint!["{}{}", parts.join(""), p];
+        }
+      },
+      ProgressFormat::Plain => {
+        for line in p.plain_lines(ended) {
+          eprintln!["{}", line];
+        }
+      },
+      ProgressFormat::Json => {
+        for line in p.json_lines() {
+          eprintln!["{}", line];
+        }
+      },
     }
   }
   pub async fn end(&mut self) {
@@ -427,6 +834,29 @@ impl Monitor {
   }
 }
 
+// keep the checkpoint with the optimized db so that --resume skips the ingest
+async fn copy_checkpoint(
+  ingest_options: &IngestOptions, out_edb_dir: &std::path::Path
+) -> Result<(),Error> {
+  if let Some(file) = ingest_options.checkpoint_file.as_ref().filter(|f| f.exists()) {
+    fs::copy(file, out_edb_dir.join(CHECKPOINT_FILE)).await?;
+  }
+  Ok(())
+}
+
+// load --feature_types rules so unknown types are reported before any work starts
+fn get_ingest(argv: &argmap::Map, stages: &[&str]) -> Result<Ingest,Error> {
+  let o_file = argv.get("feature_types")
+    .or_else(|| argv.get("feature-types"))
+    .and_then(|x| x.first());
+  Ok(match o_file {
+    Some(file) => Ingest::from_feature_types(
+      stages, FeatureTypes::from_file(std::path::Path::new(file))?
+    ),
+    None => Ingest::new(stages),
+  })
+}
+
 fn get_ingest_options(argv: &argmap::Map) -> IngestOptions {
   let mut ingest_options = IngestOptions::default();
   let o_channel_size = argv.get("channel_size")
@@ -443,6 +873,10 @@ fn get_ingest_options(argv: &argmap::Map) -> IngestOptions {
   if let Some(way_batch_size) = o_way_batch_size {
     ingest_options.way_batch_size = way_batch_size;
   }
+  ingest_options.memory_budget = argv.get("memory_budget")
+    .or_else(|| argv.get("memory-budget"))
+    .and_then(|x| x.first())
+    .map(|x| x.parse().expect("invalid value for --memory_budget. expected a size like 24G"));
   let o_relation_batch_size = argv.get("relation_batch_size")
     .or_else(|| argv.get("relation-batch-size"))
     .and_then(|x| x.first())
@@ -450,6 +884,13 @@ fn get_ingest_options(argv: &argmap::Map) -> IngestOptions {
   if let Some(relation_batch_size) = o_relation_batch_size {
     ingest_options.relation_batch_size = relation_batch_size;
   }
+  let o_relation_depth = argv.get("relation_depth")
+    .or_else(|| argv.get("relation-depth"))
+    .and_then(|x| x.first())
+    .map(|x| x.replace("_","").parse().expect("invalid number for --relation_depth"));
+  if let Some(relation_depth) = o_relation_depth {
+    ingest_options.relation_depth = relation_depth;
+  }
   ingest_options.optimize = argv.get("optimize")
     .and_then(|x| x.first())
     .filter(|x| x.ne(&"false") && x.ne(&"None") && x.ne(&"none"))
@@ -485,5 +926,62 @@ fn get_ingest_options(argv: &argmap::Map) -> IngestOptions {
   if o_ingest_relation.is_some() {
     ingest_options.ingest_relation = false;
   }
+  // rows are only synced when a checkpoint is saved, so checkpoints are opt-in
+  ingest_options.resume = argv.contains_key("resume");
+  ingest_options.checkpoint_file = get_dirs(argv)
+    .filter(|_| ingest_options.resume)
+    .map(|d| std::path::PathBuf::from(d).join(CHECKPOINT_FILE));
+  let o_error_policy = argv.get("error_policy")
+    .or_else(|| argv.get("error-policy"))
+    .and_then(|x| x.first())
+    .map(|x| x.parse().expect("invalid value for --error_policy"));
+  if let Some(error_policy) = o_error_policy {
+    ingest_options.error_policy = error_policy;
+  }
+  ingest_options.bbox = argv.get("bbox")
+    .and_then(|x| x.first())
+    .map(|x| x.parse().expect("invalid value for --bbox. expected W,S,E,N"));
+  ingest_options.boundary = argv.get("boundary")
+    .and_then(|x| x.first())
+    .map(|x| Boundary::from_file(std::path::Path::new(x)).expect("invalid --boundary file"));
+  ingest_options.clip = argv.contains_key("clip");
+  let o_filter = argv.get("filter")
+    .and_then(|x| x.first())
+    .map(|x| x.parse::<Filter>().expect("invalid value for --filter"));
+  ingest_options.node_filter = argv.get("node_filter")
+    .or_else(|| argv.get("node-filter"))
+    .and_then(|x| x.first())
+    .map(|x| x.parse().expect("invalid value for --node_filter"))
+    .or_else(|| o_filter.clone());
+  ingest_options.way_filter = argv.get("way_filter")
+    .or_else(|| argv.get("way-filter"))
+    .and_then(|x| x.first())
+    .map(|x| x.parse().expect("invalid value for --way_filter"))
+    .or_else(|| o_filter.clone());
+  ingest_options.relation_filter = argv.get("relation_filter")
+    .or_else(|| argv.get("relation-filter"))
+    .and_then(|x| x.first())
+    .map(|x| x.parse().expect("invalid value for --relation_filter"))
+    .or_else(|| o_filter.clone());
+  ingest_options.coastline = argv.get("coastline").map(|x| {
+    let fill = x.first().map(|f| f.parse().expect("invalid value for --coastline"))
+      .unwrap_or(coastline::Fill::Water);
+    let mut coastline = Coastline::new(fill);
+    let o_type = argv.get("coastline_type")
+      .or_else(|| argv.get("coastline-type"))
+      .and_then(|x| x.first());
+    if let Some(t) = o_type {
+      coastline.feature_type = feature_types::parse_type(t)
+        .expect("invalid value for --coastline_type");
+    }
+    coastline
+  });
+  ingest_options.flat_nodes_file = argv.get("flat_nodes")
+    .or_else(|| argv.get("flat-nodes"))
+    .and_then(|x| x.first())
+    .map(std::path::PathBuf::from);
+  ingest_options.rejects_file = argv.get("rejects")
+    .and_then(|x| x.first())
+    .map(std::path::PathBuf::from);
   ingest_options
 }
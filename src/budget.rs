@@ -0,0 +1,105 @@
+use crate::{Error,IngestOptions,denorm::CH_TAKE_LEN};
+use osmpbf_parser::ScanTable;
+
+// rough memory use of one decoded element in bytes, not counting what it carries
+const ELEMENT_OVERHEAD: u64 = 256;
+// decoded size per compressed byte of a way, with the refs table and node locations
+// that are built to denormalize it
+const WAY_EXPANSION: u64 = 64;
+// relations also hold their member ways and the locations of the nodes in those
+const RELATION_EXPANSION: u64 = 128;
+const NODE_EXPANSION: u64 = 16;
+// share of the budget left for the writer batches and the eyros cache
+const WRITER_SHARE: u64 = 4;
+// share of the budget for elements waiting in channels
+const CHANNEL_SHARE: u64 = 16;
+const MIN_BATCH_SIZE: usize = 1_000;
+const MIN_CHANNEL_SIZE: usize = 4;
+const MAX_CHANNEL_SIZE: usize = 5_000;
+
+// an amount of memory in bytes, parsed from a number with an optional K, M, G or T suffix
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct MemoryBudget {
+  pub bytes: u64,
+}
+
+impl std::str::FromStr for MemoryBudget {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    let t = s.trim().to_uppercase().replace("_","");
+    let t = t.strip_suffix("IB").or_else(|| t.strip_suffix('B')).unwrap_or(&t);
+    let (n,unit) = match t.chars().last() {
+      Some('K') => (&t[..t.len()-1], 1u64 << 10),
+      Some('M') => (&t[..t.len()-1], 1u64 << 20),
+      Some('G') => (&t[..t.len()-1], 1u64 << 30),
+      Some('T') => (&t[..t.len()-1], 1u64 << 40),
+      _ => (t, 1),
+    };
+    let n: f64 = n.trim().parse()
+      .map_err(|_| format!["invalid memory budget {}. expected a size like 24G", s])?;
+    if !n.is_finite() || n <= 0.0 {
+      return Err(format!["invalid memory budget {}. expected a size like 24G", s].into());
+    }
+    Ok(Self { bytes: (n * unit as f64) as u64 })
+  }
+}
+
+impl std::fmt::Display for MemoryBudget {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let units = [(1u64 << 40, "T"), (1u64 << 30, "G"), (1u64 << 20, "M"), (1u64 << 10, "K")];
+    for (size,unit) in units.iter() {
+      if self.bytes >= *size && self.bytes % size == 0 {
+        return write![f, "{}{}", self.bytes / size, unit];
+      }
+    }
+    write![f, "{}", self.bytes]
+  }
+}
+
+// the batch and channel sizes that an ingest runs with
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct BatchSizes {
+  pub channel_size: usize,
+  pub way_batch_size: usize,
+  pub relation_batch_size: usize,
+}
+
+impl BatchSizes {
+  // the sizes from the options, or sized from the scan table to fit the memory budget
+  pub fn new(ingest_options: &IngestOptions, table: &ScanTable) -> Self {
+    let sizes = Self {
+      channel_size: ingest_options.channel_size,
+      way_batch_size: ingest_options.way_batch_size,
+      relation_batch_size: ingest_options.relation_batch_size,
+    };
+    match ingest_options.memory_budget {
+      Some(budget) => sizes.fit(budget, table),
+      None => sizes,
+    }
+  }
+
+  // nodes are streamed, so they only count towards the size of channel chunks
+  fn fit(self, budget: MemoryBudget, table: &ScanTable) -> Self {
+    let node_cost = element_cost(table.node_interval_offsets.values(), NODE_EXPANSION);
+    let way_cost = element_cost(table.way_interval_offsets.values(), WAY_EXPANSION);
+    let relation_cost = element_cost(table.relation_interval_offsets.values(), RELATION_EXPANSION);
+    let available = budget.bytes - budget.bytes / WRITER_SHARE;
+    let chunk_cost = (CH_TAKE_LEN as u64) * node_cost.max(way_cost);
+    Self {
+      channel_size: ((budget.bytes / CHANNEL_SHARE / chunk_cost) as usize)
+        .max(MIN_CHANNEL_SIZE).min(MAX_CHANNEL_SIZE),
+      way_batch_size: ((available / way_cost) as usize).max(MIN_BATCH_SIZE),
+      relation_batch_size: ((available / relation_cost) as usize).max(MIN_BATCH_SIZE),
+    }
+  }
+}
+
+// estimate from the compressed bytes per element of the blobs of one type
+fn element_cost<'a>(
+  offsets: impl Iterator<Item=&'a (u64,usize,usize)>, expansion: u64,
+) -> u64 {
+  let (bytes,count) = offsets
+    .fold((0u64,0u64), |(b,c),(_,byte_len,len)| (b + *byte_len as u64, c + *len as u64));
+  if count == 0 { return ELEMENT_OVERHEAD }
+  ELEMENT_OVERHEAD + (bytes / count).max(1) * expansion
+}
@@ -0,0 +1,236 @@
+use crate::Error;
+use hashbrown::HashMap;
+use osmpbf_parser::element::{self,MemberType};
+use quick_xml::{Reader,events::{Event,BytesStart}};
+use std::io::BufRead;
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Node {
+  pub id: i64,
+  pub lon: f64,
+  pub lat: f64,
+  pub tags: Vec<(String,String)>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Way {
+  pub id: i64,
+  pub refs: Vec<i64>,
+  pub tags: Vec<(String,String)>,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Member {
+  pub member_type: MemberType,
+  pub id: i64,
+  pub role: String,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Relation {
+  pub id: i64,
+  pub members: Vec<Member>,
+  pub tags: Vec<(String,String)>,
+}
+
+// the final state of every element touched by one or more osmChange documents.
+// deleted elements map to None.
+#[derive(Debug,Clone,Default)]
+pub struct Changeset {
+  pub nodes: HashMap<i64,Option<Node>>,
+  pub ways: HashMap<i64,Option<Way>>,
+  pub relations: HashMap<i64,Option<Relation>>,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Action { Create, Modify, Delete }
+
+enum Current { Node(Node), Way(Way), Relation(Relation) }
+
+impl Changeset {
+  pub fn from_reader<R: BufRead>(reader: R) -> Result<Self,Error> {
+    let mut changeset = Self::default();
+    changeset.read(reader)?;
+    Ok(changeset)
+  }
+
+  // apply an osmChange (.osc) document on top of the current state.
+  // later documents and later blocks override earlier ones.
+  pub fn read<R: BufRead>(&mut self, reader: R) -> Result<(),Error> {
+    let mut xml = Reader::from_reader(reader);
+    xml.trim_text(true);
+    let mut buf = vec![];
+    let mut action = None;
+    let mut current = None;
+    loop {
+      let (start,empty) = match xml.read_event(&mut buf)? {
+        Event::Start(ref e) => (Some(e.to_owned()),false),
+        Event::Empty(ref e) => (Some(e.to_owned()),true),
+        Event::End(ref e) => {
+          match e.name() {
+            b"create" | b"modify" | b"delete" => { action = None },
+            b"node" | b"way" | b"relation" => {
+              if let Some(c) = current.take() { self.push(action, c)? }
+            },
+            _ => {},
+          }
+          (None,false)
+        },
+        Event::Eof => break,
+        _ => (None,false),
+      };
+      if let Some(e) = start {
+        match e.name() {
+          b"create" => { action = Some(Action::Create) },
+          b"modify" => { action = Some(Action::Modify) },
+          b"delete" => { action = Some(Action::Delete) },
+          b"node" => {
+            let attrs = attributes(&e)?;
+            current = Some(Current::Node(Node {
+              id: parse_attr(&attrs, "id")?,
+              lon: if action == Some(Action::Delete) { 0.0 } else { parse_attr(&attrs, "lon")? },
+              lat: if action == Some(Action::Delete) { 0.0 } else { parse_attr(&attrs, "lat")? },
+              tags: vec![],
+            }));
+          },
+          b"way" => {
+            let attrs = attributes(&e)?;
+            current = Some(Current::Way(Way {
+              id: parse_attr(&attrs, "id")?,
+              refs: vec![],
+              tags: vec![],
+            }));
+          },
+          b"relation" => {
+            let attrs = attributes(&e)?;
+            current = Some(Current::Relation(Relation {
+              id: parse_attr(&attrs, "id")?,
+              members: vec![],
+              tags: vec![],
+            }));
+          },
+          b"tag" => {
+            let attrs = attributes(&e)?;
+            let tag = (get_attr(&attrs, "k")?.to_string(), get_attr(&attrs, "v")?.to_string());
+            match current.as_mut() {
+              Some(Current::Node(node)) => node.tags.push(tag),
+              Some(Current::Way(way)) => way.tags.push(tag),
+              Some(Current::Relation(relation)) => relation.tags.push(tag),
+              None => {},
+            }
+          },
+          b"nd" => {
+            let attrs = attributes(&e)?;
+            if let Some(Current::Way(way)) = current.as_mut() {
+              way.refs.push(parse_attr(&attrs, "ref")?);
+            }
+          },
+          b"member" => {
+            let attrs = attributes(&e)?;
+            if let Some(Current::Relation(relation)) = current.as_mut() {
+              relation.members.push(Member {
+                member_type: match get_attr(&attrs, "type")? {
+                  "node" => MemberType::Node,
+                  "way" => MemberType::Way,
+                  "relation" => MemberType::Relation,
+                  t => return Err(format!["unexpected member type {}", t].into()),
+                },
+                id: parse_attr(&attrs, "ref")?,
+                role: get_attr(&attrs, "role").unwrap_or("").to_string(),
+              });
+            }
+          },
+          _ => {},
+        }
+        if empty {
+          if let b"node" | b"way" | b"relation" = e.name() {
+            if let Some(c) = current.take() { self.push(action, c)? }
+          }
+        }
+      }
+      buf.clear();
+    }
+    Ok(())
+  }
+
+  fn push(&mut self, action: Option<Action>, current: Current) -> Result<(),Error> {
+    let delete = match action {
+      Some(Action::Delete) => true,
+      Some(Action::Create) | Some(Action::Modify) => false,
+      None => return Err("element outside of a create, modify or delete block".into()),
+    };
+    match current {
+      Current::Node(node) => {
+        self.nodes.insert(node.id, if delete { None } else { Some(node) });
+      },
+      Current::Way(way) => {
+        self.ways.insert(way.id, if delete { None } else { Some(way) });
+      },
+      Current::Relation(relation) => {
+        self.relations.insert(relation.id, if delete { None } else { Some(relation) });
+      },
+    }
+    Ok(())
+  }
+}
+
+fn attributes(e: &BytesStart) -> Result<Vec<(String,String)>,Error> {
+  let mut attrs = vec![];
+  for r_attr in e.attributes() {
+    let attr = r_attr?;
+    attrs.push((
+      String::from_utf8(attr.key.to_vec())?,
+      String::from_utf8(attr.unescaped_value()?.to_vec())?,
+    ));
+  }
+  Ok(attrs)
+}
+
+fn get_attr<'a>(attrs: &'a [(String,String)], key: &str) -> Result<&'a str,Error> {
+  attrs.iter().find(|(k,_)| k == key).map(|(_,v)| v.as_str())
+    .ok_or_else(|| format!["missing attribute {}", key].into())
+}
+
+fn parse_attr<T: std::str::FromStr>(attrs: &[(String,String)], key: &str) -> Result<T,Error> {
+  get_attr(attrs, key)?.parse()
+    .map_err(|_| format!["invalid value for attribute {}", key].into())
+}
+
+impl From<&element::Way> for Way {
+  fn from(way: &element::Way) -> Self {
+    Self {
+      id: way.id,
+      refs: way.refs.clone(),
+      tags: way.tags.iter().map(|(k,v)| (k.clone(),v.clone())).collect(),
+    }
+  }
+}
+
+impl From<&element::Relation> for Relation {
+  fn from(relation: &element::Relation) -> Self {
+    Self {
+      id: relation.id,
+      members: relation.members.iter().map(|m| Member {
+        member_type: m.member_type.clone(),
+        id: m.id,
+        role: m.role.clone(),
+      }).collect(),
+      tags: relation.tags.iter().map(|(k,v)| (k.clone(),v.clone())).collect(),
+    }
+  }
+}
+
+impl From<&Relation> for element::Relation {
+  fn from(relation: &Relation) -> Self {
+    Self {
+      id: relation.id,
+      tags: relation.tags.clone(),
+      info: None,
+      members: relation.members.iter().map(|m| element::Member {
+        id: m.id,
+        role: m.role.clone(),
+        member_type: m.member_type.clone(),
+      }).collect(),
+    }
+  }
+}
@@ -0,0 +1,105 @@
+use crate::Error;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum StageState {
+  Pending,
+  // every blob before this offset has been written and synced
+  Offset(u64),
+  Done,
+}
+
+impl Default for StageState {
+  fn default() -> Self { StageState::Pending }
+}
+
+impl StageState {
+  pub fn start_offset(&self) -> u64 {
+    match self {
+      StageState::Offset(offset) => *offset,
+      _ => 0,
+    }
+  }
+  pub fn is_done(&self) -> bool {
+    *self == StageState::Done
+  }
+}
+
+impl std::fmt::Display for StageState {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      StageState::Pending => write![f, "pending"],
+      StageState::Offset(offset) => write![f, "{}", offset],
+      StageState::Done => write![f, "done"],
+    }
+  }
+}
+
+impl std::str::FromStr for StageState {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    match s {
+      "pending" => Ok(StageState::Pending),
+      "done" => Ok(StageState::Done),
+      x => x.parse().map(StageState::Offset)
+        .map_err(|_| format!["invalid checkpoint state {}", x].into()),
+    }
+  }
+}
+
+// progress of an ingest that has been committed to the db with a sync.
+// stored as lines of stage=state so that it can be inspected by hand.
+// rows is the number of rows written to the db so far.
+// rejects is the length of the rejects file at the checkpoint.
+// sources are the names of the input pbfs in order, since offsets span all of them.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Checkpoint {
+  pub node: StageState,
+  pub way: StageState,
+  pub relation: StageState,
+  pub coastline: StageState,
+  pub rows: u64,
+  pub rejects: u64,
+  pub sources: Vec<String>,
+}
+
+impl Checkpoint {
+  pub fn load(file: &std::path::Path) -> Result<Self,Error> {
+    let mut checkpoint = Self::default();
+    if !file.exists() { return Ok(checkpoint) }
+    for line in std::fs::read_to_string(file)?.lines() {
+      if line.trim().is_empty() { continue }
+      let (stage,state) = line.split_once('=')
+        .ok_or_else(|| format!["invalid checkpoint line {}", line])?;
+      match stage.trim() {
+        "rows" => { checkpoint.rows = state.trim().parse()? },
+        "rejects" => { checkpoint.rejects = state.trim().parse()? },
+        "source" => { checkpoint.sources.push(state.to_string()) },
+        stage => checkpoint.set(stage, state.trim().parse()?)?,
+      }
+    }
+    Ok(checkpoint)
+  }
+  pub fn save(&self, file: &std::path::Path) -> Result<(),Error> {
+    let tmp_file = file.with_extension("tmp");
+    let mut data = format![
+      "node={}\nway={}\nrelation={}\ncoastline={}\nrows={}\nrejects={}\n",
+      self.node, self.way, self.relation, self.coastline, self.rows, self.rejects
+    ];
+    for source in self.sources.iter() {
+      data += &format!["source={}\n", source];
+    }
+    std::fs::write(&tmp_file, data)?;
+    std::fs::rename(&tmp_file, file)?;
+    Ok(())
+  }
+  pub fn set(&mut self, stage: &str, state: StageState) -> Result<(),Error> {
+    match stage {
+      "node" => { self.node = state },
+      "way" => { self.way = state },
+      "relation" => { self.relation = state },
+      "coastline" => { self.coastline = state },
+      _ => return Err(format!["invalid checkpoint stage {}", stage].into()),
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,281 @@
+use crate::{Error,extract::BBox,rings::{self,Role}};
+use hashbrown::HashMap;
+
+type Line = Vec<(u64,(f64,f64))>;
+
+// polygons with more points than this are split into quadrants
+const MAX_POINTS: usize = 10_000;
+const MAX_SPLIT_DEPTH: usize = 16;
+
+// which side of the coastline is turned into area features
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Fill {
+  Water,
+  Land,
+}
+
+impl std::str::FromStr for Fill {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    match s {
+      "water" => Ok(Fill::Water),
+      "land" => Ok(Fill::Land),
+      x => Err(format!["unknown coastline fill {}. expected water or land", x].into()),
+    }
+  }
+}
+
+impl std::fmt::Display for Fill {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Fill::Water => write![f, "water"],
+      Fill::Land => write![f, "land"],
+    }
+  }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct Coastline {
+  pub fill: Fill,
+  // feature type of the areas. defaults to natural.water for water and place.island for land
+  pub feature_type: u64,
+}
+
+impl Coastline {
+  pub fn new(fill: Fill) -> Self {
+    let name = match fill {
+      Fill::Water => "natural.water",
+      Fill::Land => "place.island",
+    };
+    Self {
+      fill,
+      feature_type: *georender_pack::osm_types::get_types().get(name).unwrap(),
+    }
+  }
+}
+
+pub fn is_coastline(tags: &[(String,String)]) -> bool {
+  tags.iter().any(|(k,v)| k == "natural" && v == "coastline")
+}
+
+// join coastline ways end to start into chains. coastline ways are drawn with the land
+// on their left, so they are never reversed.
+pub fn stitch(segments: Vec<Line>) -> Vec<Line> {
+  let segments = segments.into_iter().filter(|s| s.len() >= 2).collect::<Vec<Line>>();
+  let mut starts = HashMap::new();
+  let mut ends = HashMap::new();
+  for (i,s) in segments.iter().enumerate() {
+    starts.insert(s[0].0, i);
+    ends.insert(s[s.len()-1].0, i);
+  }
+  let mut used = vec![false;segments.len()];
+  let mut chains = vec![];
+  for i in 0..segments.len() {
+    if used[i] { continue }
+    used[i] = true;
+    let mut chain = segments[i].clone();
+    while chain[0].0 != chain[chain.len()-1].0 {
+      match starts.get(&chain[chain.len()-1].0) {
+        Some(j) if !used[*j] => {
+          used[*j] = true;
+          chain.extend_from_slice(&segments[*j][1..]);
+        },
+        _ => break,
+      }
+    }
+    while chain[0].0 != chain[chain.len()-1].0 {
+      match ends.get(&chain[0].0) {
+        Some(j) if !used[*j] => {
+          used[*j] = true;
+          let mut prev = segments[*j].clone();
+          prev.pop();
+          prev.extend(chain);
+          chain = prev;
+        },
+        _ => break,
+      }
+    }
+    chains.push(chain);
+  }
+  chains
+}
+
+// turn coastline chains into polygons of the fill, each an outer ring followed by its
+// inner rings. chains that cross the edge of the bbox are joined by walking
+// counter-clockwise along the edge. chains that end inside of the bbox are broken
+// coastlines and are left out.
+pub fn polygons(
+  chains: Vec<Line>, fill: Fill, bbox: &BBox, next_id: &mut u64,
+) -> Vec<Vec<(Role,Line)>> {
+  let mut outers = vec![];
+  let mut inners = vec![];
+  let mut parts = vec![];
+  // whether a closed chain around the whole box puts it inside of the fill
+  let mut box_filled = false;
+  for mut chain in chains {
+    // with the water on the left, water polygons come out counter-clockwise
+    if fill == Fill::Water { chain.reverse() }
+    let is_closed = chain.len() >= 4 && chain[0].0 == chain[chain.len()-1].0;
+    if is_closed && chain.iter().all(|(_,p)| bbox.contains(*p)) {
+      let points = chain.iter().map(|(_,p)| *p).collect::<Vec<_>>();
+      if rings::signed_area(&points) > 0.0 {
+        outers.push(chain);
+      } else {
+        inners.push(chain);
+      }
+      continue;
+    }
+    let mut split = bbox.split_line(&chain, next_id);
+    if is_closed && split.is_empty() {
+      let points = chain.iter().map(|(_,p)| *p).collect::<Vec<_>>();
+      if rings::contains(&points, (bbox.west,bbox.south)) {
+        box_filled = rings::signed_area(&points) > 0.0;
+      }
+      continue;
+    }
+    // a closed chain that starts inside of the box continues from its last part
+    if is_closed && split.len() > 1 && split[0][0].0 == chain[0].0
+    && split[split.len()-1][split[split.len()-1].len()-1].0 == chain[0].0 {
+      let first = split.remove(0);
+      split.last_mut().unwrap().extend_from_slice(&first[1..]);
+    }
+    for part in split {
+      if bbox.on_edge(part[0].1) && bbox.on_edge(part[part.len()-1].1) {
+        parts.push(part);
+      }
+    }
+  }
+
+  let perimeter = 2.0 * ((bbox.east - bbox.west) + (bbox.north - bbox.south));
+  let corners = [
+    (bbox.east, bbox.south),
+    (bbox.east, bbox.north),
+    (bbox.west, bbox.north),
+    (bbox.west, bbox.south),
+  ];
+  let positions = parts.iter()
+    .map(|part| (
+      bbox.perimeter_position(part[0].1),
+      bbox.perimeter_position(part[part.len()-1].1),
+    ))
+    .collect::<Vec<(f64,f64)>>();
+  let mut used = vec![false;parts.len()];
+  for i in 0..parts.len() {
+    if used[i] { continue }
+    let mut ring: Line = vec![];
+    let mut j = i;
+    loop {
+      used[j] = true;
+      ring.extend_from_slice(&parts[j]);
+      let exit = positions[j].1;
+      // the next part to enter the box counter-clockwise from where this one left it
+      let o_next = (0..parts.len())
+        .filter(|k| !used[*k] || *k == i)
+        .map(|k| (k, (positions[k].0 - exit).rem_euclid(perimeter)))
+        .fold(None, |best: Option<(usize,f64)>,(k,d)| match best {
+          Some((_,bd)) if bd <= d => best,
+          _ => Some((k,d)),
+        });
+      let (k,d) = match o_next {
+        Some(next) => next,
+        None => break,
+      };
+      let mut between = corners.iter()
+        .map(|c| (*c, (bbox.perimeter_position(*c) - exit).rem_euclid(perimeter)))
+        .filter(|(_,cd)| *cd > 0.0 && *cd < d)
+        .collect::<Vec<_>>();
+      between.sort_by(|a,b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+      for (c,_) in between {
+        ring.push((*next_id, c));
+        *next_id -= 1;
+      }
+      if k == i {
+        ring.push(ring[0]);
+        break;
+      }
+      j = k;
+    }
+    if ring.len() >= 4 {
+      outers.push(ring);
+    }
+  }
+
+  // with only inner rings, such as islands in a box of water, the box is filled
+  if outers.is_empty() && (box_filled || !inners.is_empty()) {
+    let mut ring = corners[3..].iter().chain(corners[..3].iter())
+      .map(|c| { let id = *next_id; *next_id -= 1; (id,*c) })
+      .collect::<Line>();
+    ring.push(ring[0]);
+    outers.push(ring);
+  }
+
+  let outer_points = outers.iter()
+    .map(|ring| ring.iter().map(|(_,p)| *p).collect::<Vec<_>>())
+    .collect::<Vec<_>>();
+  let outer_areas = outer_points.iter()
+    .map(|points| rings::signed_area(points))
+    .collect::<Vec<f64>>();
+  let mut holes = vec![vec![];outers.len()];
+  for inner in inners {
+    let p = inner[0].1;
+    let o_outer = (0..outers.len())
+      .filter(|j| rings::contains(&outer_points[*j], p))
+      .fold(None, |best: Option<usize>,j| match best {
+        Some(b) if outer_areas[b] <= outer_areas[j] => best,
+        _ => Some(j),
+      });
+    if let Some(j) = o_outer {
+      holes[j].push(inner);
+    }
+  }
+  outers.into_iter().zip(holes.into_iter())
+    .map(|(outer,hs)| {
+      std::iter::once((Role::Outer,outer))
+        .chain(hs.into_iter().map(|h| (Role::Inner,h)))
+        .collect()
+    })
+    .collect()
+}
+
+// split a polygon into quadrants until each piece is small enough to store as one row
+pub fn split(
+  polygon: Vec<(Role,Line)>, next_id: &mut u64, out: &mut Vec<Vec<(Role,Line)>>,
+) {
+  split_depth(polygon, next_id, out, 0)
+}
+
+fn split_depth(
+  polygon: Vec<(Role,Line)>, next_id: &mut u64, out: &mut Vec<Vec<(Role,Line)>>, depth: usize,
+) {
+  let n = polygon.iter().map(|(_,line)| line.len()).sum::<usize>();
+  if n <= MAX_POINTS || depth >= MAX_SPLIT_DEPTH {
+    out.push(polygon);
+    return;
+  }
+  let mut bbox = BBox {
+    west: f64::INFINITY, south: f64::INFINITY,
+    east: f64::NEG_INFINITY, north: f64::NEG_INFINITY,
+  };
+  for (_,(lon,lat)) in polygon[0].1.iter() {
+    bbox.west = bbox.west.min(*lon);
+    bbox.south = bbox.south.min(*lat);
+    bbox.east = bbox.east.max(*lon);
+    bbox.north = bbox.north.max(*lat);
+  }
+  let (x,y) = ((bbox.west + bbox.east) * 0.5, (bbox.south + bbox.north) * 0.5);
+  let quadrants = [
+    BBox { west: bbox.west, south: bbox.south, east: x, north: y },
+    BBox { west: x, south: bbox.south, east: bbox.east, north: y },
+    BBox { west: bbox.west, south: y, east: x, north: bbox.north },
+    BBox { west: x, south: y, east: bbox.east, north: bbox.north },
+  ];
+  for q in quadrants.iter() {
+    let clipped = polygon.iter()
+      .map(|(role,line)| (*role, q.clip_ring(line, next_id)))
+      .filter(|(_,line)| line.len() >= 4)
+      .collect::<Vec<(Role,Line)>>();
+    if clipped.first().map(|(role,_)| *role == Role::Outer).unwrap_or(false) {
+      split_depth(clipped, next_id, out, depth + 1);
+    }
+  }
+}
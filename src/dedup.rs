@@ -0,0 +1,222 @@
+use crate::{error::IngestError,denorm::read_items,Progress};
+use hashbrown::HashMap;
+use std::collections::VecDeque;
+use osmpbf_parser::{Scan,ScanTable,Element};
+use std::io::{Read,Seek};
+use std::ops::Bound;
+use async_std::{channel,task,sync::{Arc,RwLock}};
+use futures::future::join_all;
+
+// elements that are in more than one pbf, mapped to the index of the pbf whose copy
+// is ingested. that is the copy with the highest version, or the first pbf on a tie.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Duplicates {
+  pub node: HashMap<i64,usize>,
+  pub way: HashMap<i64,usize>,
+  pub relation: HashMap<i64,usize>,
+}
+
+impl Duplicates {
+  // whether the copy of an element from this source should be ingested
+  pub fn keep(&self, element_type: &str, id: i64, source: usize) -> bool {
+    let winners = match element_type {
+      "node" => &self.node,
+      "way" => &self.way,
+      "relation" => &self.relation,
+      _ => return true,
+    };
+    winners.get(&id).map(|w| *w == source).unwrap_or(true)
+  }
+  pub fn len(&self) -> usize {
+    self.node.len() + self.way.len() + self.relation.len()
+  }
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+// blobs of one source whose id ranges overlap each other, which have to be read
+// together to get their ids in order. a sorted pbf has a run for each blob.
+#[derive(Debug,Clone)]
+struct Run {
+  start: i64,
+  end: i64,
+  blobs: Vec<(u64,usize)>,
+}
+
+// an element type, the runs of every source and the inclusive id range to merge
+type Part = (&'static str, Arc<Vec<Vec<Run>>>, (i64,i64));
+
+// find the elements that are in more than one source. the ids of each source are
+// read in order and merged, so only one run per source is held in memory at a time.
+// runs that don't overlap a run of another source are skipped, and the id space is
+// split into ranges that are merged in parallel.
+pub async fn find_duplicates<F,O>(
+  progress: Arc<RwLock<Progress>>, tables: &[&ScanTable], open_scan: O, nproc: usize,
+) -> Result<Duplicates,IngestError>
+where F: Read+Seek+Send+'static, O: Fn(usize) -> Result<Scan<F>,IngestError> {
+  let mut duplicates = Duplicates::default();
+  if tables.len() < 2 { return Ok(duplicates) }
+  let (part_sender,part_receiver) = channel::unbounded();
+  for element_type in ["node","way","relation"].iter() {
+    let all_runs = tables.iter().map(|table| runs(table, element_type)).collect::<Vec<_>>();
+    let shared = all_runs.iter().enumerate().map(|(i,source_runs)| {
+      source_runs.iter()
+        .filter(|run| all_runs.iter().enumerate().any(|(j,other)| {
+          j != i && overlaps(other, run.start, run.end)
+        }))
+        .cloned()
+        .collect::<Vec<_>>()
+    }).collect::<Vec<_>>();
+    let ranges = split_ids(&shared, nproc.max(1)*4);
+    let shared = Arc::new(shared);
+    for range in ranges {
+      part_sender.send((*element_type,shared.clone(),range)).await.unwrap();
+    }
+  }
+  part_sender.close();
+  let mut work = vec![];
+  for _ in 0..nproc.max(1) {
+    let mut scans = (0..tables.len()).map(|i| open_scan(i)).collect::<Result<Vec<_>,_>>()?;
+    let part_r: channel::Receiver<Part> = part_receiver.clone();
+    let progress = progress.clone();
+    work.push(task::spawn(async move {
+      let mut partial = Duplicates::default();
+      while let Ok((element_type,runs,(lo,hi))) = part_r.recv().await {
+        // the runs left to read and the ids read but not merged yet, for each source
+        let mut streams = runs.iter().map(|source_runs| {
+          let i = source_runs.partition_point(|run| run.end < lo);
+          let pending = source_runs[i..].iter()
+            .take_while(|run| run.start <= hi)
+            .collect::<VecDeque<&Run>>();
+          (pending, VecDeque::new())
+        }).collect::<Vec<_>>();
+        let winners = match element_type {
+          "node" => &mut partial.node,
+          "way" => &mut partial.way,
+          _ => &mut partial.relation,
+        };
+        let mut count = 0;
+        loop {
+          for (source,(pending,ids)) in streams.iter_mut().enumerate() {
+            while ids.is_empty() {
+              let run = match pending.pop_front() {
+                Some(run) => run,
+                None => break,
+              };
+              let mut run_ids = vec![];
+              for (offset,byte_len) in run.blobs.iter() {
+                let elements = read_items(&mut scans[source], element_type, *offset, *byte_len)?;
+                count += elements.len();
+                run_ids.extend(elements.iter()
+                  .filter_map(|element| id_version(element, element_type))
+                  .filter(|(id,_)| *id >= lo && *id <= hi));
+              }
+              run_ids.sort_unstable();
+              ids.extend(run_ids);
+            }
+          }
+          let o_min = streams.iter().filter_map(|(_,ids)| ids.front().map(|(id,_)| *id)).min();
+          let min = match o_min {
+            Some(min) => min,
+            None => break,
+          };
+          let mut copies = vec![];
+          for (source,(_,ids)) in streams.iter_mut().enumerate() {
+            while let Some((_,version)) = ids.front().copied().filter(|(id,_)| *id == min) {
+              copies.push((source,version));
+              ids.pop_front();
+            }
+          }
+          if copies.iter().all(|(s,_)| *s == copies[0].0) { continue }
+          // highest version, then lowest source index
+          let mut best = copies[0];
+          for c in copies.iter() {
+            if c.1 > best.1 || (c.1 == best.1 && c.0 < best.0) { best = *c }
+          }
+          winners.insert(min, best.0);
+        }
+        progress.write().await.add("dedup", count);
+      }
+      Ok::<Duplicates,IngestError>(partial)
+    }));
+  }
+  for r in join_all(work).await {
+    let partial = r?;
+    duplicates.node.extend(partial.node);
+    duplicates.way.extend(partial.way);
+    duplicates.relation.extend(partial.relation);
+  }
+  Ok(duplicates)
+}
+
+fn id_version(element: &Element, element_type: &str) -> Option<(i64,i64)> {
+  match (element, element_type) {
+    (Element::Node(node),"node") => Some((node.id, version(&node.info))),
+    (Element::Way(way),"way") => Some((way.id, version(&way.info))),
+    (Element::Relation(relation),"relation") => Some((relation.id, version(&relation.info))),
+    _ => None,
+  }
+}
+
+// elements without metadata count as version 0
+fn version(info: &Option<osmpbf_parser::element::Info>) -> i64 {
+  info.as_ref().map(|info| info.version as i64).unwrap_or(0)
+}
+
+// the runs of one source in id order
+fn runs(table: &ScanTable, element_type: &str) -> Vec<Run> {
+  let offsets = match element_type {
+    "node" => &table.node_interval_offsets,
+    "way" => &table.way_interval_offsets,
+    _ => &table.relation_interval_offsets,
+  };
+  let mut ranges = offsets.iter()
+    .map(|((start,end),(offset,byte_len,_len))| {
+      (bound_id(start, i64::MIN), bound_id(end, i64::MAX), *offset, *byte_len)
+    })
+    .collect::<Vec<_>>();
+  ranges.sort_unstable();
+  let mut runs: Vec<Run> = vec![];
+  for (start,end,offset,byte_len) in ranges {
+    match runs.last_mut() {
+      Some(run) if start <= run.end => {
+        run.end = run.end.max(end);
+        run.blobs.push((offset,byte_len));
+      },
+      _ => runs.push(Run { start, end, blobs: vec![(offset,byte_len)] }),
+    }
+  }
+  runs
+}
+
+// whether any of the sorted and disjoint runs overlaps start..=end
+fn overlaps(runs: &[Run], start: i64, end: i64) -> bool {
+  let i = runs.partition_point(|run| run.end < start);
+  i < runs.len() && runs[i].start <= end
+}
+
+// inclusive id ranges that cover every id and split the runs into about n parts
+fn split_ids(runs: &[Vec<Run>], n: usize) -> Vec<(i64,i64)> {
+  let mut starts = runs.iter().flatten().map(|run| run.start).collect::<Vec<_>>();
+  if starts.is_empty() { return vec![] }
+  starts.sort_unstable();
+  let mut ranges = vec![];
+  let mut lo = i64::MIN;
+  for i in 1..n {
+    let split = starts[i*starts.len()/n];
+    if split > lo {
+      ranges.push((lo,split-1));
+      lo = split;
+    }
+  }
+  ranges.push((lo,i64::MAX));
+  ranges
+}
+
+fn bound_id(bound: &Bound<i64>, unbounded: i64) -> i64 {
+  match bound {
+    Bound::Included(x) | Bound::Excluded(x) => *x,
+    Bound::Unbounded => unbounded,
+  }
+}
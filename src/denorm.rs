@@ -1,15 +1,25 @@
-use crate::error::Error;
+use crate::{error::IngestError,encode};
 use hashbrown::HashMap;
 use std::collections::HashSet;
 use osmpbf_parser::{Scan,ScanTable,Element,element};
 use std::io::{Read,Seek};
 use async_std::{channel,sync::{Arc,Mutex},task};
 
-const CH_TAKE_LEN: usize = 10_000;
+pub const CH_TAKE_LEN: usize = 10_000;
+
+pub fn read_items<F: Read+Seek>(
+  scan: &mut Scan<F>, stage: &'static str, offset: u64, len: usize,
+) -> Result<Vec<Element>,IngestError> {
+  let blob = scan.parser.read_blob(offset,len)
+    .map_err(|e| IngestError::Blob { stage, offset, source: e.into() })?;
+  let primitive = blob.decode_primitive()
+    .map_err(|e| IngestError::Blob { stage, offset, source: e.into() })?;
+  Ok(primitive.decode())
+}
 
 pub async fn get_nodes_bare_ch<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, n: usize
-) -> channel::Receiver<Vec<(i64,(f64,f64))>> {
+) -> channel::Receiver<Result<Vec<(i64,(f64,f64))>,IngestError>> {
   let (offset_sender,offset_receiver) = channel::unbounded();
   for (offset,byte_len,_len) in scans[0].get_node_blob_offsets() {
     offset_sender.send((offset,byte_len)).await.unwrap();
@@ -20,7 +30,7 @@ pub async fn get_nodes_bare_ch<F: Read+Seek+Send+'static>(
 
 pub async fn get_nodes_bare_ch_from_offsets<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, n: usize, offsets: &[(u64,usize)]
-) -> channel::Receiver<Vec<(i64,(f64,f64))>> {
+) -> channel::Receiver<Result<Vec<(i64,(f64,f64))>,IngestError>> {
   let (offset_sender,offset_receiver) = channel::unbounded();
   for (offset,byte_len) in offsets {
     offset_sender.send((*offset,*byte_len)).await.unwrap();
@@ -31,7 +41,7 @@ pub async fn get_nodes_bare_ch_from_offsets<F: Read+Seek+Send+'static>(
 
 pub async fn get_nodes_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, n: usize, offset_receiver: channel::Receiver<(u64,usize)>,
-) -> channel::Receiver<Vec<(i64,(f64,f64))>> {
+) -> channel::Receiver<Result<Vec<(i64,(f64,f64))>,IngestError>> {
   let mnactive = Arc::new(Mutex::new(scans.len()+1));
   let (node_sender,node_receiver) = channel::bounded(n);
   for mut scan in scans {
@@ -39,9 +49,14 @@ pub async fn get_nodes_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
     let offset_r = offset_receiver.clone();
     let nactive = mnactive.clone();
     task::spawn(async move {
-      while let Ok((offset,len)) = offset_r.recv().await {
-        let blob = scan.parser.read_blob(offset,len).unwrap();
-        let items = blob.decode_primitive().unwrap().decode();
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "node", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            node_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
         let mut iter = items.iter();
         loop {
           let sub_items = (&mut iter)
@@ -52,7 +67,7 @@ pub async fn get_nodes_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
             .take(CH_TAKE_LEN)
             .collect::<Vec<_>>();
           let len = sub_items.len();
-          node_s.send(sub_items).await.unwrap();
+          if node_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
           if len < CH_TAKE_LEN { break }
         }
       }
@@ -73,12 +88,21 @@ pub async fn get_nodes_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
 
 pub async fn get_nodes_ch<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, n: usize
-) -> channel::Receiver<Vec<element::Node>> {
+) -> channel::Receiver<Result<Vec<element::Node>,IngestError>> {
+  let offsets = scans[0].get_node_blob_offsets()
+    .map(|(offset,byte_len,_len)| (offset,byte_len))
+    .collect::<Vec<_>>();
+  get_nodes_ch_from_offsets(scans, n, &offsets).await
+}
+
+pub async fn get_nodes_ch_from_offsets<F: Read+Seek+Send+'static>(
+  scans: Vec<Scan<F>>, n: usize, offsets: &[(u64,usize)]
+) -> channel::Receiver<Result<Vec<element::Node>,IngestError>> {
   let mnactive = Arc::new(Mutex::new(scans.len()+1));
   let (node_sender,node_receiver) = channel::bounded(n);
   let (offset_sender,offset_receiver) = channel::unbounded();
-  for (offset,byte_len,_len) in scans[0].get_node_blob_offsets() {
-    offset_sender.send((offset,byte_len)).await.unwrap();
+  for (offset,byte_len) in offsets {
+    offset_sender.send((*offset,*byte_len)).await.unwrap();
   }
   offset_sender.close();
   for mut scan in scans {
@@ -86,9 +110,14 @@ pub async fn get_nodes_ch<F: Read+Seek+Send+'static>(
     let offset_r = offset_receiver.clone();
     let nactive = mnactive.clone();
     task::spawn(async move {
-      while let Ok((offset,len)) = offset_r.recv().await {
-        let blob = scan.parser.read_blob(offset,len).unwrap();
-        let items = blob.decode_primitive().unwrap().decode();
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "node", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            node_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
         let mut iter = items.iter();
         loop {
           let sub_items = (&mut iter)
@@ -100,7 +129,7 @@ pub async fn get_nodes_ch<F: Read+Seek+Send+'static>(
             .cloned()
             .collect::<Vec<_>>();
           let len = sub_items.len();
-          node_s.send(sub_items).await.unwrap();
+          if node_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
           if len < CH_TAKE_LEN { break }
         }
       }
@@ -151,7 +180,7 @@ pub fn get_node_offsets_from_bare_ways(
 
 pub async fn get_ways_bare_ch<F: Read+Seek+Send+'static>(
   mut scans: Vec<Scan<F>>, n: usize
-) -> channel::Receiver<Vec<(i64,Vec<i64>)>> {
+) -> channel::Receiver<Result<Vec<(i64,Vec<i64>)>,IngestError>> {
   let (offset_sender,offset_receiver) = channel::unbounded();
   for (offset,byte_len,_len) in scans[0].get_way_blob_offsets() {
     offset_sender.send((offset,byte_len)).await.unwrap();
@@ -162,7 +191,7 @@ pub async fn get_ways_bare_ch<F: Read+Seek+Send+'static>(
 
 pub async fn get_ways_bare_ch_from_offsets<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, n: usize, offsets: &[(u64,usize)]
-) -> channel::Receiver<Vec<(i64,Vec<i64>)>> {
+) -> channel::Receiver<Result<Vec<(i64,Vec<i64>)>,IngestError>> {
   let (offset_sender,offset_receiver) = channel::unbounded();
   for (offset,byte_len) in offsets {
     offset_sender.send((*offset,*byte_len)).await.unwrap();
@@ -173,7 +202,7 @@ pub async fn get_ways_bare_ch_from_offsets<F: Read+Seek+Send+'static>(
 
 pub async fn get_ways_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, n: usize, offset_receiver: channel::Receiver<(u64,usize)>,
-) -> channel::Receiver<Vec<(i64,Vec<i64>)>> {
+) -> channel::Receiver<Result<Vec<(i64,Vec<i64>)>,IngestError>> {
   let (way_sender,way_receiver) = channel::bounded(n);
   let mnactive = Arc::new(Mutex::new(scans.len()+1));
   for mut scan in scans {
@@ -181,9 +210,14 @@ pub async fn get_ways_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
     let offset_r = offset_receiver.clone();
     let nactive = mnactive.clone();
     task::spawn(async move {
-      while let Ok((offset,len)) = offset_r.recv().await {
-        let blob = scan.parser.read_blob(offset,len).unwrap();
-        let items = blob.decode_primitive().unwrap().decode();
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "way", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            way_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
         let mut iter = items.iter();
         loop {
           let sub_items = (&mut iter)
@@ -194,7 +228,7 @@ pub async fn get_ways_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
             .take(CH_TAKE_LEN)
             .collect::<Vec<_>>();
           let len = sub_items.len();
-          way_s.send(sub_items).await.unwrap();
+          if way_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
           if len < CH_TAKE_LEN { break }
         }
       }
@@ -213,13 +247,119 @@ pub async fn get_ways_bare_ch_from_offset_ch<F: Read+Seek+Send+'static>(
   way_receiver
 }
 
+pub async fn get_ways_ch_from_offsets<F: Read+Seek+Send+'static>(
+  scans: Vec<Scan<F>>, n: usize, offsets: &[(u64,usize)]
+) -> channel::Receiver<Result<Vec<element::Way>,IngestError>> {
+  let (way_sender,way_receiver) = channel::bounded(n);
+  let (offset_sender,offset_receiver) = channel::unbounded();
+  for (offset,byte_len) in offsets {
+    offset_sender.send((*offset,*byte_len)).await.unwrap();
+  }
+  offset_sender.close();
+  let mnactive = Arc::new(Mutex::new(scans.len()+1));
+  for mut scan in scans {
+    let way_s = way_sender.clone();
+    let offset_r = offset_receiver.clone();
+    let nactive = mnactive.clone();
+    task::spawn(async move {
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "way", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            way_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
+        let mut iter = items.iter();
+        loop {
+          let sub_items = (&mut iter)
+            .filter_map(|element| match element {
+              Element::Way(way) => Some(way),
+              _ => None,
+            })
+            .take(CH_TAKE_LEN)
+            .cloned()
+            .collect::<Vec<_>>();
+          let len = sub_items.len();
+          if way_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
+          if len < CH_TAKE_LEN { break }
+        }
+      }
+      {
+        let mut n = nactive.lock().await;
+        *n -= 1;
+        if *n == 0 { way_s.close(); }
+      }
+    });
+  }
+  {
+    let mut n = mnactive.lock().await;
+    *n -= 1;
+    if *n == 0 { way_sender.close(); }
+  }
+  way_receiver
+}
+
+pub async fn get_relations_ch_from_offsets<F: Read+Seek+Send+'static>(
+  scans: Vec<Scan<F>>, n: usize, offsets: &[(u64,usize)]
+) -> channel::Receiver<Result<Vec<element::Relation>,IngestError>> {
+  let (relation_sender,relation_receiver) = channel::bounded(n);
+  let (offset_sender,offset_receiver) = channel::unbounded();
+  for (offset,byte_len) in offsets {
+    offset_sender.send((*offset,*byte_len)).await.unwrap();
+  }
+  offset_sender.close();
+  let mnactive = Arc::new(Mutex::new(scans.len()+1));
+  for mut scan in scans {
+    let relation_s = relation_sender.clone();
+    let offset_r = offset_receiver.clone();
+    let nactive = mnactive.clone();
+    task::spawn(async move {
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "relation", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            relation_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
+        let mut iter = items.iter();
+        loop {
+          let sub_items = (&mut iter)
+            .filter_map(|element| match element {
+              Element::Relation(relation) => Some(relation),
+              _ => None,
+            })
+            .take(CH_TAKE_LEN)
+            .cloned()
+            .collect::<Vec<_>>();
+          let len = sub_items.len();
+          if relation_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
+          if len < CH_TAKE_LEN { break }
+        }
+      }
+      {
+        let mut n = nactive.lock().await;
+        *n -= 1;
+        if *n == 0 { relation_s.close(); }
+      }
+    });
+  }
+  {
+    let mut n = mnactive.lock().await;
+    *n -= 1;
+    if *n == 0 { relation_sender.close(); }
+  }
+  relation_receiver
+}
+
 pub fn get_way_offsets_from_relations(
   table: &ScanTable, relations: &[element::Relation]
 ) -> Vec<(u64,usize)> {
   let mut offsets = HashSet::new();
   for relation in relations {
     for m in relation.members.iter() {
-      if &m.role != "inner" && &m.role != "outer" { continue }
+      if !encode::is_drawn_member(&m.role) { continue }
       if m.member_type != element::MemberType::Way { continue }
       for offset in table.get_way_blob_offsets_for_id(m.id) {
         offsets.insert(offset);
@@ -231,19 +371,16 @@ pub fn get_way_offsets_from_relations(
     .collect::<Vec<_>>()
 }
 
-pub async fn get_ways<F: Read+Seek+Send+'static>(
-  mut scans: Vec<Scan<F>>, ch_bound: usize, start: u64, n: usize,
-) -> (Option<u64>,Vec<element::Way>) {
-  let mnactive = Arc::new(Mutex::new(scans.len()+1));
-  let (way_sender,way_receiver): (
-    channel::Sender<Vec<element::Way>>,
-    channel::Receiver<Vec<element::Way>>,
-  ) = channel::bounded(ch_bound);
-  let (offset_sender,offset_receiver) = channel::unbounded();
+// select the blobs starting at the offset start that hold up to n elements,
+// along with the offset of the blob where the next batch begins
+pub fn batch_offsets(
+  blob_offsets: impl Iterator<Item=(u64,usize,usize)>, start: u64, n: usize,
+) -> (Option<u64>,Vec<(u64,usize)>) {
   let mut count = 0;
   let mut next_offset = None;
-  let mut offsets = scans[0].get_way_blob_offsets().collect::<Vec<_>>();
+  let mut offsets = blob_offsets.collect::<Vec<_>>();
   offsets.sort_unstable();
+  let mut batch = vec![];
   for (offset,byte_len,len) in offsets {
     if offset < start { continue }
     if count+len > n && offset > start {
@@ -251,18 +388,39 @@ pub async fn get_ways<F: Read+Seek+Send+'static>(
       break;
     }
     count += len;
+    batch.push((offset,byte_len));
+  }
+  assert![next_offset != Some(start), "start offset is the same as next_offset (={})", start];
+  (next_offset,batch)
+}
+
+pub async fn get_ways<F: Read+Seek+Send+'static>(
+  mut scans: Vec<Scan<F>>, ch_bound: usize, start: u64, n: usize,
+) -> Result<(Option<u64>,Vec<element::Way>),IngestError> {
+  let mnactive = Arc::new(Mutex::new(scans.len()+1));
+  let (way_sender,way_receiver): (
+    channel::Sender<Result<Vec<element::Way>,IngestError>>,
+    channel::Receiver<Result<Vec<element::Way>,IngestError>>,
+  ) = channel::bounded(ch_bound);
+  let (offset_sender,offset_receiver) = channel::unbounded();
+  let (next_offset,offsets) = batch_offsets(scans[0].get_way_blob_offsets(), start, n);
+  for (offset,byte_len) in offsets {
     offset_sender.send((offset,byte_len)).await.unwrap();
   }
   offset_sender.close();
-  assert![next_offset != Some(start), "start offset is the same as next_offset (={})", start];
   for mut scan in scans {
     let way_s = way_sender.clone();
     let offset_r = offset_receiver.clone();
     let nactive = mnactive.clone();
     task::spawn(async move {
-      while let Ok((offset,len)) = offset_r.recv().await {
-        let blob = scan.parser.read_blob(offset,len).unwrap();
-        let items = blob.decode_primitive().unwrap().decode();
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "way", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            way_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
         let mut iter = items.iter();
         loop {
           let sub_items = (&mut iter)
@@ -274,7 +432,7 @@ pub async fn get_ways<F: Read+Seek+Send+'static>(
             .cloned()
             .collect::<Vec<_>>();
           let len = sub_items.len();
-          way_s.send(sub_items).await.unwrap();
+          if way_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
           if len < CH_TAKE_LEN { break }
         }
       }
@@ -292,43 +450,38 @@ pub async fn get_ways<F: Read+Seek+Send+'static>(
   }
   let mut ways = Vec::with_capacity(n);
   while let Ok(way_group) = way_receiver.recv().await {
-    ways.extend(way_group);
+    ways.extend(way_group?);
   }
-  (next_offset,ways)
+  Ok((next_offset,ways))
 }
 
 pub async fn get_relations<F: Read+Seek+Send+'static>(
   mut scans: Vec<Scan<F>>, ch_bound: usize, start: u64, n: usize,
-) -> (Option<u64>,Vec<element::Relation>) {
+) -> Result<(Option<u64>,Vec<element::Relation>),IngestError> {
   let mnactive = Arc::new(Mutex::new(scans.len()+1));
   let (relation_sender,relation_receiver): (
-    channel::Sender<Vec<element::Relation>>,
-    channel::Receiver<Vec<element::Relation>>,
+    channel::Sender<Result<Vec<element::Relation>,IngestError>>,
+    channel::Receiver<Result<Vec<element::Relation>,IngestError>>,
   ) = channel::bounded(ch_bound);
   let (offset_sender,offset_receiver) = channel::unbounded();
-  let mut count = 0;
-  let mut next_offset = None;
-  let mut offsets = scans[0].get_relation_blob_offsets().collect::<Vec<_>>();
-  offsets.sort_unstable();
-  for (offset,byte_len,len) in offsets {
-    if offset < start { continue }
-    if count+len > n && offset > start {
-      next_offset = Some(offset);
-      break;
-    }
-    count += len;
+  let (next_offset,offsets) = batch_offsets(scans[0].get_relation_blob_offsets(), start, n);
+  for (offset,byte_len) in offsets {
     offset_sender.send((offset,byte_len)).await.unwrap();
   }
   offset_sender.close();
-  assert![next_offset != Some(start), "start offset is the same as next_offset (={})", start];
   for mut scan in scans {
     let relation_s = relation_sender.clone();
     let offset_r = offset_receiver.clone();
     let nactive = mnactive.clone();
     task::spawn(async move {
-      while let Ok((offset,len)) = offset_r.recv().await {
-        let blob = scan.parser.read_blob(offset,len).unwrap();
-        let items = blob.decode_primitive().unwrap().decode();
+      'blobs: while let Ok((offset,len)) = offset_r.recv().await {
+        let items = match read_items(&mut scan, "relation", offset, len) {
+          Ok(items) => items,
+          Err(err) => {
+            relation_s.send(Err(err)).await.ok();
+            break;
+          },
+        };
         let mut iter = items.iter();
         loop {
           let sub_items = (&mut iter)
@@ -340,7 +493,7 @@ pub async fn get_relations<F: Read+Seek+Send+'static>(
             .cloned()
             .collect::<Vec<_>>();
           let len = sub_items.len();
-          relation_s.send(sub_items).await.unwrap();
+          if relation_s.send(Ok(sub_items)).await.is_err() { break 'blobs }
           if len < CH_TAKE_LEN { break }
         }
       }
@@ -358,9 +511,9 @@ pub async fn get_relations<F: Read+Seek+Send+'static>(
   }
   let mut relations = Vec::with_capacity(n);
   while let Ok(relation_group) = relation_receiver.recv().await {
-    relations.extend(relation_group);
+    relations.extend(relation_group?);
   }
-  (next_offset,relations)
+  Ok((next_offset,relations))
 }
 
 pub fn way_ref_table(ways: &[element::Way]) -> HashMap<i64,Vec<i64>> {
@@ -377,13 +530,27 @@ pub fn way_ref_table(ways: &[element::Way]) -> HashMap<i64,Vec<i64>> {
   ref_table
 }
 
+pub fn bare_way_ref_table(ways: &HashMap<i64,Vec<i64>>) -> HashMap<i64,Vec<i64>> {
+  let mut ref_table: HashMap<i64,Vec<i64>> = HashMap::new();
+  for (way_id,refs) in ways.iter() {
+    for r in refs.iter() {
+      if let Some(way_ids) = ref_table.get_mut(&r) {
+        way_ids.push(*way_id);
+      } else {
+        ref_table.insert(*r, vec![*way_id]);
+      }
+    }
+  }
+  ref_table
+}
+
 pub async fn denormalize_ways(
   ref_table: &HashMap<i64,Vec<i64>>,
-  node_receiver: channel::Receiver<Vec<(i64,(f64,f64))>>,
-) -> Result<HashMap<i64,(f64,f64)>,Error> {
+  node_receiver: channel::Receiver<Result<Vec<(i64,(f64,f64))>,IngestError>>,
+) -> Result<HashMap<i64,(f64,f64)>,IngestError> {
   let mut result: HashMap<i64,(f64,f64)> = HashMap::new();
   while let Ok(nodes) = node_receiver.recv().await {
-    for (node_id,(lon,lat)) in nodes {
+    for (node_id,(lon,lat)) in nodes? {
       if ref_table.contains_key(&node_id) {
         result.insert(node_id,(lon,lat));
       }
@@ -409,28 +576,122 @@ pub fn relation_ref_table(relations: &[element::Relation]) -> HashMap<i64,Vec<i6
 pub async fn denormalize_relations<F: Read+Seek+Send+'static>(
   scans: Vec<Scan<F>>, channel_size: usize,
   relation_ref_table: &HashMap<i64,Vec<i64>>,
-  way_receiver: channel::Receiver<Vec<(i64,Vec<i64>)>>,
-) -> Result<(HashMap<i64,(f64,f64)>,HashMap<i64,Vec<i64>>),Error> {
-  let mut way_deps: HashMap<i64,Vec<i64>> = HashMap::new();
-  let mut way_ref_table: HashMap<i64,Vec<i64>> = HashMap::new();
+  way_receiver: channel::Receiver<Result<Vec<(i64,Vec<i64>)>,IngestError>>,
+) -> Result<(HashMap<i64,(f64,f64)>,HashMap<i64,Vec<i64>>),IngestError> {
+  let way_deps = get_way_deps(relation_ref_table, way_receiver).await?;
+  let way_ref_table = bare_way_ref_table(&way_deps);
+  let node_receiver = {
+    let node_offsets = get_node_offsets_from_bare_ways(&scans[0].table, &way_deps);
+    get_nodes_bare_ch_from_offsets(scans, channel_size, &node_offsets).await
+  };
+  let node_deps = denormalize_ways(&way_ref_table, node_receiver).await?;
+  Ok((node_deps, way_deps))
+}
 
+// refs of the ways that are members of the relations
+pub async fn get_way_deps(
+  relation_ref_table: &HashMap<i64,Vec<i64>>,
+  way_receiver: channel::Receiver<Result<Vec<(i64,Vec<i64>)>,IngestError>>,
+) -> Result<HashMap<i64,Vec<i64>>,IngestError> {
+  let mut way_deps: HashMap<i64,Vec<i64>> = HashMap::new();
   while let Ok(ways) = way_receiver.recv().await {
-    for (way_id,refs) in ways {
+    for (way_id,refs) in ways? {
       if !relation_ref_table.contains_key(&way_id) { continue }
-      for r in refs.iter() {
-        if let Some(way_ids) = way_ref_table.get_mut(&r) {
-          way_ids.push(way_id);
-        } else {
-          way_ref_table.insert(*r, vec![way_id]);
+      way_deps.insert(way_id,refs);
+    }
+  }
+  Ok(way_deps)
+}
+
+// fetch the relations that are members of the given relations, and their relation
+// members in turn, up to max_depth levels deep. open_scans opens the scans of each of
+// the sources and keep says whether to use the copy of a relation from a source.
+pub async fn get_sub_relations<F,O,K>(
+  open_scans: O, sources: usize, keep: K,
+  channel_size: usize, relations: &[element::Relation], max_depth: usize,
+) -> Result<HashMap<i64,element::Relation>,IngestError>
+where
+  F: Read+Seek+Send+'static,
+  O: Fn(usize) -> Result<Vec<Scan<F>>,IngestError>,
+  K: Fn(i64,usize) -> bool,
+{
+  let mut sub_relations: HashMap<i64,element::Relation> = HashMap::new();
+  let mut ids = relation_member_ids(relations.iter());
+  for _ in 0..max_depth {
+    if ids.is_empty() { break }
+    let mut fetched = vec![];
+    for source in 0..sources {
+      let scans = open_scans(source)?;
+      let mut offsets = HashSet::new();
+      for id in ids.iter() {
+        for offset in scans[0].table.get_relation_blob_offsets_for_id(*id) {
+          offsets.insert(offset);
+        }
+      }
+      if offsets.is_empty() { continue }
+      let offsets = offsets.iter()
+        .map(|(offset,byte_len,_len)| (*offset,*byte_len))
+        .collect::<Vec<_>>();
+      let relation_receiver = get_relations_ch_from_offsets(scans, channel_size, &offsets).await;
+      while let Ok(relation_group) = relation_receiver.recv().await {
+        for relation in relation_group? {
+          if ids.contains(&relation.id) && keep(relation.id, source) {
+            fetched.push(relation.id);
+            sub_relations.insert(relation.id, relation);
+          }
         }
       }
-      way_deps.insert(way_id,refs);
+    }
+    ids = relation_member_ids(fetched.iter().filter_map(|id| sub_relations.get(id)));
+    ids.retain(|id| !sub_relations.contains_key(id));
+  }
+  Ok(sub_relations)
+}
+
+fn relation_member_ids<'a>(relations: impl Iterator<Item=&'a element::Relation>) -> HashSet<i64> {
+  relations
+    .flat_map(|relation| relation.members.iter())
+    .filter(|m| m.member_type == element::MemberType::Relation)
+    .map(|m| m.id)
+    .collect()
+}
+
+// replace the relation members of each relation with the members of those relations.
+// members without a role take the role of the relation they came from.
+// sub-relations deeper than max_depth or that would form a cycle are left out.
+pub fn flatten_relations(
+  relations: &mut [element::Relation], sub_relations: &HashMap<i64,element::Relation>,
+  max_depth: usize,
+) {
+  for relation in relations.iter_mut() {
+    if !relation.members.iter().any(|m| m.member_type == element::MemberType::Relation) {
+      continue
+    }
+    let mut members = vec![];
+    let mut path = vec![relation.id];
+    flatten_members(&relation.members, "", sub_relations, max_depth, &mut path, &mut members);
+    relation.members = members;
+  }
+}
+
+fn flatten_members(
+  members: &[element::Member], parent_role: &str,
+  sub_relations: &HashMap<i64,element::Relation>, max_depth: usize,
+  path: &mut Vec<i64>, out: &mut Vec<element::Member>,
+) {
+  for m in members.iter() {
+    if m.member_type == element::MemberType::Relation {
+      if path.len() > max_depth || path.contains(&m.id) { continue }
+      if let Some(sub_relation) = sub_relations.get(&m.id) {
+        let role = if m.role.is_empty() { parent_role } else { m.role.as_str() };
+        path.push(m.id);
+        flatten_members(&sub_relation.members, role, sub_relations, max_depth, path, out);
+        path.pop();
+      }
+    } else {
+      let mut m = m.clone();
+      if m.role.is_empty() { m.role = parent_role.to_string() }
+      out.push(m);
     }
   }
-  let node_receiver = {
-    let node_offsets = get_node_offsets_from_bare_ways(&scans[0].table, &way_deps);
-    get_nodes_bare_ch_from_offsets(scans, channel_size, &node_offsets).await
-  };
-  let node_deps = denormalize_ways(&way_ref_table, node_receiver).await?;
-  Ok((node_deps, way_deps))
 }
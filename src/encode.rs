@@ -0,0 +1,528 @@
+use crate::{P,V,IngestOptions,FeatureTypes,extract::{BBox,Boundary},reject::{Reject,RejectReason},rings::{self,Role,RingError}};
+use hashbrown::HashMap;
+use std::sync::Arc;
+use osmpbf_parser::element;
+
+pub fn tag_refs(tags: &[(String,String)]) -> Vec<(&str,&str)> {
+  tags.iter().map(|(k,v)| (k.as_str(),v.as_str())).collect()
+}
+
+// way members with these roles are drawn as part of a relation.
+// platforms of routes are mapped as features of their own.
+pub fn is_drawn_member(role: &str) -> bool {
+  !role.starts_with("platform")
+}
+
+type Line = Vec<(u64,(f64,f64))>;
+
+// rows are stored under id*3 plus 0 for nodes, 1 for ways and 2 for relations
+const ELEMENT_TYPES: [&str;3] = ["node","way","relation"];
+
+// rows made by ingest that have no osm element, like coastline areas, are numbered up
+// from here. osm ids would need to be over 2^63/3 to reach it.
+pub const GENERATED_ID: u64 = 1 << 63;
+
+// the element type and osm id of an encoded id.
+// generated rows have the type "generated" and are numbered from 0.
+pub fn element_id(encoded_id: u64) -> (&'static str, i64) {
+  if is_generated(encoded_id) {
+    ("generated", (encoded_id - GENERATED_ID) as i64)
+  } else {
+    (ELEMENT_TYPES[(encoded_id % 3) as usize], (encoded_id / 3) as i64)
+  }
+}
+
+pub fn generated_id(i: u64) -> u64 {
+  GENERATED_ID + i
+}
+
+pub fn is_generated(encoded_id: u64) -> bool {
+  encoded_id >= GENERATED_ID
+}
+
+// turns osm elements into eyros rows
+#[derive(Debug,Clone)]
+pub struct Encoder {
+  pub place_other: u64,
+  pub feature_types: Arc<FeatureTypes>,
+  // elements outside of the bbox are dropped
+  pub bbox: Option<BBox>,
+  // elements outside of the boundary polygons are dropped
+  pub boundary: Option<Boundary>,
+  // cut lines and areas at the edge of the bbox and boundary
+  pub clip: bool,
+}
+
+impl Encoder {
+  pub fn new(
+    place_other: u64, feature_types: Arc<FeatureTypes>, ingest_options: &IngestOptions,
+  ) -> Self {
+    Self {
+      place_other,
+      feature_types,
+      bbox: ingest_options.bbox,
+      boundary: ingest_options.boundary.clone(),
+      clip: ingest_options.clip,
+    }
+  }
+
+  // the feature type and labels from georender-pack with the user feature types applied
+  fn parse_tags(&self, tags: &[(&str,&str)]) -> Result<(u64,Vec<u8>),Reject> {
+    let (ft,labels) = georender_pack::tags::parse(tags)
+      .map_err(|e| Reject::from_error(RejectReason::TagParse, e.into()))?;
+    Ok((self.feature_types.classify(tags, ft, self.place_other), labels))
+  }
+
+  // georender-pack a node into an eyros row. nodes outside of the bbox or boundary
+  // give no rows.
+  pub fn node(
+    &self, id: i64, (lon,lat): (f64,f64), tags: &[(&str,&str)],
+  ) -> Result<Vec<(P,V)>,Reject> {
+    if let Some(bbox) = &self.bbox {
+      if !bbox.contains((lon,lat)) { return Ok(vec![]) }
+    }
+    if let Some(boundary) = &self.boundary {
+      if !boundary.contains((lon,lat)) { return Ok(vec![]) }
+    }
+    let (ft,labels) = self.parse_tags(tags)?;
+    if ft == self.place_other { return Ok(vec![]) }
+    let r_encoded = georender_pack::encode::node_from_parsed(
+      (id as u64)*3+0, (lon as f32, lat as f32), ft, &labels
+    );
+    match r_encoded {
+      Ok(encoded) if encoded.is_empty() => Ok(vec![]),
+      Ok(encoded) => Ok(vec![(
+        (eyros::Coord::Scalar(lon as f32), eyros::Coord::Scalar(lat as f32)),
+        encoded.into()
+      )]),
+      Err(e) => Err(Reject::from_error(RejectReason::Encode, e.into())),
+    }
+  }
+
+  // georender-pack a way into eyros rows using the already resolved node locations
+  pub fn way(
+    &self, id: i64, tags: &[(&str,&str)], refs: &[i64],
+    all_node_deps: &HashMap<i64,(f64,f64)>,
+  ) -> Result<Vec<(P,V)>,Reject> {
+    let (ft,labels) = self.parse_tags(tags)?;
+    if ft == self.place_other { return Ok(vec![]) }
+    let refs = refs.iter().map(|r| *r as u64).collect::<Vec<u64>>();
+    let is_area = osm_is_area::way(tags, &refs);
+    self.line((id as u64)*3+1, ft, is_area, &labels, refs, all_node_deps)
+  }
+
+  // georender-pack a relation into eyros rows.
+  // members are (type,id,role). for areas only inner/outer way members are used
+  // and they are stitched into closed rings before encoding.
+  // other relations such as routes become lines through their member ways.
+  pub fn relation(
+    &self, id: i64, tags: &[(&str,&str)],
+    members: &[(element::MemberType,i64,&str)],
+    all_node_deps: &HashMap<i64,(f64,f64)>, all_way_deps: &HashMap<i64,Vec<i64>>,
+  ) -> Result<Vec<(P,V)>,Reject> {
+    let (ft,labels) = self.parse_tags(tags)?;
+    if ft == self.place_other { return Ok(vec![]) }
+    let is_area = osm_is_area::relation(tags, &vec![1]);
+    if !is_area {
+      let lines = concat_members(members, all_way_deps);
+      if lines.is_empty() { return Err(Reject::new(RejectReason::MissingMembers)) }
+      // a route with gaps becomes a row for each connected piece with the same id.
+      // pieces that can't be encoded are left out unless none of them can be.
+      let mut rows = vec![];
+      let mut o_reject = None;
+      for refs in lines {
+        match self.line((id as u64)*3+2, ft, false, &labels, refs, all_node_deps) {
+          Ok(piece_rows) => rows.extend(piece_rows),
+          Err(reject) => { o_reject.get_or_insert(reject); },
+        }
+      }
+      return match o_reject {
+        Some(reject) if rows.is_empty() => Err(reject),
+        _ => Ok(rows),
+      };
+    }
+    let members = members.iter()
+      .filter(|(t,_,_)| *t == element::MemberType::Way)
+      .filter_map(|(_,id,role)| match *role {
+        "outer" => Some((*id,Role::Outer)),
+        "inner" => Some((*id,Role::Inner)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    if members.is_empty() { return Err(Reject::new(RejectReason::MissingMembers)) }
+
+    let rings = rings::assemble(&members, all_way_deps, all_node_deps).map_err(|e| match e {
+      RingError::MissingWay { .. } | RingError::MissingNode { .. } => {
+        Reject::from_error(RejectReason::MissingMembers, e.into())
+      },
+      RingError::Unclosed { .. } | RingError::NoOuter => {
+        Reject::from_error(RejectReason::UnclosedRing, e.into())
+      },
+      RingError::StrayInner { .. } => {
+        Reject::from_error(RejectReason::StrayInner, e.into())
+      },
+    })?;
+    let rings = rings.iter()
+      .map(|ring| (ring.role, ring.refs.iter().map(|r| (*r as u64, all_node_deps[r])).collect()))
+      .collect::<Vec<(Role,Line)>>();
+    // new points from clipping need ids that can't collide with osm node ids
+    let mut next_id = u64::MAX;
+    match self.extract_area(rings, &mut next_id) {
+      Some(rings) => self.areas((id as u64)*3+2, ft, &labels, rings, &mut next_id),
+      None => Ok(vec![]),
+    }
+  }
+
+  // georender-pack an area that was assembled from coastlines
+  pub fn coastline(
+    &self, encoded_id: u64, ft: u64, polygon: &[(Role,Line)],
+  ) -> Result<Option<(P,V)>,Reject> {
+    self.encode_area(encoded_id, ft, &[0], polygon)
+  }
+
+  // georender-pack a line or a single ring area through the node refs
+  fn line(
+    &self, encoded_id: u64, ft: u64, is_area: bool, labels: &[u8], refs: Vec<u64>,
+    all_node_deps: &HashMap<i64,(f64,f64)>,
+  ) -> Result<Vec<(P,V)>,Reject> {
+    let mut line = refs.iter()
+      .filter_map(|r| all_node_deps.get(&(*r as i64)).map(|p| (*r,*p)))
+      .collect::<Line>();
+    let distinct = line.iter().map(|(r,_)| *r).collect::<std::collections::HashSet<u64>>();
+    if distinct.len() <= 1 { return Err(Reject::new(RejectReason::TooFewRefs)) }
+    let mut next_id = u64::MAX;
+    let lines = if self.bbox.is_none() && self.boundary.is_none() {
+      vec![line]
+    } else if is_area {
+      match self.extract_area(vec![(Role::Outer,line)], &mut next_id) {
+        // clipping to a boundary can split an area or cut holes into it
+        Some(rings) if rings.len() > 1 => {
+          return self.areas(encoded_id, ft, labels, rings, &mut next_id);
+        },
+        Some(mut rings) => vec![rings.pop().unwrap().1],
+        None => return Ok(vec![]),
+      }
+    } else {
+      // clipping splits a line into a row for each part that is inside
+      match self.extract_line(line, &mut next_id) {
+        Some(lines) => lines,
+        None => return Ok(vec![]),
+      }
+    };
+    let mut rows = vec![];
+    for line in lines.iter() {
+      rows.extend(self.wrapped_line(encoded_id, ft, is_area, labels, line, &mut next_id)?);
+    }
+    Ok(rows)
+  }
+
+  // a line or a single ring area with a row for each side of the antimeridian it crosses
+  fn wrapped_line(
+    &self, encoded_id: u64, ft: u64, is_area: bool, labels: &[u8], line: &[(u64,(f64,f64))],
+    next_id: &mut u64,
+  ) -> Result<Vec<(P,V)>,Reject> {
+    if !crosses_antimeridian(line) {
+      return Ok(self.encode_line(encoded_id, ft, is_area, labels, line)?.into_iter().collect());
+    }
+    // one row for each side of the antimeridian with the same id
+    let line = unwrap_lon(line);
+    let mut rows = vec![];
+    for (side,shift) in sides().iter() {
+      let pieces = if is_area {
+        vec![side.clip_ring(&line, next_id)]
+      } else {
+        side.split_line(&line, next_id)
+      };
+      for piece in pieces {
+        if piece.len() < if is_area { 4 } else { 2 } { continue }
+        let piece = shift_lon(piece, *shift);
+        rows.extend(self.encode_line(encoded_id, ft, is_area, labels, &piece)?);
+      }
+    }
+    Ok(rows)
+  }
+
+  // georender-pack an area made of rings, each outer followed by its inners.
+  // areas across the antimeridian or with parts on both sides of it are split
+  // into a row for the east and a row for the west.
+  fn areas(
+    &self, encoded_id: u64, ft: u64, labels: &[u8], rings: Vec<(Role,Line)>,
+    next_id: &mut u64,
+  ) -> Result<Vec<(P,V)>,Reject> {
+    let (west,east) = rings.iter()
+      .filter(|(role,_)| *role == Role::Outer)
+      .flat_map(|(_,line)| line.iter())
+      .fold((f64::INFINITY,f64::NEG_INFINITY), |(w,e),(_,(lon,_))| (w.min(*lon),e.max(*lon)));
+    if east - west <= 180.0 && !rings.iter().any(|(_,line)| crosses_antimeridian(line)) {
+      return Ok(self.encode_area(encoded_id, ft, labels, &rings)?.into_iter().collect());
+    }
+    let mut groups: [Vec<(Role,Line)>;2] = [vec![],vec![]];
+    for (role,line) in rings.into_iter() {
+      let pieces = if crosses_antimeridian(&line) {
+        let line = unwrap_lon(&line);
+        sides().iter()
+          .map(|(side,shift)| shift_lon(side.clip_ring(&line, next_id), *shift))
+          .filter(|piece| piece.len() >= 4)
+          .collect::<Vec<Line>>()
+      } else {
+        vec![line]
+      };
+      for piece in pieces {
+        let lon = piece.iter().map(|(_,(lon,_))| *lon).sum::<f64>() / (piece.len() as f64);
+        groups[if lon < 0.0 { 0 } else { 1 }].push((role,piece));
+      }
+    }
+    let mut rows = vec![];
+    for group in groups.iter() {
+      if group.iter().any(|(role,_)| *role == Role::Outer) {
+        rows.extend(self.encode_area(encoded_id, ft, labels, group)?);
+      }
+    }
+    Ok(rows)
+  }
+
+  // georender-pack a line or a single ring area
+  fn encode_line(
+    &self, encoded_id: u64, ft: u64, is_area: bool, labels: &[u8], line: &[(u64,(f64,f64))],
+  ) -> Result<Option<(P,V)>,Reject> {
+    let refs = line.iter().map(|(r,_)| *r).collect::<Vec<u64>>();
+    let pdeps = line.iter()
+      .map(|(r,(lon,lat))| (*r,(*lon as f32,*lat as f32)))
+      .collect::<std::collections::HashMap<u64,(f32,f32)>>();
+    let mut bbox = (f32::INFINITY,f32::INFINITY,f32::NEG_INFINITY,f32::NEG_INFINITY);
+    for (lon,lat) in pdeps.values() {
+      bbox.0 = bbox.0.min(*lon);
+      bbox.1 = bbox.1.min(*lat);
+      bbox.2 = bbox.2.max(*lon);
+      bbox.3 = bbox.3.max(*lat);
+    }
+    let r_encoded = georender_pack::encode::way_from_parsed(
+      encoded_id, ft, is_area, labels, &refs, &pdeps
+    );
+    match r_encoded {
+      Ok(encoded) if encoded.is_empty() => Ok(None),
+      Ok(encoded) => Ok(Some((
+        (eyros::Coord::Interval(bbox.0,bbox.2), eyros::Coord::Interval(bbox.1,bbox.3)),
+        encoded.into()
+      ))),
+      Err(e) => Err(Reject::from_error(RejectReason::Encode, e.into())),
+    }
+  }
+
+  // georender-pack an area made of rings, each outer followed by its inners
+  fn encode_area(
+    &self, encoded_id: u64, ft: u64, labels: &[u8], rings: &[(Role,Line)],
+  ) -> Result<Option<(P,V)>,Reject> {
+    // each ring is passed to georender-pack as a way numbered by its index
+    let mut node_deps = std::collections::HashMap::new();
+    let mut way_deps = std::collections::HashMap::new();
+    let mut ring_members = Vec::with_capacity(rings.len());
+    for (i,(role,line)) in rings.iter().enumerate() {
+      ring_members.push(georender_pack::Member::new(
+        i as u64,
+        match role {
+          Role::Outer => georender_pack::MemberRole::Outer(),
+          Role::Inner => georender_pack::MemberRole::Inner(),
+        },
+        georender_pack::MemberType::Way()
+      ));
+      way_deps.insert(i as u64, line.iter().map(|(r,_)| *r).collect::<Vec<u64>>());
+      for (r,(lon,lat)) in line.iter() {
+        node_deps.insert(*r, (*lon as f32, *lat as f32));
+      }
+    }
+
+    if node_deps.len() <= 1 { return Err(Reject::new(RejectReason::MissingMembers)) }
+    let mut bbox = (f32::INFINITY,f32::INFINITY,f32::NEG_INFINITY,f32::NEG_INFINITY);
+    for p in node_deps.values() {
+      bbox.0 = bbox.0.min(p.0);
+      bbox.1 = bbox.1.min(p.1);
+      bbox.2 = bbox.2.max(p.0);
+      bbox.3 = bbox.3.max(p.1);
+    }
+    let r_encoded = georender_pack::encode::relation_from_parsed(
+      encoded_id, ft, true,
+      labels, &ring_members, &node_deps, &way_deps
+    );
+    match r_encoded {
+      Ok(encoded) => Ok(Some((
+        (eyros::Coord::Interval(bbox.0,bbox.2), eyros::Coord::Interval(bbox.1,bbox.3)),
+        encoded.into()
+      ))),
+      Err(e) => Err(Reject::from_error(RejectReason::Encode, e.into())),
+    }
+  }
+
+  // None when the line is entirely outside of the bbox or boundary. otherwise the
+  // parts of the line inside of them when clipping is on, which can be none.
+  fn extract_line(&self, line: Line, next_id: &mut u64) -> Option<Vec<Line>> {
+    let mut lines = vec![line];
+    if let Some(bbox) = &self.bbox {
+      if !lines.iter().any(|line| bbox.intersects_line(&coords(line))) { return None }
+      if self.clip {
+        lines = lines.iter().flat_map(|line| bbox.split_line(line, next_id)).collect();
+      }
+    }
+    if let Some(boundary) = &self.boundary {
+      if !lines.iter().any(|line| boundary.intersects_line(&coords(line))) { return None }
+      if self.clip {
+        lines = lines.iter().flat_map(|line| boundary.split_line(line, next_id)).collect();
+      }
+    }
+    Some(lines)
+  }
+
+  // None when the area is entirely outside of the bbox or boundary
+  // or when no outer ring is left after clipping
+  fn extract_area(
+    &self, mut rings: Vec<(Role,Line)>, next_id: &mut u64,
+  ) -> Option<Vec<(Role,Line)>> {
+    if let Some(bbox) = &self.bbox {
+      let ring_coords = rings.iter().map(|(_,line)| coords(line)).collect::<Vec<_>>();
+      if !bbox.intersects_area(&ring_coords) { return None }
+      if self.clip {
+        rings = rings.into_iter()
+          .map(|(role,line)| (role,bbox.clip_ring(&line, next_id)))
+          .filter(|(_,line)| line.len() >= 4)
+          .collect();
+      }
+    }
+    if let Some(boundary) = &self.boundary {
+      let ring_coords = rings.iter().map(|(_,line)| coords(line)).collect::<Vec<_>>();
+      if !boundary.intersects_area(&ring_coords) { return None }
+      if self.clip {
+        rings = clip_to_boundary(boundary, &rings, next_id);
+      }
+    }
+    if !rings.iter().any(|(role,_)| *role == Role::Outer) { return None }
+    Some(rings)
+  }
+}
+
+// a segment longer than half of the world goes the short way across the antimeridian
+fn crosses_antimeridian(line: &[(u64,(f64,f64))]) -> bool {
+  line.windows(2).any(|w| ((w[1].1).0 - (w[0].1).0).abs() > 180.0)
+}
+
+// the boxes on either side of the antimeridian for unwrapped longitudes
+// and the shift that brings them back into -180 to 180
+fn sides() -> [(BBox,f64);3] {
+  [
+    (BBox { west: -540.0, south: -90.0, east: -180.0, north: 90.0 }, 360.0),
+    (BBox { west: -180.0, south: -90.0, east: 180.0, north: 90.0 }, 0.0),
+    (BBox { west: 180.0, south: -90.0, east: 540.0, north: 90.0 }, -360.0),
+  ]
+}
+
+// add or remove 360 degrees so that no segment is longer than 180 degrees of longitude
+fn unwrap_lon(line: &[(u64,(f64,f64))]) -> Line {
+  let mut offset = 0.0;
+  let mut prev: Option<f64> = None;
+  line.iter().map(|(r,(lon,lat))| {
+    if let Some(p) = prev {
+      if lon - p > 180.0 { offset -= 360.0 }
+      if lon - p < -180.0 { offset += 360.0 }
+    }
+    prev = Some(*lon);
+    (*r,(lon + offset,*lat))
+  }).collect()
+}
+
+fn shift_lon(line: Line, shift: f64) -> Line {
+  if shift == 0.0 { return line }
+  line.into_iter().map(|(r,(lon,lat))| (r,(lon + shift,lat))).collect()
+}
+
+fn coords(line: &[(u64,(f64,f64))]) -> Vec<(f64,f64)> {
+  line.iter().map(|(_,p)| *p).collect()
+}
+
+// intersect rings with the boundary polygons. points that were already in the rings
+// keep their node ids and points on the boundary get new ids.
+fn clip_to_boundary(
+  boundary: &Boundary, rings: &[(Role,Line)], next_id: &mut u64,
+) -> Vec<(Role,Line)> {
+  use geo::{BooleanOps,Orient,orient::Direction};
+  let mut ids = std::collections::HashMap::new();
+  let mut polygons: Vec<geo::Polygon<f64>> = vec![];
+  for (role,line) in rings.iter() {
+    for (r,(lon,lat)) in line.iter() {
+      ids.insert((lon.to_bits(),lat.to_bits()), *r);
+    }
+    let ring = geo::LineString::from(coords(line));
+    match (role, polygons.last_mut()) {
+      (Role::Inner, Some(polygon)) => polygon.interiors_push(ring),
+      (Role::Inner, None) => {},
+      (Role::Outer, _) => polygons.push(geo::Polygon::new(ring, vec![])),
+    }
+  }
+  let clipped = geo::MultiPolygon(polygons)
+    .intersection(boundary.polygons())
+    .orient(Direction::Default);
+  let mut to_line = |ring: &geo::LineString<f64>| -> Line {
+    ring.0.iter().map(|c| {
+      let r = *ids.entry((c.x.to_bits(),c.y.to_bits())).or_insert_with(|| {
+        let r = *next_id;
+        *next_id -= 1;
+        r
+      });
+      (r,(c.x,c.y))
+    }).collect()
+  };
+  let mut out = vec![];
+  for polygon in clipped.0.iter() {
+    let exterior = to_line(polygon.exterior());
+    if exterior.len() < 4 { continue }
+    out.push((Role::Outer,exterior));
+    for interior in polygon.interiors().iter() {
+      let interior = to_line(interior);
+      if interior.len() >= 4 {
+        out.push((Role::Inner,interior));
+      }
+    }
+  }
+  out
+}
+
+// concatenate the member ways of a linear relation in order into lines,
+// reversing ways so that consecutive ways join end to start where they share a node.
+// a way that doesn't join the one before it starts a new line.
+fn concat_members(
+  members: &[(element::MemberType,i64,&str)], all_way_deps: &HashMap<i64,Vec<i64>>,
+) -> Vec<Vec<u64>> {
+  let mut lines = vec![];
+  let mut refs: Vec<i64> = vec![];
+  let mut first_len = 0;
+  for (t,way_id,role) in members.iter() {
+    if *t != element::MemberType::Way || !is_drawn_member(role) { continue }
+    let way_refs = match all_way_deps.get(way_id) {
+      Some(way_refs) if !way_refs.is_empty() => way_refs,
+      _ => continue,
+    };
+    if refs.is_empty() {
+      refs.extend_from_slice(way_refs);
+      first_len = refs.len();
+      continue;
+    }
+    // the first way of a line may point away from the second
+    if refs.len() == first_len
+    && refs.first() != refs.last()
+    && (way_refs.first() == refs.first() || way_refs.last() == refs.first()) {
+      refs.reverse();
+    }
+    let end = refs.last().copied();
+    if way_refs.first().copied() == end {
+      refs.extend_from_slice(&way_refs[1..]);
+    } else if way_refs.last().copied() == end {
+      refs.extend(way_refs.iter().rev().skip(1));
+    } else {
+      lines.push(refs.iter().map(|r| *r as u64).collect());
+      refs = way_refs.clone();
+      first_len = refs.len();
+    }
+  }
+  if !refs.is_empty() {
+    lines.push(refs.iter().map(|r| *r as u64).collect());
+  }
+  lines
+}
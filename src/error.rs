@@ -1 +1,63 @@
 pub type Error = Box<dyn std::error::Error+Send+Sync>;
+
+// failures from scan and ingest, with enough context to say where in the pbf
+// or in which element they happened
+#[derive(Debug)]
+pub enum IngestError {
+  // opening the pbf file or reading its metadata
+  Io { file: String, source: std::io::Error },
+  // reading or decoding the blob at this byte offset of the pbf
+  Blob { stage: &'static str, offset: u64, source: Error },
+  // parsing the tags of or encoding one osm element
+  Element { stage: &'static str, id: i64, source: Error },
+  // writing rows to or syncing the eyros db
+  Write { stage: &'static str, source: Error },
+  // reading or writing the ingest checkpoint file
+  Checkpoint { file: std::path::PathBuf, source: Error },
+  // opening or writing the flat nodes file
+  FlatNodes { file: std::path::PathBuf, source: Error },
+  // a worker hung up before it was done
+  Channel { stage: &'static str },
+}
+
+impl std::fmt::Display for IngestError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      IngestError::Io { file, source } => {
+        write![f, "error opening {}: {}", file, source]
+      },
+      IngestError::Blob { stage, offset, source } => {
+        write![f, "[{}] error reading blob at offset {}: {}", stage, offset, source]
+      },
+      IngestError::Element { stage, id, source } => {
+        write![f, "[{}] error processing id {}: {}", stage, id, source]
+      },
+      IngestError::Write { stage, source } => {
+        write![f, "[{}] error writing to db: {}", stage, source]
+      },
+      IngestError::Checkpoint { file, source } => {
+        write![f, "error with checkpoint file {}: {}", file.display(), source]
+      },
+      IngestError::FlatNodes { file, source } => {
+        write![f, "error with flat nodes file {}: {}", file.display(), source]
+      },
+      IngestError::Channel { stage } => {
+        write![f, "[{}] worker exited before finishing", stage]
+      },
+    }
+  }
+}
+
+impl std::error::Error for IngestError {
+  fn source(&self) -> Option<&(dyn std::error::Error+'static)> {
+    match self {
+      IngestError::Io { source, .. } => Some(source),
+      IngestError::Blob { source, .. } => Some(source.as_ref()),
+      IngestError::Element { source, .. } => Some(source.as_ref()),
+      IngestError::Write { source, .. } => Some(source.as_ref()),
+      IngestError::Checkpoint { source, .. } => Some(source.as_ref()),
+      IngestError::FlatNodes { source, .. } => Some(source.as_ref()),
+      IngestError::Channel { .. } => None,
+    }
+  }
+}
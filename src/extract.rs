@@ -0,0 +1,446 @@
+use crate::{Error,rings};
+
+// a lon/lat box to extract from the pbf
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub struct BBox {
+  pub west: f64,
+  pub south: f64,
+  pub east: f64,
+  pub north: f64,
+}
+
+impl std::str::FromStr for BBox {
+  type Err = Error;
+  // W,S,E,N
+  fn from_str(s: &str) -> Result<Self,Error> {
+    let xs = s.split(',')
+      .map(|x| x.trim().parse::<f64>())
+      .collect::<Result<Vec<f64>,_>>()
+      .map_err(|_| format!["invalid bbox {}. expected W,S,E,N", s])?;
+    if xs.len() != 4 {
+      return Err(format!["invalid bbox {}. expected W,S,E,N", s].into());
+    }
+    let bbox = BBox { west: xs[0], south: xs[1], east: xs[2], north: xs[3] };
+    if bbox.west > bbox.east || bbox.south > bbox.north {
+      return Err(format!["invalid bbox {}. west must be <= east and south <= north", s].into());
+    }
+    Ok(bbox)
+  }
+}
+
+impl std::fmt::Display for BBox {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write![f, "{},{},{},{}", self.west, self.south, self.east, self.north]
+  }
+}
+
+impl BBox {
+  pub fn contains(&self, (lon,lat): (f64,f64)) -> bool {
+    self.west <= lon && lon <= self.east && self.south <= lat && lat <= self.north
+  }
+
+  // whether a line through the points touches the box
+  pub fn intersects_line(&self, points: &[(f64,f64)]) -> bool {
+    points.iter().any(|p| self.contains(*p))
+      || points.windows(2).any(|w| self.clip_segment(w[0], w[1]).is_some())
+  }
+
+  // whether an area made of closed rings overlaps the box,
+  // including when the box is entirely inside of the area
+  pub fn intersects_area(&self, rings: &[Vec<(f64,f64)>]) -> bool {
+    if rings.iter().any(|ring| self.intersects_line(ring)) { return true }
+    let corner = (self.west, self.south);
+    rings.iter().filter(|ring| rings::contains(ring, corner)).count() % 2 == 1
+  }
+
+  // liang-barsky clip of the segment from a to b
+  fn clip_segment(
+    &self, a: (f64,f64), b: (f64,f64)
+  ) -> Option<((f64,f64),(f64,f64))> {
+    let (dx,dy) = (b.0 - a.0, b.1 - a.1);
+    let mut t0 = 0.0_f64;
+    let mut t1 = 1.0_f64;
+    let edges = [
+      (-dx, a.0 - self.west),
+      (dx, self.east - a.0),
+      (-dy, a.1 - self.south),
+      (dy, self.north - a.1),
+    ];
+    for (p,q) in edges.iter() {
+      if *p == 0.0 {
+        if *q < 0.0 { return None }
+      } else {
+        let t = q / p;
+        if *p < 0.0 {
+          if t > t1 { return None }
+          t0 = t0.max(t);
+        } else {
+          if t < t0 { return None }
+          t1 = t1.min(t);
+        }
+      }
+    }
+    Some((
+      (a.0 + t0 * dx, a.1 + t0 * dy),
+      (a.0 + t1 * dx, a.1 + t1 * dy),
+    ))
+  }
+
+  // the parts of a line that are inside of the box, split where the line leaves it.
+  // new points at the box edge get ids counting down from next_id.
+  pub fn split_line(
+    &self, points: &[(u64,(f64,f64))], next_id: &mut u64,
+  ) -> Vec<Vec<(u64,(f64,f64))>> {
+    let mut parts = vec![];
+    let mut part = vec![];
+    for w in points.windows(2) {
+      match self.clip_segment(w[0].1, w[1].1) {
+        Some((a,b)) => {
+          if part.is_empty() {
+            part.push(if a == w[0].1 { w[0] } else { new_point(next_id, a) });
+          }
+          if b == w[1].1 {
+            part.push(w[1]);
+          } else {
+            part.push(new_point(next_id, b));
+            parts.push(std::mem::take(&mut part));
+          }
+        },
+        None if !part.is_empty() => parts.push(std::mem::take(&mut part)),
+        None => {},
+      }
+    }
+    if !part.is_empty() { parts.push(part) }
+    parts.retain(|part| part.len() >= 2);
+    parts
+  }
+
+  // position along the edge of the box, counter-clockwise from the south-west corner
+  pub fn perimeter_position(&self, p: (f64,f64)) -> f64 {
+    let (w,h) = (self.east - self.west, self.north - self.south);
+    let d = [
+      (p.1 - self.south).abs(),
+      (p.0 - self.east).abs(),
+      (p.1 - self.north).abs(),
+      (p.0 - self.west).abs(),
+    ];
+    let edge = (0..4).fold(0, |e,i| if d[i] < d[e] { i } else { e });
+    match edge {
+      0 => p.0 - self.west,
+      1 => w + (p.1 - self.south),
+      2 => w + h + (self.east - p.0),
+      _ => 2.0*w + h + (self.north - p.1),
+    }
+  }
+
+  pub fn on_edge(&self, p: (f64,f64)) -> bool {
+    const EPSILON: f64 = 1e-9;
+    self.contains(p) && (
+      (p.0 - self.west).abs() < EPSILON || (p.0 - self.east).abs() < EPSILON
+      || (p.1 - self.south).abs() < EPSILON || (p.1 - self.north).abs() < EPSILON
+    )
+  }
+
+  // sutherland-hodgman clip of a closed ring. the result is closed again or empty.
+  pub fn clip_ring(
+    &self, ring: &[(u64,(f64,f64))], next_id: &mut u64,
+  ) -> Vec<(u64,(f64,f64))> {
+    let mut pts = ring.to_vec();
+    if pts.len() > 1 && pts.first().map(|p| p.1) == pts.last().map(|p| p.1) { pts.pop(); }
+    for edge in 0..4 {
+      if pts.is_empty() { break }
+      let inside = |p: (f64,f64)| match edge {
+        0 => p.0 >= self.west,
+        1 => p.0 <= self.east,
+        2 => p.1 >= self.south,
+        _ => p.1 <= self.north,
+      };
+      let cross = |a: (f64,f64), b: (f64,f64)| match edge {
+        0 => at_x(a, b, self.west),
+        1 => at_x(a, b, self.east),
+        2 => at_y(a, b, self.south),
+        _ => at_y(a, b, self.north),
+      };
+      let input = std::mem::take(&mut pts);
+      let mut prev = input[input.len()-1];
+      for cur in input.iter() {
+        match (inside(cur.1), inside(prev.1)) {
+          (true,true) => pts.push(*cur),
+          (true,false) => {
+            pts.push(new_point(next_id, cross(prev.1, cur.1)));
+            pts.push(*cur);
+          },
+          (false,true) => pts.push(new_point(next_id, cross(prev.1, cur.1))),
+          (false,false) => {},
+        }
+        prev = *cur;
+      }
+    }
+    if pts.len() < 3 { return vec![] }
+    pts.push(pts[0]);
+    pts
+  }
+}
+
+fn new_point(next_id: &mut u64, p: (f64,f64)) -> (u64,(f64,f64)) {
+  let id = *next_id;
+  *next_id -= 1;
+  (id,p)
+}
+
+fn at_x(a: (f64,f64), b: (f64,f64), x: f64) -> (f64,f64) {
+  (x, a.1 + (b.1 - a.1) * (x - a.0) / (b.0 - a.0))
+}
+
+fn at_y(a: (f64,f64), b: (f64,f64), y: f64) -> (f64,f64) {
+  (a.0 + (b.0 - a.0) * (y - a.1) / (b.1 - a.1), y)
+}
+
+// boundary edges are grouped into this many horizontal bands
+// so that point and crossing tests only look at nearby edges
+const BANDS: usize = 1024;
+
+type Edge = ((f64,f64),(f64,f64));
+
+// an area to extract from the pbf, read from an osmosis .poly file or
+// from a geojson Polygon, MultiPolygon, Feature or FeatureCollection
+#[derive(Debug,Clone)]
+pub struct Boundary {
+  polygons: geo::MultiPolygon<f64>,
+  bbox: BBox,
+  bands: Vec<Vec<Edge>>,
+}
+
+impl Boundary {
+  pub fn new(polygons: geo::MultiPolygon<f64>) -> Result<Self,Error> {
+    let mut edges = vec![];
+    for polygon in polygons.0.iter() {
+      for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors().iter()) {
+        for line in ring.lines() {
+          edges.push(((line.start.x,line.start.y),(line.end.x,line.end.y)));
+        }
+      }
+    }
+    if edges.is_empty() { return Err("boundary has no polygons".into()) }
+    let mut bbox = BBox {
+      west: f64::INFINITY, south: f64::INFINITY,
+      east: f64::NEG_INFINITY, north: f64::NEG_INFINITY,
+    };
+    for (a,b) in edges.iter() {
+      for p in [a,b].iter() {
+        bbox.west = bbox.west.min(p.0);
+        bbox.south = bbox.south.min(p.1);
+        bbox.east = bbox.east.max(p.0);
+        bbox.north = bbox.north.max(p.1);
+      }
+    }
+    let mut boundary = Self { polygons, bbox, bands: vec![vec![];BANDS] };
+    for (a,b) in edges {
+      let (i,j) = (boundary.band(a.1.min(b.1)), boundary.band(a.1.max(b.1)));
+      for band in boundary.bands[i..=j].iter_mut() {
+        band.push((a,b));
+      }
+    }
+    Ok(boundary)
+  }
+
+  pub fn from_file(file: &std::path::Path) -> Result<Self,Error> {
+    let src = std::fs::read_to_string(file)?;
+    if src.trim_start().starts_with('{') {
+      Self::from_geojson(&src)
+    } else {
+      Self::from_poly(&src)
+    }
+  }
+
+  // osmosis polygon filter format: a name line, then sections of "lon lat" lines
+  // that each end with END. sections with a name starting with ! are holes.
+  pub fn from_poly(src: &str) -> Result<Self,Error> {
+    let mut lines = src.lines().map(|line| line.trim()).filter(|line| !line.is_empty());
+    lines.next().ok_or("empty poly file")?;
+    let mut outers: Vec<(Vec<(f64,f64)>,Vec<Vec<(f64,f64)>>)> = vec![];
+    let mut holes = vec![];
+    while let Some(name) = lines.next() {
+      if name == "END" { break }
+      let mut ring = vec![];
+      loop {
+        let line = lines.next().ok_or("unexpected end of poly file")?;
+        if line == "END" { break }
+        let xs = line.split_whitespace()
+          .map(|x| x.parse::<f64>())
+          .collect::<Result<Vec<f64>,_>>()
+          .map_err(|_| format!["invalid coordinates in poly file: {}", line])?;
+        if xs.len() != 2 {
+          return Err(format!["invalid coordinates in poly file: {}", line].into());
+        }
+        ring.push((xs[0],xs[1]));
+      }
+      if ring.len() < 3 { continue }
+      if ring.first() != ring.last() { ring.push(ring[0]) }
+      if name.starts_with('!') {
+        holes.push(ring);
+      } else {
+        outers.push((ring,vec![]));
+      }
+    }
+    // holes apply to whichever section contains them
+    for hole in holes {
+      let o_outer = outers.iter_mut().find(|(outer,_)| rings::contains(outer, hole[0]));
+      if let Some((_,inners)) = o_outer {
+        inners.push(hole);
+      }
+    }
+    Self::new(geo::MultiPolygon(outers.into_iter().map(|(outer,inners)| {
+      geo::Polygon::new(outer.into(), inners.into_iter().map(|inner| inner.into()).collect())
+    }).collect()))
+  }
+
+  pub fn from_geojson(src: &str) -> Result<Self,Error> {
+    let value: serde_json::Value = serde_json::from_str(src)?;
+    let mut polygons = vec![];
+    geojson_polygons(&value, &mut polygons)?;
+    Self::new(geo::MultiPolygon(polygons))
+  }
+
+  pub fn polygons(&self) -> &geo::MultiPolygon<f64> {
+    &self.polygons
+  }
+
+  pub fn bbox(&self) -> &BBox {
+    &self.bbox
+  }
+
+  fn band(&self, y: f64) -> usize {
+    let h = self.bbox.north - self.bbox.south;
+    if h <= 0.0 { return 0 }
+    (((y - self.bbox.south) / h * (BANDS as f64)) as usize).min(BANDS-1)
+  }
+
+  // even-odd test against every ring, so points in holes are outside
+  pub fn contains(&self, p: (f64,f64)) -> bool {
+    if !self.bbox.contains(p) { return false }
+    let mut inside = false;
+    for (a,b) in self.bands[self.band(p.1)].iter() {
+      if (a.1 > p.1) != (b.1 > p.1) && p.0 < (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1) + a.0 {
+        inside = !inside;
+      }
+    }
+    inside
+  }
+
+  // positions along the segment from a to b (0 to 1) where it crosses the boundary
+  fn crossings(&self, a: (f64,f64), b: (f64,f64)) -> Vec<f64> {
+    let mut ts = vec![];
+    if a.1.max(b.1) < self.bbox.south || a.1.min(b.1) > self.bbox.north
+    || a.0.max(b.0) < self.bbox.west || a.0.min(b.0) > self.bbox.east {
+      return ts;
+    }
+    let (i,j) = (self.band(a.1.min(b.1)), self.band(a.1.max(b.1)));
+    let r = (b.0 - a.0, b.1 - a.1);
+    for band in self.bands[i..=j].iter() {
+      for (c,d) in band.iter() {
+        let s = (d.0 - c.0, d.1 - c.1);
+        let denom = r.0 * s.1 - r.1 * s.0;
+        if denom == 0.0 { continue }
+        let t = ((c.0 - a.0) * s.1 - (c.1 - a.1) * s.0) / denom;
+        let u = ((c.0 - a.0) * r.1 - (c.1 - a.1) * r.0) / denom;
+        if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&u) {
+          ts.push(t);
+        }
+      }
+    }
+    ts
+  }
+
+  pub fn intersects_line(&self, points: &[(f64,f64)]) -> bool {
+    points.iter().any(|p| self.contains(*p))
+      || points.windows(2).any(|w| !self.crossings(w[0], w[1]).is_empty())
+  }
+
+  // whether an area made of closed rings overlaps the boundary,
+  // including when the boundary is entirely inside of the area
+  pub fn intersects_area(&self, rings: &[Vec<(f64,f64)>]) -> bool {
+    if rings.iter().any(|ring| self.intersects_line(ring)) { return true }
+    let p = self.polygons.0.iter()
+      .filter_map(|polygon| polygon.exterior().0.first())
+      .map(|c| (c.x,c.y))
+      .next();
+    match p {
+      Some(p) => rings.iter().filter(|ring| rings::contains(ring, p)).count() % 2 == 1,
+      None => false,
+    }
+  }
+
+  // the parts of a line that are inside of the boundary, split where the line leaves it.
+  // new points at the boundary get ids counting down from next_id.
+  pub fn split_line(
+    &self, points: &[(u64,(f64,f64))], next_id: &mut u64,
+  ) -> Vec<Vec<(u64,(f64,f64))>> {
+    let mut parts = vec![];
+    let mut part = vec![];
+    for w in points.windows(2) {
+      let (a,b) = (w[0].1, w[1].1);
+      let at = |t: f64| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+      let mut ts = self.crossings(a, b);
+      ts.push(0.0);
+      ts.push(1.0);
+      ts.sort_by(|x,y| x.partial_cmp(y).unwrap_or(std::cmp::Ordering::Equal));
+      for t in ts.windows(2) {
+        if t[1] - t[0] <= 0.0 { continue }
+        if self.contains(at((t[0] + t[1]) * 0.5)) {
+          if part.is_empty() {
+            part.push(if t[0] == 0.0 { w[0] } else { new_point(next_id, at(t[0])) });
+          }
+          part.push(if t[1] == 1.0 { w[1] } else { new_point(next_id, at(t[1])) });
+        } else if !part.is_empty() {
+          parts.push(std::mem::take(&mut part));
+        }
+      }
+    }
+    if !part.is_empty() { parts.push(part) }
+    parts.retain(|part| part.len() >= 2);
+    parts
+  }
+}
+
+fn geojson_polygons(value: &serde_json::Value, out: &mut Vec<geo::Polygon<f64>>) -> Result<(),Error> {
+  match value.get("type").and_then(|t| t.as_str()) {
+    Some("FeatureCollection") => {
+      for feature in value["features"].as_array().ok_or("geojson features must be an array")? {
+        geojson_polygons(feature, out)?;
+      }
+    },
+    Some("Feature") => geojson_polygons(&value["geometry"], out)?,
+    Some("GeometryCollection") => {
+      for geometry in value["geometries"].as_array().ok_or("geojson geometries must be an array")? {
+        geojson_polygons(geometry, out)?;
+      }
+    },
+    Some("Polygon") => out.push(geojson_polygon(&value["coordinates"])?),
+    Some("MultiPolygon") => {
+      for polygon in value["coordinates"].as_array().ok_or("invalid MultiPolygon coordinates")? {
+        out.push(geojson_polygon(polygon)?);
+      }
+    },
+    Some(t) => return Err(format!["unsupported geojson type {} for a boundary", t].into()),
+    None => return Err("geojson object without a type".into()),
+  }
+  Ok(())
+}
+
+fn geojson_polygon(coordinates: &serde_json::Value) -> Result<geo::Polygon<f64>,Error> {
+  let mut rings = vec![];
+  for ring in coordinates.as_array().ok_or("invalid Polygon coordinates")? {
+    let mut points: Vec<(f64,f64)> = vec![];
+    for position in ring.as_array().ok_or("invalid Polygon ring")? {
+      match (position.get(0).and_then(|x| x.as_f64()), position.get(1).and_then(|y| y.as_f64())) {
+        (Some(x),Some(y)) => points.push((x,y)),
+        _ => return Err("invalid Polygon position".into()),
+      }
+    }
+    rings.push(geo::LineString::from(points));
+  }
+  if rings.is_empty() { return Err("Polygon without rings".into()) }
+  let exterior = rings.remove(0);
+  Ok(geo::Polygon::new(exterior, rings))
+}
@@ -0,0 +1,121 @@
+use crate::{Error,Filter};
+
+// how rules combine with georender-pack's own classification
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Mode {
+  // rules are tried first and georender-pack classifies everything else
+  Extend,
+  // elements that no rule matches are left out
+  Replace,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub struct Rule {
+  pub filter: Filter,
+  pub feature_type: u64,
+  pub priority: i64,
+}
+
+// a user mapping of tag rules to feature types, read from a json file:
+//
+// {
+//   "mode": "extend",
+//   "rules": [
+//     { "tags": "amenity=charging_station", "type": "amenity.fuel", "priority": 10 },
+//     { "tags": "shop=* and craft=*", "type": 400 }
+//   ]
+// }
+//
+// "tags" is a filter expression (see Filter). "type" is a feature type name from
+// georender-pack's table or the numeric id of one.
+// rules with a higher priority are tried first, then in file order.
+#[derive(Debug,Clone,PartialEq)]
+pub struct FeatureTypes {
+  pub mode: Mode,
+  pub rules: Vec<Rule>,
+}
+
+impl Default for FeatureTypes {
+  fn default() -> Self {
+    Self { mode: Mode::Extend, rules: vec![] }
+  }
+}
+
+// a feature type name from georender-pack's table or the numeric id of one
+pub fn parse_type(t: &str) -> Result<u64,Error> {
+  let types = georender_pack::osm_types::get_types();
+  match t.parse::<u64>() {
+    Ok(id) if types.values().any(|v| *v == id) => Ok(id),
+    Ok(id) => Err(format!["unknown feature type id {}", id].into()),
+    Err(_) => types.get(t).copied().ok_or_else(|| format!["unknown feature type {}", t].into()),
+  }
+}
+
+impl FeatureTypes {
+  pub fn from_file(file: &std::path::Path) -> Result<Self,Error> {
+    let src = std::fs::read_to_string(file)
+      .map_err(|e| format!["could not read feature types file {}: {}", file.display(), e])?;
+    Self::from_json(&src)
+      .map_err(|e| format!["invalid feature types file {}: {}", file.display(), e].into())
+  }
+
+  pub fn from_json(src: &str) -> Result<Self,Error> {
+    let value: serde_json::Value = serde_json::from_str(src)?;
+    let mode = match value.get("mode").and_then(|m| m.as_str()) {
+      None | Some("extend") => Mode::Extend,
+      Some("replace") => Mode::Replace,
+      Some(m) => return Err(format!["unknown mode {}. expected extend or replace", m].into()),
+    };
+    let types = georender_pack::osm_types::get_types();
+    let ids = types.values().copied().collect::<std::collections::HashSet<u64>>();
+    let mut rules = vec![];
+    let mut unknown = vec![];
+    let rule_values = value.get("rules").and_then(|r| r.as_array())
+      .ok_or("expected a rules array")?;
+    for (i,rule) in rule_values.iter().enumerate() {
+      let filter = rule.get("tags").and_then(|t| t.as_str())
+        .ok_or_else(|| format!["rule {} has no tags expression", i])?
+        .parse::<Filter>()
+        .map_err(|e| format!["rule {}: {}", i, e])?;
+      let feature_type = match rule.get("type") {
+        Some(serde_json::Value::String(name)) => match types.get(name.as_str()) {
+          Some(t) => *t,
+          None => {
+            unknown.push(name.clone());
+            continue;
+          },
+        },
+        Some(serde_json::Value::Number(n)) if n.is_u64() => match n.as_u64().unwrap() {
+          // renderers decode ids with the same table, so others couldn't be drawn
+          t if ids.contains(&t) => t,
+          t => {
+            unknown.push(t.to_string());
+            continue;
+          },
+        },
+        _ => return Err(format!["rule {} needs a type name or id", i].into()),
+      };
+      let priority = match rule.get("priority") {
+        None => 0,
+        Some(p) => p.as_i64().ok_or_else(|| format!["rule {} has an invalid priority", i])?,
+      };
+      rules.push(Rule { filter, feature_type, priority });
+    }
+    if !unknown.is_empty() {
+      return Err(format!["unknown feature types: {}", unknown.join(", ")].into());
+    }
+    // sort is stable so equal priorities keep their file order
+    rules.sort_by(|a,b| b.priority.cmp(&a.priority));
+    Ok(Self { mode, rules })
+  }
+
+  // the feature type from the first matching rule. otherwise the type from
+  // georender-pack, or place_other to leave the element out in replace mode.
+  pub fn classify(&self, tags: &[(&str,&str)], default: u64, place_other: u64) -> u64 {
+    match self.rules.iter().find(|rule| rule.filter.matches(tags)) {
+      Some(rule) => rule.feature_type,
+      None if self.mode == Mode::Replace => place_other,
+      None => default,
+    }
+  }
+}
@@ -0,0 +1,234 @@
+use crate::Error;
+
+// a tag filter expression such as `highway=* and not access=private`.
+//
+// - `key` or `key=*` matches elements that have the key
+// - `key=value` and `key!=value` compare the value. values can list alternatives
+//   like `highway=primary|secondary`
+// - keys and values can use * as a wildcard, like `disused:*`
+// - terms combine with `and`, `or`, `not` and parentheses
+// - words with spaces or symbols can be quoted: `name="main street"`
+#[derive(Debug,Clone,PartialEq)]
+pub struct Filter {
+  source: String,
+  expr: Expr,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Expr {
+  Has(Pattern),
+  Eq(Pattern,Vec<Pattern>),
+  Ne(Pattern,Vec<Pattern>),
+  Not(Box<Expr>),
+  And(Box<Expr>,Box<Expr>),
+  Or(Box<Expr>,Box<Expr>),
+}
+
+// a glob split on *. `a*b` is ["a","b"]
+#[derive(Debug,Clone,PartialEq)]
+struct Pattern(Vec<String>);
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token {
+  Word(String),
+  Quoted(String),
+  Open,
+  Close,
+  Eq,
+  Ne,
+}
+
+impl Filter {
+  pub fn matches(&self, tags: &[(&str,&str)]) -> bool {
+    self.expr.matches(tags)
+  }
+}
+
+impl std::str::FromStr for Filter {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    let tokens = tokenize(s)?;
+    let mut parser = Parser { tokens: &tokens, i: 0 };
+    let expr = parser.or()?;
+    if let Some(token) = parser.peek() {
+      return Err(format!["unexpected {:?} in filter {:?}", token, s].into());
+    }
+    Ok(Self { source: s.to_string(), expr })
+  }
+}
+
+impl std::fmt::Display for Filter {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write![f, "{}", self.source]
+  }
+}
+
+impl Expr {
+  fn matches(&self, tags: &[(&str,&str)]) -> bool {
+    match self {
+      Expr::Has(k) => tags.iter().any(|(key,_)| k.matches(key)),
+      Expr::Eq(k,vs) => tags.iter()
+        .any(|(key,value)| k.matches(key) && vs.iter().any(|v| v.matches(value))),
+      // an element without the key is also not equal
+      Expr::Ne(k,vs) => !tags.iter()
+        .any(|(key,value)| k.matches(key) && vs.iter().any(|v| v.matches(value))),
+      Expr::Not(x) => !x.matches(tags),
+      Expr::And(a,b) => a.matches(tags) && b.matches(tags),
+      Expr::Or(a,b) => a.matches(tags) || b.matches(tags),
+    }
+  }
+}
+
+impl Pattern {
+  fn new(s: &str, glob: bool) -> Self {
+    if glob {
+      Pattern(s.split('*').map(|x| x.to_string()).collect())
+    } else {
+      Pattern(vec![s.to_string()])
+    }
+  }
+
+  fn matches(&self, s: &str) -> bool {
+    let parts = &self.0;
+    if parts.len() == 1 { return parts[0] == s }
+    let (first,last) = (&parts[0], &parts[parts.len()-1]);
+    if s.len() < first.len() + last.len() || !s.starts_with(first.as_str())
+    || !s.ends_with(last.as_str()) {
+      return false;
+    }
+    let mut rest = &s[first.len()..s.len()-last.len()];
+    for part in parts[1..parts.len()-1].iter() {
+      match rest.find(part.as_str()) {
+        Some(i) => rest = &rest[i+part.len()..],
+        None => return false,
+      }
+    }
+    true
+  }
+
+  fn is_any(&self) -> bool {
+    self.0.len() == 2 && self.0.iter().all(|x| x.is_empty())
+  }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>,Error> {
+  let mut tokens = vec![];
+  let mut chars = s.chars().peekable();
+  while let Some(c) = chars.next() {
+    match c {
+      ' ' | '\t' | '\n' | '\r' => {},
+      '(' => tokens.push(Token::Open),
+      ')' => tokens.push(Token::Close),
+      '=' => tokens.push(Token::Eq),
+      '!' if chars.peek() == Some(&'=') => {
+        chars.next();
+        tokens.push(Token::Ne);
+      },
+      '"' => {
+        let mut word = String::new();
+        loop {
+          match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+              Some(c) => word.push(c),
+              None => return Err(format!["unterminated string in filter {:?}", s].into()),
+            },
+            Some(c) => word.push(c),
+            None => return Err(format!["unterminated string in filter {:?}", s].into()),
+          }
+        }
+        tokens.push(Token::Quoted(word));
+      },
+      c => {
+        let mut word = c.to_string();
+        while let Some(c) = chars.peek() {
+          if c.is_whitespace() || ['(',')','=','!','"'].contains(c) { break }
+          word.push(*c);
+          chars.next();
+        }
+        tokens.push(Token::Word(word));
+      },
+    }
+  }
+  Ok(tokens)
+}
+
+struct Parser<'a> {
+  tokens: &'a [Token],
+  i: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<&'a Token> {
+    self.tokens.get(self.i)
+  }
+
+  fn next(&mut self) -> Option<&'a Token> {
+    let token = self.tokens.get(self.i);
+    self.i += 1;
+    token
+  }
+
+  fn is_keyword(&self, keyword: &str) -> bool {
+    matches![self.peek(), Some(Token::Word(w)) if w == keyword]
+  }
+
+  fn or(&mut self) -> Result<Expr,Error> {
+    let mut expr = self.and()?;
+    while self.is_keyword("or") {
+      self.next();
+      expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+    }
+    Ok(expr)
+  }
+
+  fn and(&mut self) -> Result<Expr,Error> {
+    let mut expr = self.not()?;
+    while self.is_keyword("and") {
+      self.next();
+      expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+    }
+    Ok(expr)
+  }
+
+  fn not(&mut self) -> Result<Expr,Error> {
+    if self.is_keyword("not") {
+      self.next();
+      return Ok(Expr::Not(Box::new(self.not()?)));
+    }
+    self.term()
+  }
+
+  fn term(&mut self) -> Result<Expr,Error> {
+    let key = match self.next() {
+      Some(Token::Open) => {
+        let expr = self.or()?;
+        return match self.next() {
+          Some(Token::Close) => Ok(expr),
+          _ => Err("missing ) in filter".into()),
+        };
+      },
+      Some(Token::Word(w)) => Pattern::new(w, true),
+      Some(Token::Quoted(w)) => Pattern::new(w, false),
+      Some(token) => return Err(format!["unexpected {:?} in filter", token].into()),
+      None => return Err("unexpected end of filter".into()),
+    };
+    let is_eq = match self.peek() {
+      Some(Token::Eq) => true,
+      Some(Token::Ne) => false,
+      _ => return Ok(Expr::Has(key)),
+    };
+    self.next();
+    let values = match self.next() {
+      Some(Token::Word(w)) => w.split('|').map(|v| Pattern::new(v, true)).collect::<Vec<_>>(),
+      Some(Token::Quoted(w)) => vec![Pattern::new(w, false)],
+      _ => return Err("expected a value after = or != in filter".into()),
+    };
+    Ok(match (is_eq, values.iter().any(|v| v.is_any())) {
+      (true,true) => Expr::Has(key),
+      (false,true) => Expr::Not(Box::new(Expr::Has(key))),
+      (true,false) => Expr::Eq(key,values),
+      (false,false) => Expr::Ne(key,values),
+    })
+  }
+}
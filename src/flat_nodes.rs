@@ -0,0 +1,105 @@
+use crate::Error;
+use memmap2::MmapMut;
+
+// each node takes 8 bytes at id*8: lon and lat as little endian fixed point 1e-7 degrees.
+// lat is stored with an offset so that the zeros of a sparse file read back as missing.
+const RECORD_SIZE: u64 = 8;
+const LAT_OFFSET: i64 = 1_000_000_000;
+const SCALE: f64 = 1e7;
+// the file grows in steps of this many bytes so that it isn't remapped for every node
+const GROW_SIZE: u64 = 64 * 1024 * 1024;
+
+// node locations indexed by id in a memory-mapped file, like the flat nodes of osm2pgsql.
+// the file is written during the node pass and read when denormalizing ways and relations.
+pub struct FlatNodes {
+  file: std::fs::File,
+  mmap: Option<MmapMut>,
+  size: u64,
+}
+
+impl FlatNodes {
+  // open the file, keeping the locations that are already there
+  pub fn open(path: &std::path::Path) -> Result<Self,Error> {
+    let file = std::fs::OpenOptions::new()
+      .read(true).write(true).create(true)
+      .open(path)?;
+    Self::from_file(file)
+  }
+
+  // open the file and remove any locations from a previous ingest
+  pub fn create(path: &std::path::Path) -> Result<Self,Error> {
+    let file = std::fs::OpenOptions::new()
+      .read(true).write(true).create(true).truncate(true)
+      .open(path)?;
+    Self::from_file(file)
+  }
+
+  fn from_file(file: std::fs::File) -> Result<Self,Error> {
+    let size = file.metadata()?.len();
+    let mmap = if size > 0 { Some(unsafe { MmapMut::map_mut(&file)? }) } else { None };
+    Ok(Self { file, mmap, size })
+  }
+
+  pub fn get(&self, id: i64) -> Option<(f64,f64)> {
+    if id < 0 { return None }
+    let i = (id as u64) * RECORD_SIZE;
+    if i + RECORD_SIZE > self.size { return None }
+    let buf = &self.mmap.as_ref()?[i as usize..(i + RECORD_SIZE) as usize];
+    let lon = i32::from_le_bytes([buf[0],buf[1],buf[2],buf[3]]);
+    let lat = u32::from_le_bytes([buf[4],buf[5],buf[6],buf[7]]);
+    if lat == 0 { return None }
+    Some((
+      (lon as f64) / SCALE,
+      ((lat as i64) - LAT_OFFSET) as f64 / SCALE,
+    ))
+  }
+
+  pub fn set(&mut self, id: i64, (lon,lat): (f64,f64)) -> Result<(),Error> {
+    if id < 0 { return Err(format!["negative node id {} in flat nodes", id].into()) }
+    let i = (id as u64) * RECORD_SIZE;
+    self.reserve(i + RECORD_SIZE)?;
+    let lon = (lon * SCALE).round() as i32;
+    let lat = (((lat * SCALE).round() as i64) + LAT_OFFSET) as u32;
+    let buf = &mut self.mmap.as_mut().unwrap()[i as usize..(i + RECORD_SIZE) as usize];
+    buf[0..4].copy_from_slice(&lon.to_le_bytes());
+    buf[4..8].copy_from_slice(&lat.to_le_bytes());
+    Ok(())
+  }
+
+  pub fn remove(&mut self, id: i64) {
+    if id < 0 { return }
+    let i = (id as u64) * RECORD_SIZE;
+    if i + RECORD_SIZE > self.size { return }
+    if let Some(mmap) = self.mmap.as_mut() {
+      mmap[i as usize..(i + RECORD_SIZE) as usize].fill(0);
+    }
+  }
+
+  // the locations of every id that is in the file
+  pub fn get_many<'a>(
+    &self, ids: impl Iterator<Item=&'a i64>,
+  ) -> hashbrown::HashMap<i64,(f64,f64)> {
+    ids.filter_map(|id| self.get(*id).map(|p| (*id,p))).collect()
+  }
+
+  // write changes to disk. called before a checkpoint so a resumed ingest has them.
+  pub fn flush(&self) -> Result<(),Error> {
+    if let Some(mmap) = self.mmap.as_ref() {
+      mmap.flush()?;
+    }
+    Ok(())
+  }
+
+  fn reserve(&mut self, size: u64) -> Result<(),Error> {
+    if size <= self.size { return Ok(()) }
+    let new_size = ((size + GROW_SIZE - 1) / GROW_SIZE) * GROW_SIZE;
+    if let Some(mmap) = self.mmap.take() {
+      mmap.flush()?;
+    }
+    // the new space is a sparse hole of zeros until nodes are written there
+    self.file.set_len(new_size)?;
+    self.size = new_size;
+    self.mmap = Some(unsafe { MmapMut::map_mut(&self.file)? });
+    Ok(())
+  }
+}
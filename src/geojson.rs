@@ -0,0 +1,216 @@
+use crate::{Error,DB,BBox,encode::element_id};
+use georender_pack::Feature;
+use serde_json::{json,Value};
+use std::collections::HashMap;
+use async_std::prelude::*;
+
+// a FeatureCollection, or one Feature per line
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Format {
+  GeoJson,
+  NdJson,
+}
+
+impl Default for Format {
+  fn default() -> Self { Format::GeoJson }
+}
+
+impl std::str::FromStr for Format {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    match s {
+      "geojson" => Ok(Format::GeoJson),
+      "ndjson" | "geojsonl" => Ok(Format::NdJson),
+      x => Err(format!["unknown format {}. expected geojson or ndjson", x].into()),
+    }
+  }
+}
+
+// write the features of the db that touch the bbox. returns how many were written.
+pub async fn write<S: eyros::RA, W: std::io::Write>(
+  db: &mut DB<S>, bbox: &BBox, format: Format, out: &mut W,
+) -> Result<u64,Error> {
+  let names = crate::stats::type_names();
+  let q = ((bbox.west as f32, bbox.south as f32), (bbox.east as f32, bbox.north as f32));
+  let mut stream = db.query(&q).await?;
+  if format == Format::GeoJson {
+    write![out, r#"{{"type":"FeatureCollection","features":["#]?;
+  }
+  let mut count = 0;
+  let r = async {
+    while let Some(result) = stream.next().await {
+      let (_,v) = result?;
+      let feature = georender_pack::decode(&v.data)?;
+      match (format,count) {
+        (Format::GeoJson,0) => writeln![out]?,
+        (Format::GeoJson,_) => writeln![out, ","]?,
+        _ => {},
+      }
+      write![out, "{}", to_feature(&feature, &names)]?;
+      if format == Format::NdJson { writeln![out]? }
+      count += 1;
+    }
+    Ok::<(),Error>(())
+  }.await;
+  // the collection is closed on an error too, so what was written is still valid
+  if format == Format::GeoJson {
+    writeln![out, "\n]}}"]?;
+  }
+  out.flush()?;
+  r.map(|_| count)
+}
+
+// a geojson Feature with the osm type and id, feature type name and labels
+pub fn to_feature(feature: &Feature, names: &HashMap<u64,String>) -> Value {
+  let (id,feature_type,labels,geometry) = match feature {
+    Feature::Point(x) => (x.id, x.feature_type, &x.labels, json!({
+      "type": "Point",
+      "coordinates": [x.point.0, x.point.1],
+    })),
+    Feature::Line(x) => (x.id, x.feature_type, &x.labels, json!({
+      "type": "LineString",
+      "coordinates": pairs(&x.positions),
+    })),
+    Feature::Area(x) => (x.id, x.feature_type, &x.labels, json!({
+      "type": "MultiPolygon",
+      "coordinates": polygons(&x.positions, &x.cells),
+    })),
+  };
+  let (element_type,osm_id) = element_id(id);
+  json!({
+    "type": "Feature",
+    "id": format!["{}/{}", element_type, osm_id],
+    "properties": {
+      "osm_type": element_type,
+      "osm_id": osm_id,
+      "feature_type": names.get(&feature_type).cloned()
+        .unwrap_or_else(|| feature_type.to_string()),
+      "labels": decode_labels(labels),
+    },
+    "geometry": geometry,
+  })
+}
+
+// georender-pack labels are length prefixed strings like "=name" or "en=name",
+// ending with an empty one. they become name and name:en properties.
+pub fn decode_labels(labels: &[u8]) -> serde_json::Map<String,Value> {
+  let mut props = serde_json::Map::new();
+  let mut offset = 0;
+  while offset < labels.len() {
+    let (s,len) = match desert::varint::decode(&labels[offset..]) {
+      Ok(x) => x,
+      Err(_) => break,
+    };
+    offset += s;
+    let len = len as usize;
+    if len == 0 || offset + len > labels.len() { break }
+    let label = String::from_utf8_lossy(&labels[offset..offset+len]);
+    offset += len;
+    if let Some((lang,name)) = label.split_once('=') {
+      let key = if lang.is_empty() { "name".to_string() } else { format!["name:{}", lang] };
+      props.insert(key, Value::from(name));
+    }
+  }
+  props
+}
+
+fn pairs(positions: &[f32]) -> Vec<[f32;2]> {
+  positions.chunks_exact(2).map(|p| [p[0],p[1]]).collect()
+}
+
+type Ring = Vec<[f32;2]>;
+
+// rebuild the outlines of an area from its triangles. edges that belong to only one
+// triangle are on the outline, and are chained into closed rings. rings inside of an
+// odd number of other rings are holes of the ring that directly contains them.
+// outer rings are counter-clockwise and holes clockwise, as rfc 7946 asks.
+fn polygons(positions: &[f32], cells: &[usize]) -> Vec<Vec<Ring>> {
+  let key = |i: usize| (positions[i*2].to_bits(), positions[i*2+1].to_bits());
+  let point = |i: usize| [positions[i*2], positions[i*2+1]];
+  let mut edges: HashMap<((u32,u32),(u32,u32)),(usize,usize,usize)> = HashMap::new();
+  for cell in cells.chunks_exact(3) {
+    for (a,b) in [(cell[0],cell[1]),(cell[1],cell[2]),(cell[2],cell[0])].iter() {
+      if key(*a) == key(*b) { continue }
+      let (ka,kb) = (key(*a),key(*b));
+      let k = if ka < kb { (ka,kb) } else { (kb,ka) };
+      edges.entry(k).or_insert((*a,*b,0)).2 += 1;
+    }
+  }
+  let mut next: HashMap<(u32,u32),Vec<usize>> = HashMap::new();
+  for (a,b,n) in edges.values() {
+    if *n != 1 { continue }
+    next.entry(key(*a)).or_insert_with(Vec::new).push(*b);
+  }
+  let mut starts = edges.values()
+    .filter(|(_,_,n)| *n == 1)
+    .map(|(a,_,_)| *a)
+    .collect::<Vec<_>>();
+  starts.sort_unstable();
+  let mut rings: Vec<Ring> = vec![];
+  for start in starts {
+    // a vertex where the outline touches itself starts more than one ring
+    while let Some(mut i) = next.get_mut(&key(start)).and_then(|js| js.pop()) {
+      let mut ring = vec![point(start), point(i)];
+      while key(i) != key(start) {
+        match next.get_mut(&key(i)).and_then(|js| js.pop()) {
+          Some(j) => {
+            ring.push(point(j));
+            i = j;
+          },
+          None => break,
+        }
+      }
+      if ring.len() >= 4 && key(i) == key(start) {
+        rings.push(ring);
+      }
+    }
+  }
+  let depths = (0..rings.len())
+    .map(|i| (0..rings.len()).filter(|j| *j != i && contains(&rings[*j], rings[i][0])).count())
+    .collect::<Vec<usize>>();
+  let mut out: Vec<Vec<Ring>> = vec![];
+  let mut outer_index = HashMap::new();
+  for (i,ring) in rings.iter().enumerate() {
+    if depths[i] % 2 == 0 {
+      outer_index.insert(i, out.len());
+      out.push(vec![oriented(ring, true)]);
+    }
+  }
+  for (i,ring) in rings.iter().enumerate() {
+    if depths[i] % 2 == 0 { continue }
+    let parent = (0..rings.len())
+      .find(|j| depths[*j] + 1 == depths[i] && contains(&rings[*j], ring[0]))
+      .and_then(|j| outer_index.get(&j));
+    match parent {
+      Some(k) => out[*k].push(oriented(ring, false)),
+      None => out.push(vec![oriented(ring, true)]),
+    }
+  }
+  out
+}
+
+// a copy of the ring that winds counter-clockwise when ccw is set and clockwise if not
+fn oriented(ring: &[[f32;2]], ccw: bool) -> Ring {
+  let mut ring = ring.to_vec();
+  if (signed_area(&ring) > 0.0) != ccw { ring.reverse() }
+  ring
+}
+
+// positive for counter-clockwise rings
+fn signed_area(ring: &[[f32;2]]) -> f64 {
+  ring.windows(2)
+    .map(|w| (w[0][0] as f64) * (w[1][1] as f64) - (w[1][0] as f64) * (w[0][1] as f64))
+    .sum::<f64>() / 2.0
+}
+
+// even-odd ray casting
+fn contains(ring: &[[f32;2]], p: [f32;2]) -> bool {
+  let mut inside = false;
+  for w in ring.windows(2) {
+    let ([x0,y0],[x1,y1]) = (w[0],w[1]);
+    if (y0 > p[1]) != (y1 > p[1]) && p[0] < (x1 - x0) * (p[1] - y0) / (y1 - y0) + x0 {
+      inside = !inside;
+    }
+  }
+  inside
+}
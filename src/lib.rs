@@ -7,11 +7,45 @@ mod progress;
 mod divide;
 use divide::divide;
 pub mod denorm;
-pub use progress::Progress;
+mod encode;
+pub use encode::{element_id,GENERATED_ID};
+mod rings;
+pub mod extract;
+pub use extract::{BBox,Boundary};
+pub mod changeset;
+pub use changeset::Changeset;
+pub mod checkpoint;
+pub use checkpoint::{Checkpoint,StageState};
+pub mod reject;
+pub use reject::{ErrorPolicy,Reject,RejectReason,Rejects};
+pub mod filter;
+pub use filter::Filter;
+pub mod feature_types;
+pub use feature_types::FeatureTypes;
+pub mod coastline;
+pub use coastline::Coastline;
+pub mod flat_nodes;
+pub use flat_nodes::FlatNodes;
+pub mod budget;
+pub use budget::{MemoryBudget,BatchSizes};
+pub mod dedup;
+pub use dedup::Duplicates;
+pub mod source;
+pub use source::{Source,SourceReader,FileSource,BufferSource,FnSource,SpoolSource};
+pub mod verify;
+pub use verify::Report;
+pub mod stats;
+pub use stats::Stats;
+pub mod geojson;
+pub mod sink;
+pub use sink::{Sink,VecSink};
+pub mod storage;
+pub use storage::{MemoryStorage,MemoryFile,MemDB};
+pub use progress::{Progress,Info};
 use osmpbf_parser::{Parser,Scan,ScanTable,element};
 mod par_scan;
 use par_scan::parallel_scan;
-use eyros::{Point,Value,Tree};
+use eyros::{Point,Value,Tree,RA};
 use std::collections::HashMap;
 
 pub const BACKREF_PREFIX: u8 = 1;
@@ -24,21 +58,54 @@ use futures::future::join_all;
 type T = eyros::Tree2<f32,f32,V>;
 type P = (eyros::Coord<f32>,eyros::Coord<f32>);
 type V = value::V;
-pub type EDB = eyros::DB<random_access_disk::RandomAccessDisk,T,P,V>;
+// a db of packed features on any random access storage
+pub type DB<S> = eyros::DB<S,T,P,V>;
+pub type EDB = DB<random_access_disk::RandomAccessDisk>;
+// the box and the georender-packed payload of one feature, as written to a sink
+pub type Row = (P,V);
 
 pub struct Ingest {
   place_other: u64,
+  feature_types: std::sync::Arc<FeatureTypes>,
   pub progress: Arc<RwLock<Progress>>,
 }
 
+#[derive(Clone)]
 pub struct IngestOptions {
   pub channel_size: usize,
   pub way_batch_size: usize,
   pub relation_batch_size: usize,
+  // how many levels of relations inside of relations to resolve
+  pub relation_depth: usize,
+  // replaces the channel size and the way and relation batch sizes with sizes
+  // estimated from the scan table to fit in this much memory
+  pub memory_budget: Option<MemoryBudget>,
+  // only keep elements that touch this box
+  pub bbox: Option<BBox>,
+  // only keep elements that touch these polygons
+  pub boundary: Option<Boundary>,
+  // cut lines and areas at the edge of the bbox and boundary
+  pub clip: bool,
+  // only encode elements whose tags match these filters
+  pub node_filter: Option<Filter>,
+  pub way_filter: Option<Filter>,
+  pub relation_filter: Option<Filter>,
+  // assemble natural=coastline ways into water or land areas
+  pub coastline: Option<Coastline>,
+  // store node locations in this file during the node stage and read them back
+  // for ways and relations instead of decoding node blobs again
+  pub flat_nodes_file: Option<std::path::PathBuf>,
   pub ingest_node: bool,
   pub ingest_way: bool,
   pub ingest_relation: bool,
   pub optimize: Option<(usize,usize)>,
+  // save a checkpoint here after each batch. rows are only flushed to the sink when a
+  // checkpoint is saved, so that a resumed ingest never writes a row twice.
+  pub checkpoint_file: Option<std::path::PathBuf>,
+  // continue from the checkpoint in checkpoint_file
+  pub resume: bool,
+  pub error_policy: ErrorPolicy,
+  pub rejects_file: Option<std::path::PathBuf>,
 }
 
 impl Default for IngestOptions {
@@ -47,211 +114,549 @@ impl Default for IngestOptions {
       channel_size: 500,
       way_batch_size: 10_000_000,
       relation_batch_size: 1_000_000,
+      relation_depth: 8,
+      memory_budget: None,
+      bbox: None,
+      boundary: None,
+      clip: false,
+      node_filter: None,
+      way_filter: None,
+      relation_filter: None,
+      coastline: None,
+      flat_nodes_file: None,
       ingest_node: true,
       ingest_way: true,
       ingest_relation: true,
       optimize: None,
+      checkpoint_file: None,
+      resume: false,
+      error_policy: ErrorPolicy::default(),
+      rejects_file: None,
     }
   }
 }
 
+impl IngestOptions {
+  // a rejects file implies that skipped elements should be logged
+  pub fn get_error_policy(&self) -> ErrorPolicy {
+    match (self.error_policy, &self.rejects_file) {
+      (ErrorPolicy::Skip, Some(_)) => ErrorPolicy::SkipAndLog,
+      (policy, _) => policy,
+    }
+  }
+  // elements are kept when there is no filter for their type
+  pub fn keep(&self, element_type: &str, tags: &[(&str,&str)]) -> bool {
+    let o_filter = match element_type {
+      "node" => &self.node_filter,
+      "way" => &self.way_filter,
+      "relation" => &self.relation_filter,
+      _ => return true,
+    };
+    o_filter.as_ref().map(|filter| filter.matches(tags)).unwrap_or(true)
+  }
+}
+
+fn open_scans(
+  pbf: &dyn Source, table: &ScanTable, n: usize
+) -> Result<Vec<Scan<impl std::io::Read+std::io::Seek+Send+'static>>,IngestError> {
+  (0..n).map(|_| {
+    let h = pbf.open().map_err(|source| IngestError::Io { file: pbf.name(), source })?;
+    let parser = Parser::new(Box::new(h));
+    Ok(Scan::from_table(parser, table.clone()))
+  }).collect()
+}
+
+// stop the writer and prefer its error over the producer error,
+// since a failed write only shows up as a closed channel in the producers
+async fn abort_ingest<X>(
+  batch_sender: channel::Sender<X>,
+  work: Vec<task::JoinHandle<Result<(),IngestError>>>,
+  err: IngestError,
+) -> IngestError {
+  batch_sender.close();
+  for r in join_all(work).await {
+    if let Err(e) = r { return e }
+  }
+  err
+}
+
+// the position of each source when the sources are read one after another, plus the end
+// of the last one. checkpoint offsets are positions, so with one source they are offsets
+// into that pbf.
+fn source_bases(sources: &[(Arc<dyn Source>,ScanTable)]) -> Result<Vec<u64>,IngestError> {
+  let mut bases = vec![0];
+  for (pbf,_) in sources.iter() {
+    let size = pbf.size().map_err(|source| IngestError::Io { file: pbf.name(), source })?;
+    bases.push(bases[bases.len()-1] + size);
+  }
+  Ok(bases)
+}
+
+// the offset in source i to resume a stage from, or None when the stage is past it
+fn source_start(bases: &[u64], i: usize, position: u64) -> Option<u64> {
+  if position >= bases[i+1] { return None }
+  Some(position.saturating_sub(bases[i]))
+}
+
+// the checkpoint state after a batch of source i. the end of a source that isn't the
+// last is the start of the next one.
+fn source_state(bases: &[u64], i: usize, o_next_offset: Option<u64>) -> StageState {
+  match o_next_offset {
+    Some(offset) => StageState::Offset(bases[i] + offset),
+    None if i + 2 < bases.len() => StageState::Offset(bases[i+1]),
+    None => StageState::Done,
+  }
+}
+
+// the extent of the sources, from their pbf header bboxes or else from every node
+async fn dataset_bounds(
+  sources: &[(Arc<dyn Source>,ScanTable)], nproc: usize, channel_size: usize,
+) -> Result<BBox,IngestError> {
+  let mut bbox = BBox {
+    west: f64::INFINITY, south: f64::INFINITY,
+    east: f64::NEG_INFINITY, north: f64::NEG_INFINITY,
+  };
+  let mut extend = |(lon,lat): (f64,f64)| {
+    bbox.west = bbox.west.min(lon);
+    bbox.south = bbox.south.min(lat);
+    bbox.east = bbox.east.max(lon);
+    bbox.north = bbox.north.max(lat);
+  };
+  for (pbf,table) in sources.iter() {
+    let header_error = |source| IngestError::Blob { stage: "coastline", offset: 0, source };
+    let h = pbf.open().map_err(|source| IngestError::Io { file: pbf.name(), source })?;
+    let (_,_,blob) = Parser::new(Box::new(h)).read_fileblock(0).map_err(header_error)?;
+    if let Some(b) = blob.decode_header().map_err(header_error)?.bbox {
+      // header bounds are in nanodegrees
+      extend((b.left as f64 * 1e-9, b.bottom as f64 * 1e-9));
+      extend((b.right as f64 * 1e-9, b.top as f64 * 1e-9));
+      continue;
+    }
+    let node_receiver = {
+      let scans = open_scans(pbf.as_ref(), table, nproc)?;
+      let offsets = scans[0].get_node_blob_offsets()
+        .map(|(offset,byte_len,_len)| (offset,byte_len))
+        .collect::<Vec<_>>();
+      denorm::get_nodes_bare_ch_from_offsets(scans, channel_size, &offsets).await
+    };
+    while let Ok(nodes) = node_receiver.recv().await {
+      for (_,p) in nodes? {
+        extend(p);
+      }
+    }
+  }
+  Ok(bbox)
+}
+
+// locations of the nodes in ref_table from every source. a node that is in more than
+// one source gets its location from the copy that won deduplication.
+async fn get_node_deps<G>(
+  sources: &[(Arc<dyn Source>,ScanTable)], duplicates: &Duplicates, nproc: usize, channel_size: usize,
+  ref_table: &hashbrown::HashMap<i64,Vec<i64>>, get_offsets: G,
+) -> Result<hashbrown::HashMap<i64,(f64,f64)>,IngestError>
+where G: Fn(&ScanTable) -> Vec<(u64,usize)> {
+  let mut node_deps = hashbrown::HashMap::new();
+  for (i,(pbf,table)) in sources.iter().enumerate() {
+    let node_offsets = get_offsets(table);
+    if node_offsets.is_empty() { continue }
+    let node_receiver = {
+      let scans = open_scans(pbf.as_ref(), table, nproc)?;
+      denorm::get_nodes_bare_ch_from_offsets(scans, channel_size, &node_offsets).await
+    };
+    let deps = denorm::denormalize_ways(ref_table, node_receiver).await?;
+    node_deps.extend(deps.into_iter().filter(|(id,_)| duplicates.keep("node", *id, i)));
+  }
+  Ok(node_deps)
+}
+
 impl Ingest {
   pub fn new(stages: &[&str]) -> Self {
+    Self::from_feature_types(stages, FeatureTypes::default())
+  }
+
+  // classify elements with user rules before georender-pack's own table
+  pub fn from_feature_types(stages: &[&str], feature_types: FeatureTypes) -> Self {
     Self {
       place_other: *georender_pack::osm_types::get_types().get("place.other").unwrap(),
+      feature_types: std::sync::Arc::new(feature_types),
       progress: Arc::new(RwLock::new(Progress::new(stages))),
     }
   }
 
   // build the scan table
-  pub async fn scan(&mut self, pbf_file: &str) -> ScanTable {
+  pub async fn scan(&mut self, pbf_file: &str) -> Result<ScanTable,IngestError> {
+    self.scan_source(&FileSource::new(pbf_file)).await
+  }
+
+  // build the scan table of a pbf from any source
+  pub async fn scan_source(&mut self, pbf: &dyn Source) -> Result<ScanTable,IngestError> {
     self.progress.write().await.start("scan");
     let scan_table = {
+      let io_error = |source| IngestError::Io { file: pbf.name(), source };
       let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
       let parsers = (0..nproc).map(|_| {
-        let h = std::fs::File::open(pbf_file).unwrap();
-        Parser::new(Box::new(h))
-      }).collect::<Vec<_>>();
-      let file_size = std::fs::File::open(pbf_file).unwrap().metadata().unwrap().len();
-      parallel_scan(self.progress.clone(), parsers, 0, file_size).await.unwrap()
+        let h = pbf.open().map_err(io_error)?;
+        Ok(Parser::new(Box::new(h)))
+      }).collect::<Result<Vec<_>,IngestError>>()?;
+      let file_size = pbf.size().map_err(io_error)?;
+      parallel_scan(self.progress.clone(), parsers, 0, file_size).await?
     };
     self.progress.write().await.add("scan", 0);
     self.progress.write().await.end("scan");
-    scan_table
+    Ok(scan_table)
+  }
+
+  // find the elements that are in more than one of the pbfs
+  pub async fn dedup(
+    &mut self, sources: &[(Arc<dyn Source>,ScanTable)]
+  ) -> Result<Duplicates,IngestError> {
+    self.progress.write().await.start("dedup");
+    let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let tables = sources.iter().map(|(_,table)| table).collect::<Vec<_>>();
+    let duplicates = dedup::find_duplicates(self.progress.clone(), &tables, |i| {
+      let (pbf,table) = &sources[i];
+      Ok(open_scans(pbf.as_ref(), table, 1)?.remove(0))
+    }, nproc).await?;
+    self.progress.write().await.end("dedup");
+    Ok(duplicates)
+  }
+
+  // compare the rows of a db with the pbf it was ingested from. the pbf is ingested
+  // again into a sink that only keeps ids, so elements that a bbox, boundary, filter
+  // or the error policy dropped on purpose are not expected.
+  pub async fn verify<S: RA>(
+    &mut self, mut db: DB<S>, pbf: Arc<dyn Source>, scan_table: ScanTable,
+    ingest_options: &IngestOptions,
+  ) -> Result<Report,Error> {
+    let mut options = ingest_options.clone();
+    // leave the files of the real ingest alone
+    options.checkpoint_file = None;
+    options.resume = false;
+    options.flat_nodes_file = None;
+    options.rejects_file = None;
+    // an ingest that failed on a reject is missing that element either way
+    options.error_policy = ErrorPolicy::Skip;
+    // generated rows aren't compared
+    options.coastline = None;
+    let sink = verify::IdSink::default();
+    self.ingest_source(sink.clone(), pbf, scan_table, &options).await?;
+    self.progress.write().await.start("verify");
+    let report = verify::compare(&mut db, sink.take_counts()).await?;
+    self.progress.write().await.end("verify");
+    Ok(report)
   }
 
   // loop over the pbf, denormalize the records, georender-pack the data into eyros
-  pub async fn ingest(
-    &mut self, mut db: EDB, pbf_file: &str, scan_table: ScanTable,
+  pub async fn ingest<K: Sink+'static>(
+    &mut self, sink: K, pbf_file: &str, scan_table: ScanTable,
     ingest_options: &IngestOptions
-  ) -> () {
+  ) -> Result<(),IngestError> {
+    self.ingest_source(sink, Arc::new(FileSource::new(pbf_file)), scan_table, ingest_options).await
+  }
+
+  // ingest a pbf from any source, like a buffer or a range reader on an object store
+  pub async fn ingest_source<K: Sink+'static>(
+    &mut self, sink: K, pbf: Arc<dyn Source>, scan_table: ScanTable,
+    ingest_options: &IngestOptions
+  ) -> Result<(),IngestError> {
+    let sources = vec![(pbf,scan_table)];
+    self.ingest_files(sink, sources, Duplicates::default(), ingest_options).await
+  }
+
+  // ingest several pbfs into one db. elements in more than one pbf are written once,
+  // from the pbf that duplicates says to keep, and ways and relations find their
+  // members in any of the pbfs.
+  pub async fn ingest_files<K: Sink+'static>(
+    &mut self, mut sink: K, sources: Vec<(Arc<dyn Source>,ScanTable)>, duplicates: Duplicates,
+    ingest_options: &IngestOptions
+  ) -> Result<(),IngestError> {
     const BATCH_SEND_SIZE: usize = 10_000;
     const BATCH_SIZE: usize = 100_000;
     self.progress.write().await.start("ingest");
     let mut work = vec![];
     let (batch_sender,batch_receiver) = channel::bounded(100);
     let mnactive = Arc::new(Mutex::new(1));
+    let mut checkpoint = match (&ingest_options.checkpoint_file, ingest_options.resume) {
+      (Some(file),true) => Checkpoint::load(file).map_err(|source| {
+        IngestError::Checkpoint { file: file.clone(), source }
+      })?,
+      _ => Checkpoint::default(),
+    };
+    // offsets are only meaningful for the same pbfs in the same order
+    let source_names = sources.iter().map(|(pbf,_)| pbf.name()).collect::<Vec<_>>();
+    if let Some(file) = &ingest_options.checkpoint_file {
+      if checkpoint != Checkpoint::default() && checkpoint.sources != source_names {
+        return Err(IngestError::Checkpoint {
+          file: file.clone(),
+          source: format![
+            "checkpoint is for {:?} but the inputs are {:?}", checkpoint.sources, source_names
+          ].into(),
+        });
+      }
+    }
+    checkpoint.sources = source_names;
+    let rejects = Rejects::open(
+      ingest_options.get_error_policy(), ingest_options.rejects_file.as_deref(),
+      checkpoint.rejects, "ingest", self.progress.clone(),
+    )?;
+    let encoder = encode::Encoder::new(
+      self.place_other, self.feature_types.clone(), ingest_options
+    );
+    let options = Arc::new(ingest_options.clone());
+    let batch_sizes = match sources.as_slice() {
+      [(_,table)] => BatchSizes::new(ingest_options, table),
+      _ => {
+        let mut table = ScanTable::default();
+        for (_,t) in sources.iter() { table.extend(t) }
+        BatchSizes::new(ingest_options, &table)
+      },
+    };
+    let bases = source_bases(&sources)?;
+    let duplicates = Arc::new(duplicates);
+    let o_flat_nodes = match &ingest_options.flat_nodes_file {
+      // a node stage that starts from the beginning replaces the locations of a previous ingest
+      Some(file) => {
+        let fresh = ingest_options.ingest_node && checkpoint.node == StageState::Pending;
+        let flat_nodes_error = |source| IngestError::FlatNodes { file: file.clone(), source };
+        // otherwise every way would be encoded without locations
+        if !fresh && std::fs::metadata(file).map(|m| m.len() == 0).unwrap_or(true) {
+          return Err(flat_nodes_error("no node locations. run the node stage first".into()));
+        }
+        let r = if fresh { FlatNodes::create(file) } else { FlatNodes::open(file) };
+        let flat_nodes = r.map_err(flat_nodes_error)?;
+        Some(Arc::new(RwLock::new(flat_nodes)))
+      },
+      None => None,
+    };
+    {
+      let mut total = 0;
+      for (i,(_,scan_table)) in sources.iter().enumerate() {
+        let stages = [
+          (ingest_options.ingest_node, checkpoint.node, &scan_table.node_interval_offsets),
+          (ingest_options.ingest_way, checkpoint.way, &scan_table.way_interval_offsets),
+          (ingest_options.ingest_relation, checkpoint.relation, &scan_table.relation_interval_offsets),
+        ];
+        for (enabled,state,offsets) in stages.iter() {
+          if !enabled || state.is_done() { continue }
+          let start = match source_start(&bases, i, state.start_offset()) {
+            Some(start) => start,
+            None => continue,
+          };
+          total += offsets.values()
+            .filter(|(offset,_,_)| *offset >= start)
+            .map(|(_,_,len)| *len as u64)
+            .sum::<u64>();
+        }
+      }
+      self.progress.write().await.set_total("ingest", total);
+    }
 
     {
       let progress = self.progress.clone();
+      let checkpoint_file = ingest_options.checkpoint_file.clone();
+      let mut checkpoint = checkpoint.clone();
       work.push(task::spawn_local(async move {
+        let write_error = |source| IngestError::Write { stage: "ingest", source };
         let mut sync_count = 0;
-        let mut batch = Vec::with_capacity(BATCH_SEND_SIZE);
-        while let Ok((element_counter,rows)) = batch_receiver.recv().await {
-          batch.extend(rows);
+        let mut rows = checkpoint.rows;
+        let mut batch = Vec::new();
+        while let Ok((element_counter,new_rows,o_mark)) = batch_receiver.recv().await {
+          batch.extend(new_rows);
           if batch.len() >= BATCH_SIZE {
-            db.batch(&batch).await.unwrap();
             sync_count += batch.len();
-            batch.clear();
-            if sync_count > 500_000 {
-              db.sync().await.unwrap();
+            rows += batch.len() as u64;
+            sink.write(std::mem::take(&mut batch)).await.map_err(write_error)?;
+            // with checkpoints, only sync at batch boundaries so a resumed ingest
+            // never sees rows from a partially written batch
+            if sync_count > 500_000 && checkpoint_file.is_none() {
+              sink.flush().await.map_err(write_error)?;
               sync_count = 0;
             }
           }
+          if let (Some(file),Some((stage,state,rejects_len))) = (&checkpoint_file,o_mark) {
+            if !batch.is_empty() {
+              rows += batch.len() as u64;
+              sink.write(std::mem::take(&mut batch)).await.map_err(write_error)?;
+            }
+            sink.flush().await.map_err(write_error)?;
+            sync_count = 0;
+            let checkpoint_error = |source| IngestError::Checkpoint { file: file.clone(), source };
+            checkpoint.set(stage, state).map_err(checkpoint_error)?;
+            checkpoint.rows = rows;
+            checkpoint.rejects = rejects_len;
+            checkpoint.save(file).map_err(checkpoint_error)?;
+          }
           progress.write().await.add("ingest", element_counter);
         }
         if !batch.is_empty() {
-          db.batch(&batch).await.unwrap();
+          rows += batch.len() as u64;
+          sink.write(batch).await.map_err(write_error)?;
         }
-        db.sync().await.unwrap();
+        sink.finish().await.map_err(write_error)?;
         progress.write().await.add("ingest", 0);
+        Ok::<(),IngestError>(())
       }));
     }
 
-    if ingest_options.ingest_node { // node thread
+    if ingest_options.ingest_node && !checkpoint.node.is_done() { // node thread
       *mnactive.lock().await += 1;
-      let place_other = self.place_other.clone();
-      let file = pbf_file.to_string();
+      let options = options.clone();
+      let encoder = encoder.clone();
+      let sources = sources.clone();
+      let bases = bases.clone();
+      let duplicates = duplicates.clone();
       let bs = batch_sender.clone();
-      let table = scan_table.clone();
       let nactive = mnactive.clone();
-      let channel_size = ingest_options.channel_size;
-      task::spawn(async move {
+      let channel_size = batch_sizes.channel_size;
+      let start_offset = checkpoint.node.start_offset();
+      let rejects = rejects.clone();
+      let o_flat_nodes = o_flat_nodes.clone();
+      let flat_nodes_file = ingest_options.flat_nodes_file.clone();
+      let r = task::spawn(async move {
         let mut element_counter = 0;
         let mut batch = Vec::with_capacity(BATCH_SEND_SIZE);
         let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
-        let node_receiver = {
-          let scans = (0..nproc).map(|_| {
-            let h = std::fs::File::open(&file).unwrap();
-            let parser = Parser::new(Box::new(h));
-            Scan::from_table(parser, table.clone())
-          }).collect::<Vec<_>>();
-          denorm::get_nodes_ch(scans, channel_size).await
-        };
-        while let Ok(nodes) = node_receiver.recv().await {
-          for node in nodes.iter() {
-            element_counter += 1;
-            let tags = node.tags.iter()
-              .map(|(k,v)| (k.as_str(),v.as_str()))
-              .collect::<Vec<(&str,&str)>>();
-            let (ft,labels) = georender_pack::tags::parse(&tags).unwrap();
-            if ft == place_other { continue }
-            let r_encoded = georender_pack::encode::node_from_parsed(
-              (node.id as u64)*3+0, (node.lon as f32, node.lat as f32), ft, &labels
-            );
-            if let Ok(encoded) = r_encoded {
-              if encoded.is_empty() { continue }
-              batch.push(eyros::Row::Insert(
-                (
-                  eyros::Coord::Scalar(node.lon as f32),
-                  eyros::Coord::Scalar(node.lat as f32)
-                ),
-                encoded.into()
-              ));
-              if batch.len() >= BATCH_SEND_SIZE {
-                bs.send((element_counter,batch.clone())).await.unwrap();
-                batch.clear();
-                element_counter = 0;
+        // nodes are streamed, so a checkpoint is only saved at the end of each source
+        for (i,(pbf,table)) in sources.iter().enumerate() {
+          let start = match source_start(&bases, i, start_offset) {
+            Some(start) => start,
+            None => continue,
+          };
+          let node_receiver = {
+            let scans = open_scans(pbf.as_ref(), table, nproc)?;
+            let offsets = scans[0].get_node_blob_offsets()
+              .filter(|(offset,_,_)| *offset >= start)
+              .map(|(offset,byte_len,_len)| (offset,byte_len))
+              .collect::<Vec<_>>();
+            denorm::get_nodes_ch_from_offsets(scans, channel_size, &offsets).await
+          };
+          while let Ok(r_nodes) = node_receiver.recv().await {
+            let nodes = r_nodes?;
+            if let (Some(flat_nodes),Some(flat_file)) = (&o_flat_nodes,&flat_nodes_file) {
+              let mut flat_nodes = flat_nodes.write().await;
+              for node in nodes.iter().filter(|node| duplicates.keep("node", node.id, i)) {
+                flat_nodes.set(node.id, (node.lon,node.lat)).map_err(|source| {
+                  IngestError::FlatNodes { file: flat_file.clone(), source }
+                })?;
+              }
+            }
+            for node in nodes.iter() {
+              element_counter += 1;
+              if !duplicates.keep("node", node.id, i) { continue }
+              let tags = node.tags.iter()
+                .map(|(k,v)| (k.as_str(),v.as_str()))
+                .collect::<Vec<(&str,&str)>>();
+              if !options.keep("node", &tags) { continue }
+              let encoded_rows = match encoder.node(node.id, (node.lon,node.lat), &tags) {
+                Ok(encoded_rows) => encoded_rows,
+                Err(reject) => {
+                  rejects.push("node", node.id, &tags, reject).await?;
+                  vec![]
+                },
+              };
+              for (point,encoded) in encoded_rows {
+                batch.push((point, encoded));
+                if batch.len() >= BATCH_SEND_SIZE {
+                  bs.send((element_counter,batch.clone(),None)).await
+                    .map_err(|_| IngestError::Channel { stage: "node" })?;
+                  batch.clear();
+                  element_counter = 0;
+                }
               }
             }
           }
+          // locations have to be on disk before the checkpoint says this source is done
+          if let (Some(flat_nodes),Some(flat_file)) = (&o_flat_nodes,&flat_nodes_file) {
+            flat_nodes.read().await.flush().map_err(|source| {
+              IngestError::FlatNodes { file: flat_file.clone(), source }
+            })?;
+          }
+          let state = source_state(&bases, i, None);
+          let rejects_len = rejects.position().await?;
+          bs.send((element_counter,batch.clone(),Some(("node",state,rejects_len)))).await
+            .map_err(|_| IngestError::Channel { stage: "node" })?;
+          batch.clear();
+          element_counter = 0;
         }
-        if !batch.is_empty() {
-          bs.send((element_counter,batch)).await.unwrap();
-        }
+        rejects.flush().await?;
         {
           let mut n = nactive.lock().await;
           *n -= 1;
           if *n == 0 { bs.close(); }
         }
+        Ok::<(),IngestError>(())
       }).await;
+      if let Err(err) = r {
+        return Err(abort_ingest(batch_sender, work, err).await);
+      }
     }
 
-    if ingest_options.ingest_way { // way thread
+    if ingest_options.ingest_way && !checkpoint.way.is_done() { // way thread
       *mnactive.lock().await += 1;
-      let place_other = self.place_other.clone();
-      let file = pbf_file.to_string();
+      let options = options.clone();
+      let encoder = encoder.clone();
+      let sources = sources.clone();
+      let bases = bases.clone();
+      let duplicates = duplicates.clone();
       let bs = batch_sender.clone();
-      let table = scan_table.clone();
       let nactive = mnactive.clone();
-      let channel_size = ingest_options.channel_size;
-      let way_batch_size = ingest_options.way_batch_size;
-      task::spawn(async move {
+      let channel_size = batch_sizes.channel_size;
+      let way_batch_size = batch_sizes.way_batch_size;
+      let start_offset = checkpoint.way.start_offset();
+      let rejects = rejects.clone();
+      let o_flat_nodes = o_flat_nodes.clone();
+      let r = task::spawn(async move {
         let mut batch = Vec::with_capacity(BATCH_SEND_SIZE);
         let mut element_counter = 0;
         let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
-        {
-          let mut offset = 0;
+        for (i,(pbf,table)) in sources.iter().enumerate() {
+          let mut offset = match source_start(&bases, i, start_offset) {
+            Some(offset) => offset,
+            None => continue,
+          };
           loop {
             let (o_next_offset,ways) = {
-              let scans = (0..nproc).map(|_| {
-                let h = std::fs::File::open(&file).unwrap();
-                let parser = Parser::new(Box::new(h));
-                Scan::from_table(parser, table.clone())
-              }).collect::<Vec<_>>();
-              denorm::get_ways(scans, channel_size, offset, way_batch_size).await
+              let scans = open_scans(pbf.as_ref(), table, nproc)?;
+              denorm::get_ways(scans, channel_size, offset, way_batch_size).await?
             };
-            let way_ref_table = denorm::way_ref_table(&ways);
-            let node_receiver = {
-              let node_offsets = denorm::get_node_offsets_from_ways(&table, &ways);
-              let scans = (0..nproc).map(|_| {
-                let h = std::fs::File::open(&file).unwrap();
-                let parser = Parser::new(Box::new(h));
-                Scan::from_table(parser, table.clone())
-              }).collect::<Vec<_>>();
-              denorm::get_nodes_bare_ch_from_offsets(scans, channel_size, &node_offsets).await
+            let all_node_deps = if let Some(flat_nodes) = &o_flat_nodes {
+              flat_nodes.read().await.get_many(ways.iter().flat_map(|way| way.refs.iter()))
+            } else {
+              let way_ref_table = denorm::way_ref_table(&ways);
+              get_node_deps(&sources, &duplicates, nproc, channel_size, &way_ref_table, |t| {
+                denorm::get_node_offsets_from_ways(t, &ways)
+              }).await?
             };
-            let all_node_deps = denorm::denormalize_ways(&way_ref_table, node_receiver).await.unwrap();
             for way in ways {
               element_counter += 1;
+              if !duplicates.keep("way", way.id, i) { continue }
               let tags = way.tags.iter()
                 .map(|(k,v)| (k.as_str(),v.as_str()))
                 .collect::<Vec<(&str,&str)>>();
-              let (ft,labels) = georender_pack::tags::parse(&tags).unwrap();
-              if ft == place_other { continue }
-              let mut pdeps = std::collections::HashMap::new();
-              for r in way.refs.iter() {
-                if let Some((lon,lat)) = all_node_deps.get(r) {
-                  pdeps.insert(*r as u64, (*lon as f32, *lat as f32));
-                }
-              }
-              let mut bbox = (f32::INFINITY,f32::INFINITY,f32::NEG_INFINITY,f32::NEG_INFINITY);
-              if pdeps.len() <= 1 { continue }
-              for (lon,lat) in pdeps.values() {
-                bbox.0 = bbox.0.min(*lon);
-                bbox.1 = bbox.1.min(*lat);
-                bbox.2 = bbox.2.max(*lon);
-                bbox.3 = bbox.3.max(*lat);
-              }
-              let refs = way.refs.iter().map(|r| *r as u64).collect::<Vec<u64>>();
-              let is_area = osm_is_area::way(&tags, &refs);
-              let r_encoded = georender_pack::encode::way_from_parsed(
-                (way.id as u64)*3+1, ft, is_area, &labels, &refs, &pdeps
-              );
-              if let Ok(encoded) = r_encoded {
-                if encoded.is_empty() { continue }
-                let point = (
-                  eyros::Coord::Interval(bbox.0,bbox.2),
-                  eyros::Coord::Interval(bbox.1,bbox.3),
-                );
-                batch.push(eyros::Row::Insert(point, encoded.into()));
+              if !options.keep("way", &tags) { continue }
+              let encoded_rows = match encoder.way(way.id, &tags, &way.refs, &all_node_deps) {
+                Ok(encoded_rows) => encoded_rows,
+                Err(reject) => {
+                  rejects.push("way", way.id, &tags, reject).await?;
+                  vec![]
+                },
+              };
+              for (point,encoded) in encoded_rows {
+                batch.push((point, encoded));
                 if batch.len() >= BATCH_SEND_SIZE {
-                  bs.send((element_counter,batch.clone())).await.unwrap();
+                  bs.send((element_counter,batch.clone(),None)).await
+                    .map_err(|_| IngestError::Channel { stage: "way" })?;
                   batch.clear();
                   element_counter = 0;
                 }
               }
             }
+            let state = source_state(&bases, i, o_next_offset);
+            let rejects_len = rejects.position().await?;
+            bs.send((element_counter,batch.clone(),Some(("way",state,rejects_len)))).await
+              .map_err(|_| IngestError::Channel { stage: "way" })?;
+            batch.clear();
+            element_counter = 0;
             if let Some(next_offset) = o_next_offset {
               offset = next_offset;
             } else {
@@ -259,125 +664,115 @@ impl Ingest {
             }
           }
         }
-        if !batch.is_empty() {
-          bs.send((element_counter,batch)).await.unwrap();
-        }
+        rejects.flush().await?;
         {
           let mut n = nactive.lock().await;
           *n -= 1;
           if *n == 0 { bs.close(); }
         }
+        Ok::<(),IngestError>(())
       }).await;
+      if let Err(err) = r {
+        return Err(abort_ingest(batch_sender, work, err).await);
+      }
     }
 
-    if ingest_options.ingest_relation { // relation thread
+    if ingest_options.ingest_relation && !checkpoint.relation.is_done() { // relation thread
       *mnactive.lock().await += 1;
-      let place_other = self.place_other.clone();
-      let file = pbf_file.to_string();
+      let options = options.clone();
+      let encoder = encoder.clone();
+      let sources = sources.clone();
+      let bases = bases.clone();
+      let duplicates = duplicates.clone();
       let bs = batch_sender.clone();
-      let table = scan_table.clone();
       let nactive = mnactive.clone();
-      let channel_size = ingest_options.channel_size;
-      let relation_batch_size = ingest_options.relation_batch_size;
-      task::spawn(async move {
+      let channel_size = batch_sizes.channel_size;
+      let relation_batch_size = batch_sizes.relation_batch_size;
+      let relation_depth = ingest_options.relation_depth;
+      let start_offset = checkpoint.relation.start_offset();
+      let rejects = rejects.clone();
+      let o_flat_nodes = o_flat_nodes.clone();
+      let r = task::spawn(async move {
         let mut batch = Vec::with_capacity(BATCH_SEND_SIZE);
         let mut element_counter = 0;
         let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
-        {
-          let mut offset = 0;
+        for (i,(pbf,table)) in sources.iter().enumerate() {
+          let mut offset = match source_start(&bases, i, start_offset) {
+            Some(offset) => offset,
+            None => continue,
+          };
           loop {
-            let (o_next_offset,relations) = {
-              let scans = (0..nproc).map(|_| {
-                let h = std::fs::File::open(&file).unwrap();
-                let parser = Parser::new(Box::new(h));
-                Scan::from_table(parser, table.clone())
-              }).collect::<Vec<_>>();
-              denorm::get_relations(scans, channel_size, offset, relation_batch_size).await
+            let (o_next_offset,mut relations) = {
+              let scans = open_scans(pbf.as_ref(), table, nproc)?;
+              denorm::get_relations(scans, channel_size, offset, relation_batch_size).await?
             };
+            if relation_depth > 0 {
+              let sub_relations = denorm::get_sub_relations(
+                |j| open_scans(sources[j].0.as_ref(), &sources[j].1, nproc), sources.len(),
+                |id,j| duplicates.keep("relation", id, j),
+                channel_size, &relations, relation_depth
+              ).await?;
+              denorm::flatten_relations(&mut relations, &sub_relations, relation_depth);
+            }
             let relation_ref_table = denorm::relation_ref_table(&relations);
-            let way_receiver = {
-              let scans = (0..nproc).map(|_| {
-                let h = std::fs::File::open(&file).unwrap();
-                let parser = Parser::new(Box::new(h));
-                Scan::from_table(parser, table.clone())
-              }).collect::<Vec<_>>();
-              let way_offsets = denorm::get_way_offsets_from_relations(&table, &relations);
-              denorm::get_ways_bare_ch_from_offsets(scans, channel_size, &way_offsets).await
-            };
-            let (all_node_deps,all_way_deps) = {
-              let scans = (0..nproc).map(|_| {
-                let h = std::fs::File::open(&file).unwrap();
-                let parser = Parser::new(Box::new(h));
-                Scan::from_table(parser, table.clone())
-              }).collect::<Vec<_>>();
-              denorm::denormalize_relations(
-                scans, channel_size, &relation_ref_table, way_receiver
-              ).await.unwrap()
+            // member ways can be in any of the sources
+            let mut all_way_deps = hashbrown::HashMap::new();
+            for (j,(pbf,table)) in sources.iter().enumerate() {
+              let way_offsets = denorm::get_way_offsets_from_relations(table, &relations);
+              if way_offsets.is_empty() { continue }
+              let way_receiver = {
+                let scans = open_scans(pbf.as_ref(), table, nproc)?;
+                denorm::get_ways_bare_ch_from_offsets(scans, channel_size, &way_offsets).await
+              };
+              let way_deps = denorm::get_way_deps(&relation_ref_table, way_receiver).await?;
+              all_way_deps.extend(way_deps.into_iter().filter(|(id,_)| {
+                duplicates.keep("way", *id, j)
+              }));
+            }
+            let all_node_deps = if let Some(flat_nodes) = &o_flat_nodes {
+              flat_nodes.read().await.get_many(all_way_deps.values().flatten())
+            } else {
+              let way_ref_table = denorm::bare_way_ref_table(&all_way_deps);
+              get_node_deps(&sources, &duplicates, nproc, channel_size, &way_ref_table, |t| {
+                denorm::get_node_offsets_from_bare_ways(t, &all_way_deps)
+              }).await?
             };
 
             for relation in relations {
               element_counter += 1;
+              if !duplicates.keep("relation", relation.id, i) { continue }
               let tags = relation.tags.iter()
                 .map(|(k,v)| (k.as_str(),v.as_str()))
                 .collect::<Vec<(&str,&str)>>();
-              let (ft,labels) = georender_pack::tags::parse(&tags).unwrap();
-              if ft == place_other { continue }
-              let is_area = osm_is_area::relation(&tags, &vec![1]);
-              if !is_area { continue }
+              if !options.keep("relation", &tags) { continue }
               let members = relation.members.iter()
-                .filter(|m| m.member_type == element::MemberType::Way)
-                .filter(|m| &m.role == "inner" || &m.role == "outer")
-                .map(|m| georender_pack::Member::new(
-                  m.id as u64,
-                  match m.role.as_str() {
-                    "outer" => georender_pack::MemberRole::Outer(),
-                    "inner" => georender_pack::MemberRole::Inner(),
-                    _ => panic!["unexpected role should have been filtered out"],
-                  },
-                  georender_pack::MemberType::Way()
-                ))
+                .map(|m| (m.member_type.clone(), m.id, m.role.as_str()))
                 .collect::<Vec<_>>();
-              if members.is_empty() { continue }
-
-              let mut node_deps = std::collections::HashMap::new();
-              let mut way_deps = std::collections::HashMap::new();
-              for m in members.iter() {
-                if let Some(refs) = all_way_deps.get(&(m.id as i64)) {
-                  way_deps.insert(m.id as u64, refs.iter()
-                    .map(|r| *r as u64).collect::<Vec<u64>>());
-                  for r in refs.iter() {
-                    if let Some((lon,lat)) = all_node_deps.get(r) {
-                      node_deps.insert(*r as u64, (*lon as f32, *lat as f32));
-                    }
-                  }
-                }
-              }
-
-              if node_deps.len() <= 1 { continue }
-              let mut bbox = (f32::INFINITY,f32::INFINITY,f32::NEG_INFINITY,f32::NEG_INFINITY);
-              for p in node_deps.values() {
-                bbox.0 = bbox.0.min(p.0);
-                bbox.1 = bbox.1.min(p.1);
-                bbox.2 = bbox.2.max(p.0);
-                bbox.3 = bbox.3.max(p.1);
-              }
-              let r_encoded = georender_pack::encode::relation_from_parsed(
-                (relation.id as u64)*3+2, ft, is_area,
-                &labels, &members, &node_deps, &way_deps
-              );
-              if let Ok(encoded) = r_encoded {
-                let point = (
-                  eyros::Coord::Interval(bbox.0,bbox.2),
-                  eyros::Coord::Interval(bbox.1,bbox.3),
-                );
-                batch.push(eyros::Row::Insert(point, encoded.into()));
+              let encoded_rows = match encoder.relation(
+                relation.id, &tags, &members, &all_node_deps, &all_way_deps
+              ) {
+                Ok(encoded_rows) => encoded_rows,
+                Err(reject) => {
+                  rejects.push("relation", relation.id, &tags, reject).await?;
+                  vec![]
+                },
+              };
+              for (point,encoded) in encoded_rows {
+                batch.push((point, encoded));
                 if batch.len() >= BATCH_SEND_SIZE {
-                  bs.send((element_counter,batch.clone())).await.unwrap();
+                  bs.send((element_counter,batch.clone(),None)).await
+                    .map_err(|_| IngestError::Channel { stage: "relation" })?;
                   batch.clear();
                   element_counter = 0;
                 }
               }
             }
+            let state = source_state(&bases, i, o_next_offset);
+            let rejects_len = rejects.position().await?;
+            bs.send((element_counter,batch.clone(),Some(("relation",state,rejects_len)))).await
+              .map_err(|_| IngestError::Channel { stage: "relation" })?;
+            batch.clear();
+            element_counter = 0;
             if let Some(next_offset) = o_next_offset {
               offset = next_offset;
             } else {
@@ -385,15 +780,107 @@ impl Ingest {
             }
           }
         }
-        if !batch.is_empty() {
-          bs.send((element_counter,batch)).await.unwrap();
+        rejects.flush().await?;
+        {
+          let mut n = nactive.lock().await;
+          *n -= 1;
+          if *n == 0 { bs.close(); }
         }
+        Ok::<(),IngestError>(())
+      }).await;
+      if let Err(err) = r {
+        return Err(abort_ingest(batch_sender, work, err).await);
+      }
+    }
+
+    if let Some(coastline) = ingest_options.coastline.filter(|_| !checkpoint.coastline.is_done()) {
+      // coastline thread
+      *mnactive.lock().await += 1;
+      let encoder = encoder.clone();
+      let sources = sources.clone();
+      let duplicates = duplicates.clone();
+      let bs = batch_sender.clone();
+      let nactive = mnactive.clone();
+      let channel_size = batch_sizes.channel_size;
+      let way_batch_size = batch_sizes.way_batch_size;
+      let o_region = ingest_options.bbox
+        .or_else(|| ingest_options.boundary.as_ref().map(|b| *b.bbox()));
+      let rejects = rejects.clone();
+      let o_flat_nodes = o_flat_nodes.clone();
+      let r = task::spawn(async move {
+        let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        let mut lines = vec![];
+        for (i,(pbf,table)) in sources.iter().enumerate() {
+          let mut offset = 0;
+          loop {
+            let (o_next_offset,ways) = {
+              let scans = open_scans(pbf.as_ref(), table, nproc)?;
+              denorm::get_ways(scans, channel_size, offset, way_batch_size).await?
+            };
+            let ways = ways.into_iter()
+              .filter(|way| duplicates.keep("way", way.id, i))
+              .filter(|way| coastline::is_coastline(&way.tags))
+              .collect::<Vec<_>>();
+            let node_deps = if let Some(flat_nodes) = &o_flat_nodes {
+              flat_nodes.read().await.get_many(ways.iter().flat_map(|way| way.refs.iter()))
+            } else {
+              let way_ref_table = denorm::way_ref_table(&ways);
+              get_node_deps(&sources, &duplicates, nproc, channel_size, &way_ref_table, |t| {
+                denorm::get_node_offsets_from_ways(t, &ways)
+              }).await?
+            };
+            for way in ways.iter() {
+              lines.push(way.refs.iter()
+                .filter_map(|r| node_deps.get(r).map(|p| (*r as u64,*p)))
+                .collect::<Vec<_>>());
+            }
+            match o_next_offset {
+              Some(next_offset) => offset = next_offset,
+              None => break,
+            }
+          }
+        }
+        // without an extract region, chains are closed against the bounds of the data
+        let region = match o_region {
+          Some(region) => region,
+          None => dataset_bounds(&sources, nproc, channel_size).await?,
+        };
+        let mut next_id = u64::MAX;
+        let mut polygons = vec![];
+        if region.west < region.east && region.south < region.north {
+          let chains = coastline::stitch(lines);
+          for polygon in coastline::polygons(chains, coastline.fill, &region, &mut next_id) {
+            coastline::split(polygon, &mut next_id, &mut polygons);
+          }
+        }
+        let mut batch = Vec::with_capacity(BATCH_SEND_SIZE);
+        for (i,polygon) in polygons.iter().enumerate() {
+          let encoded_id = encode::generated_id(i as u64);
+          match encoder.coastline(encoded_id, coastline.feature_type, polygon) {
+            Ok(Some(row)) => batch.push(row),
+            Ok(None) => {},
+            Err(reject) => rejects.push("coastline", i as i64, &[], reject).await?,
+          }
+          if batch.len() >= BATCH_SEND_SIZE {
+            bs.send((0,batch.clone(),None)).await
+              .map_err(|_| IngestError::Channel { stage: "coastline" })?;
+            batch.clear();
+          }
+        }
+        let rejects_len = rejects.position().await?;
+        bs.send((0,batch.clone(),Some(("coastline",StageState::Done,rejects_len)))).await
+          .map_err(|_| IngestError::Channel { stage: "coastline" })?;
+        rejects.flush().await?;
         {
           let mut n = nactive.lock().await;
           *n -= 1;
           if *n == 0 { bs.close(); }
         }
+        Ok::<(),IngestError>(())
       }).await;
+      if let Err(err) = r {
+        return Err(abort_ingest(batch_sender, work, err).await);
+      }
     }
 
     {
@@ -401,12 +888,270 @@ impl Ingest {
       *n -= 1;
       if *n == 0 { batch_sender.close(); }
     }
-    join_all(work).await;
+    for r in join_all(work).await { r?; }
     self.progress.write().await.end("ingest");
+    Ok(())
+  }
+
+  // apply an osmChange on top of an edb that was ingested from pbf_file.
+  // locations, refs and members of elements that are not in the changeset are
+  // read from the pbf, so ways and relations with moved nodes get new geometry.
+  // the edb doesn't keep them, so pbf_file has to have every earlier changeset
+  // applied to it too (like with osmium apply-changes), or elements that use an
+  // element from an earlier changeset are encoded with its old state.
+  pub async fn changeset<S: RA>(
+    &mut self, mut db: DB<S>, pbf_file: &str, scan_table: ScanTable,
+    changeset: &Changeset, ingest_options: &IngestOptions,
+  ) -> Result<(),Error> {
+    self.progress.write().await.start("changeset");
+    let rejects = Rejects::open(
+      ingest_options.get_error_policy(), ingest_options.rejects_file.as_deref(),
+      0, "changeset", self.progress.clone(),
+    )?;
+    let encoder = encode::Encoder::new(
+      self.place_other, self.feature_types.clone(), ingest_options
+    );
+    let nproc = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let channel_size = ingest_options.channel_size;
+    let pbf = FileSource::new(pbf_file);
+
+    let mut ways: hashbrown::HashMap<i64,changeset::Way> = changeset.ways.iter()
+      .filter_map(|(id,o_way)| o_way.as_ref().map(|way| (*id,way.clone())))
+      .collect();
+    { // ways from the pbf that reference a changed node
+      let scans = open_scans(&pbf, &scan_table, nproc)?;
+      let offsets = scans[0].get_way_blob_offsets()
+        .map(|(offset,byte_len,_len)| (offset,byte_len))
+        .collect::<Vec<_>>();
+      let way_receiver = denorm::get_ways_ch_from_offsets(scans, channel_size, &offsets).await;
+      while let Ok(way_group) = way_receiver.recv().await {
+        for way in way_group?.iter() {
+          if changeset.ways.contains_key(&way.id) { continue }
+          if way.refs.iter().any(|r| changeset.nodes.contains_key(r)) {
+            ways.insert(way.id, way.into());
+          }
+        }
+      }
+    }
+
+    let mut relations: hashbrown::HashMap<i64,changeset::Relation> = changeset.relations.iter()
+      .filter_map(|(id,o_relation)| o_relation.as_ref().map(|relation| (*id,relation.clone())))
+      .collect();
+    { // relations from the pbf with a changed member
+      // relations with relation members can depend on relations that come later
+      let mut parents = vec![];
+      let scans = open_scans(&pbf, &scan_table, nproc)?;
+      let offsets = scans[0].get_relation_blob_offsets()
+        .map(|(offset,byte_len,_len)| (offset,byte_len))
+        .collect::<Vec<_>>();
+      let relation_receiver = denorm::get_relations_ch_from_offsets(
+        scans, channel_size, &offsets
+      ).await;
+      while let Ok(relation_group) = relation_receiver.recv().await {
+        for relation in relation_group?.iter() {
+          if changeset.relations.contains_key(&relation.id) { continue }
+          let changed = relation.members.iter().any(|m| match m.member_type {
+            element::MemberType::Node => changeset.nodes.contains_key(&m.id),
+            element::MemberType::Way => {
+              ways.contains_key(&m.id) || changeset.ways.contains_key(&m.id)
+            },
+            element::MemberType::Relation => changeset.relations.contains_key(&m.id),
+          });
+          if changed {
+            relations.insert(relation.id, relation.into());
+          } else if relation.members.iter()
+            .any(|m| m.member_type == element::MemberType::Relation) {
+            parents.push(changeset::Relation::from(relation));
+          }
+        }
+      }
+      // a relation with a changed sub-relation is changed too, up to any depth
+      loop {
+        let (changed,rest): (Vec<_>,Vec<_>) = parents.into_iter().partition(|relation| {
+          relation.members.iter().any(|m| {
+            m.member_type == element::MemberType::Relation && relations.contains_key(&m.id)
+          })
+        });
+        parents = rest;
+        if changed.is_empty() { break }
+        for relation in changed {
+          relations.insert(relation.id, relation);
+        }
+      }
+    }
+
+    // flatten sub-relations into their parents as ingest does. sub-relations come from
+    // the changeset when it has them and from the pbf otherwise.
+    if ingest_options.relation_depth > 0 {
+      let mut nested = relations.values()
+        .filter(|relation| {
+          relation.members.iter().any(|m| m.member_type == element::MemberType::Relation)
+        })
+        .map(element::Relation::from)
+        .collect::<Vec<_>>();
+      if !nested.is_empty() {
+        let created = changeset.relations.values()
+          .filter_map(|o_relation| o_relation.as_ref().map(element::Relation::from))
+          .collect::<Vec<_>>();
+        let parents = nested.iter().chain(created.iter()).cloned().collect::<Vec<_>>();
+        let mut sub_relations = denorm::get_sub_relations(
+          |_| open_scans(&pbf, &scan_table, nproc), 1,
+          |id,_| !changeset.relations.contains_key(&id),
+          channel_size, &parents, ingest_options.relation_depth
+        ).await?;
+        sub_relations.extend(created.into_iter().map(|relation| (relation.id,relation)));
+        denorm::flatten_relations(&mut nested, &sub_relations, ingest_options.relation_depth);
+        for relation in nested.iter() {
+          relations.insert(relation.id, relation.into());
+        }
+      }
+    }
+
+    let mut way_deps: hashbrown::HashMap<i64,Vec<i64>> = ways.iter()
+      .map(|(id,way)| (*id,way.refs.clone()))
+      .collect();
+    { // unchanged member ways of the relations
+      let missing = relations.values()
+        .flat_map(|relation| relation.members.iter())
+        .filter(|m| m.member_type == element::MemberType::Way)
+        .filter(|m| !way_deps.contains_key(&m.id) && !changeset.ways.contains_key(&m.id))
+        .map(|m| m.id)
+        .collect::<std::collections::HashSet<i64>>();
+      let mut offsets = std::collections::HashSet::new();
+      for id in missing.iter() {
+        for offset in scan_table.get_way_blob_offsets_for_id(*id) {
+          offsets.insert(offset);
+        }
+      }
+      let offsets = offsets.iter()
+        .map(|(offset,byte_len,_len)| (*offset,*byte_len))
+        .collect::<Vec<_>>();
+      let way_receiver = denorm::get_ways_bare_ch_from_offsets(
+        open_scans(&pbf, &scan_table, nproc)?, channel_size, &offsets
+      ).await;
+      while let Ok(way_group) = way_receiver.recv().await {
+        for (id,refs) in way_group? {
+          if missing.contains(&id) {
+            way_deps.insert(id, refs);
+          }
+        }
+      }
+    }
+
+    let mut node_deps: hashbrown::HashMap<i64,(f64,f64)> = hashbrown::HashMap::new();
+    {
+      let mut missing = std::collections::HashSet::new();
+      for refs in way_deps.values() {
+        for r in refs.iter() {
+          match changeset.nodes.get(r) {
+            Some(Some(node)) => { node_deps.insert(*r, (node.lon,node.lat)); },
+            Some(None) => {},
+            None => { missing.insert(*r); },
+          }
+        }
+      }
+      let mut offsets = std::collections::HashSet::new();
+      for id in missing.iter() {
+        for offset in scan_table.get_node_blob_offsets_for_id(*id) {
+          offsets.insert(offset);
+        }
+      }
+      let offsets = offsets.iter()
+        .map(|(offset,byte_len,_len)| (*offset,*byte_len))
+        .collect::<Vec<_>>();
+      let node_receiver = denorm::get_nodes_bare_ch_from_offsets(
+        open_scans(&pbf, &scan_table, nproc)?, channel_size, &offsets
+      ).await;
+      while let Ok(nodes) = node_receiver.recv().await {
+        for (id,p) in nodes? {
+          if missing.contains(&id) {
+            node_deps.insert(id, p);
+          }
+        }
+      }
+    }
+
+    // remove the previous rows for every element that is deleted or re-encoded
+    {
+      let mut ids = std::collections::HashSet::new();
+      for id in changeset.nodes.keys() {
+        ids.insert((*id as u64)*3+0);
+      }
+      for id in changeset.ways.keys().chain(ways.keys()) {
+        ids.insert((*id as u64)*3+1);
+      }
+      for id in changeset.relations.keys().chain(relations.keys()) {
+        ids.insert((*id as u64)*3+2);
+      }
+      let mut rows = vec![];
+      {
+        let mut stream = db.query(&((-180.0,-90.0),(180.0,90.0))).await?;
+        while let Some(result) = stream.next().await {
+          let (p,v) = result?;
+          if v.is_empty() { continue }
+          let id = v.get_id();
+          if ids.contains(&id) {
+            rows.push(eyros::Row::Delete(p,id));
+          }
+        }
+      }
+      if !rows.is_empty() {
+        db.batch(&rows).await?;
+        db.sync().await?;
+      }
+    }
+
+    self.progress.write().await.set_total(
+      "changeset", (changeset.nodes.len() + ways.len() + relations.len()) as u64
+    );
+    let mut rows = vec![];
+    for node in changeset.nodes.values().filter_map(|o_node| o_node.as_ref()) {
+      let tags = encode::tag_refs(&node.tags);
+      if !ingest_options.keep("node", &tags) { continue }
+      match encoder.node(node.id, (node.lon,node.lat), &tags) {
+        Ok(encoded_rows) => rows.extend(encoded_rows.into_iter()
+          .map(|(point,encoded)| eyros::Row::Insert(point, encoded))),
+        Err(reject) => rejects.push("node", node.id, &tags, reject).await?,
+      }
+    }
+    self.progress.write().await.add("changeset", changeset.nodes.len());
+    for way in ways.values() {
+      let tags = encode::tag_refs(&way.tags);
+      if !ingest_options.keep("way", &tags) { continue }
+      match encoder.way(way.id, &tags, &way.refs, &node_deps) {
+        Ok(encoded_rows) => rows.extend(encoded_rows.into_iter()
+          .map(|(point,encoded)| eyros::Row::Insert(point, encoded))),
+        Err(reject) => rejects.push("way", way.id, &tags, reject).await?,
+      }
+    }
+    self.progress.write().await.add("changeset", ways.len());
+    for relation in relations.values() {
+      let tags = encode::tag_refs(&relation.tags);
+      if !ingest_options.keep("relation", &tags) { continue }
+      let members = relation.members.iter()
+        .map(|m| (m.member_type.clone(), m.id, m.role.as_str()))
+        .collect::<Vec<_>>();
+      match encoder.relation(
+        relation.id, &tags, &members, &node_deps, &way_deps
+      ) {
+        Ok(encoded_rows) => rows.extend(encoded_rows.into_iter()
+          .map(|(point,encoded)| eyros::Row::Insert(point, encoded))),
+        Err(reject) => rejects.push("relation", relation.id, &tags, reject).await?,
+      }
+    }
+    self.progress.write().await.add("changeset", relations.len());
+    rejects.flush().await?;
+    if !rows.is_empty() {
+      db.batch(&rows).await?;
+    }
+    db.sync().await?;
+    self.progress.write().await.add("changeset", 0);
+    self.progress.write().await.end("changeset");
+    Ok(())
   }
 
-  pub async fn optimize(
-    &mut self, in_db: EDB, mut out_db: EDB, xy_divs: (usize,usize)
+  pub async fn optimize<S: RA, R: RA>(
+    &mut self, in_db: DB<S>, mut out_db: DB<R>, xy_divs: (usize,usize)
   ) -> Result<(),Error> {
     self.progress.write().await.start("optimize");
     let (x_divs,y_divs) = xy_divs;
@@ -424,6 +1169,8 @@ impl Ingest {
       (db_bounds.1).1 - (db_bounds.0).1,
     );
 
+    // the db has no row count and counting would read it twice,
+    // so the optimize total is left unknown
     let (bbox_sender,bbox_receiver) = channel::unbounded();
     task::spawn(async move {
       for iy in 0..y_divs {
@@ -467,16 +1214,6 @@ impl Ingest {
             let (p,v) = r?;
             if v.is_empty() { continue }
             let id = v.get_id();
-            if skip.read().await.contains_key(&id) {
-              let mut skip_w = skip.write().await;
-              let n = {
-                let n = skip_w.get_mut(&id).unwrap();
-                *n -= 1;
-                *n
-              };
-              if n <= 0 { skip_w.remove(&id); }
-              continue;
-            }
             let pbounds = (
               (
                 *match &p.0 {
@@ -499,6 +1236,22 @@ impl Ingest {
                 },
               ),
             );
+            // rows are told apart by their bounds too, since an element can be
+            // stored as more than one row, like on both sides of the antimeridian
+            let key = (id, [
+              (pbounds.0).0.to_bits(), (pbounds.0).1.to_bits(),
+              (pbounds.1).0.to_bits(), (pbounds.1).1.to_bits(),
+            ]);
+            if skip.read().await.contains_key(&key) {
+              let mut skip_w = skip.write().await;
+              let n = {
+                let n = skip_w.get_mut(&key).unwrap();
+                *n -= 1;
+                *n
+              };
+              if n <= 0 { skip_w.remove(&key); }
+              continue;
+            }
             {
               let mut nx = 1;
               let mut ny = 1;
@@ -510,7 +1263,7 @@ impl Ingest {
               }
               let n = nx*ny-1;
               if n > 0 {
-                skip.write().await.insert(id, n);
+                skip.write().await.insert(key, n);
               }
             }
             (q_bbox.0).0 = (q_bbox.0).0.min((pbounds.0).0);
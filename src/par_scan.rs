@@ -1,4 +1,4 @@
-use crate::{Error,progress::Progress};
+use crate::{IngestError,progress::Progress};
 use async_std::{channel,task,sync::{Arc,RwLock},prelude::*};
 use osmpbf_parser::{Parser,ScanTable,element,Element};
 use futures::future::join_all;
@@ -7,35 +7,48 @@ use std::io::{Read,Seek};
 
 pub async fn parallel_scan<F: Read+Seek+Send+'static>(
   progress: Arc<RwLock<Progress>>, mut parsers: Vec<Parser<F>>, start: u64, end: u64
-) -> Result<ScanTable,Error> {
+) -> Result<ScanTable,IngestError> {
   let (offset_sender,offset_receiver) = channel::unbounded();
   let offset_work = {
     let mut parser = parsers.pop().unwrap();
     task::spawn(async move {
       let mut offset = start;
       while offset < end {
-        let (blob_header_len,blob_header) = parser.read_blob_header(offset).unwrap();
+        let r_header = parser.read_blob_header(offset);
+        let (blob_header_len,blob_header) = match r_header {
+          Ok(header) => header,
+          Err(e) => {
+            offset_sender.close();
+            return Err(IngestError::Blob { stage: "scan", offset, source: e.into() });
+          },
+        };
         let blob_offset = offset + blob_header_len;
         let blob_len = blob_header.datasize as usize;
         let len = blob_header_len + blob_len as u64;
         if offset > 0 { // skip header
-          offset_sender.send((blob_offset,blob_len)).await.unwrap();
+          if offset_sender.send((blob_offset,blob_len)).await.is_err() {
+            return Err(IngestError::Channel { stage: "scan" });
+          }
         }
         offset += len;
       }
       offset_sender.close();
+      Ok(())
     })
   };
 
-  let mut table_work: Vec<task::JoinHandle<Result<ScanTable,Error>>> = vec![];
+  let mut table_work: Vec<task::JoinHandle<Result<ScanTable,IngestError>>> = vec![];
   for mut parser in parsers {
     let r = offset_receiver.clone();
     let p = progress.clone();
     table_work.push(task::spawn(async move {
       let mut table = ScanTable::default();
       while let Ok((blob_offset,blob_len)) = r.recv().await {
-        let blob = parser.read_blob(blob_offset,blob_len)?;
-        let items = blob.decode_primitive()?.decode();
+        let blob = parser.read_blob(blob_offset,blob_len)
+          .map_err(|e| IngestError::Blob { stage: "scan", offset: blob_offset, source: e.into() })?;
+        let items = blob.decode_primitive()
+          .map_err(|e| IngestError::Blob { stage: "scan", offset: blob_offset, source: e.into() })?
+          .decode();
         let mut etype = element::MemberType::Node;
         let mut min_id = i64::MAX;
         let mut max_id = i64::MIN;
@@ -90,9 +103,10 @@ pub async fn parallel_scan<F: Read+Seek+Send+'static>(
   }
 
   let mut scan_table = ScanTable::default();
-  let (tables,_) = join_all(table_work).join(offset_work).await;
+  let (tables,r_offsets) = join_all(table_work).join(offset_work).await;
   for table in tables {
     scan_table.extend(&table?)
   }
+  r_offsets?;
   Ok(scan_table)
 }
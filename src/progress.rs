@@ -38,6 +38,11 @@ impl Progress {
       info.push_err(err);
     }
   }
+  pub fn set_total(&mut self, label: &str, total: u64) {
+    if let Some(info) = self.info.get_mut(label) {
+      info.set_total(total);
+    }
+  }
   pub fn start(&mut self, label: &str) {
     if let Some(info) = self.info.get_mut(label) {
       info.start();
@@ -57,6 +62,21 @@ impl Progress {
       info.tick();
     }
   }
+  // a line for each running stage, without escape codes for log files. stages that
+  // haven't started are skipped and ended stages are printed one last time.
+  pub fn plain_lines(&self, ended: &mut std::collections::HashSet<String>) -> Vec<String> {
+    let mut lines = vec![];
+    for info in self.stages.iter().map(|s| &self.info[s]) {
+      if !info.is_started() || ended.contains(info.label()) { continue }
+      if info.is_ended() { ended.insert(info.label().to_string()); }
+      lines.push(info.to_string());
+    }
+    lines
+  }
+  // an ndjson record for each stage
+  pub fn json_lines(&self) -> Vec<String> {
+    self.stages.iter().map(|s| self.info[s].to_json().to_string()).collect()
+  }
 }
 
 pub struct Info {
@@ -65,6 +85,7 @@ pub struct Info {
   end: Option<std::time::Instant>,
   samples: VecDeque<(std::time::Duration,u64)>,
   count: u64,
+  total: Option<u64>,
   sample_size: usize,
   errors: VecDeque<String>,
   error_size: usize,
@@ -72,32 +93,18 @@ pub struct Info {
 
 impl std::fmt::Display for Info {
   fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-    let d = hms(match (self.start,self.end) {
-      (Some(s),Some(e)) => e.checked_duration_since(s),
-      (Some(s),None) => Some(s.elapsed()),
-      _ => None
-    });
-    if let (Some(s),Some(e)) = (self.start,self.end) {
-      let rate = (self.count as f64) / e.duration_since(s).as_secs_f64();
-      write![f, "[{:<9} {}] {:>14} ({:>10}/s)", self.label, d,
-        un(self.count), un(rate.round() as u64)]
-    } else if let (Some(first),Some(last)) = (self.samples.front(),self.samples.back()) {
-      let e = first.0.as_secs_f64() - last.0.as_secs_f64();
-      if e < 0.001 && self.start.is_some() {
-        let rate = (self.count as f64) / self.start.unwrap().elapsed().as_secs_f64();
-        write![f, "[{:<9} {}] {:>14} ({:>10}/s)", self.label, d,
-          un(self.count), un(rate.round() as u64)]
-      } else if e < 0.001 {
-        write![f, "[{:<9} {}] {:^14} ({:^10}/s)", self.label, d,
-          un(self.count), "---"]
-      } else {
-        let rate = ((first.1 - last.1) as f64) / e;
-        write![f, "[{:<9} {}] {:>14} ({:>10}/s)", self.label, d,
-          un(self.count), un(rate.round() as u64)]
-      }
-    } else {
-      write![f, "[{:<9} {}] {:^14} ({:^10}/s)", self.label, d, "---", "---"]
+    let d = hms(self.elapsed());
+    match (self.samples.is_empty() && self.end.is_none(), self.rate()) {
+      (true,_) => write![f, "[{:<9} {}] {:^14} ({:^10}/s)", self.label, d, "---", "---"]?,
+      (false,Some(rate)) => write![f, "[{:<9} {}] {:>14} ({:>10}/s)", self.label, d,
+        un(self.count), un(rate.round() as u64)]?,
+      (false,None) => write![f, "[{:<9} {}] {:^14} ({:^10}/s)", self.label, d,
+        un(self.count), "---"]?,
+    }
+    if let Some(percent) = self.percent() {
+      write![f, " {:>5.1}% eta {}", percent, hms(self.eta())]?;
     }
+    Ok(())
   }
 }
 
@@ -110,6 +117,7 @@ impl Info {
       samples: VecDeque::with_capacity(10),
       errors: VecDeque::with_capacity(10),
       count: 0,
+      total: None,
       sample_size: 20,
       error_size: 10,
     }
@@ -117,6 +125,9 @@ impl Info {
   pub fn add(&mut self, x: usize) {
     self.count += x as u64;
   }
+  pub fn set_total(&mut self, total: u64) {
+    self.total = Some(total);
+  }
   pub fn push_err(&mut self, err: &Error) {
     self.errors.push_front(format!["{}", err]);
     self.errors.truncate(self.error_size);
@@ -127,6 +138,79 @@ impl Info {
   pub fn end(&mut self) {
     self.end = Some(std::time::Instant::now());
   }
+  pub fn label(&self) -> &str {
+    &self.label
+  }
+  pub fn count(&self) -> u64 {
+    self.count
+  }
+  pub fn is_started(&self) -> bool {
+    self.start.is_some()
+  }
+  pub fn is_ended(&self) -> bool {
+    self.end.is_some()
+  }
+  // most recent first
+  pub fn errors(&self) -> impl Iterator<Item=&String> {
+    self.errors.iter()
+  }
+  pub fn elapsed(&self) -> Option<std::time::Duration> {
+    match (self.start,self.end) {
+      (Some(s),Some(e)) => e.checked_duration_since(s),
+      (Some(s),None) => Some(s.elapsed()),
+      _ => None
+    }
+  }
+  // items per second over the whole stage once it has ended,
+  // otherwise over the recent samples
+  pub fn rate(&self) -> Option<f64> {
+    if let (Some(s),Some(e)) = (self.start,self.end) {
+      Some((self.count as f64) / e.duration_since(s).as_secs_f64())
+    } else if let (Some(first),Some(last)) = (self.samples.front(),self.samples.back()) {
+      let e = first.0.as_secs_f64() - last.0.as_secs_f64();
+      if e < 0.001 {
+        self.start.map(|s| (self.count as f64) / s.elapsed().as_secs_f64())
+      } else {
+        Some(((first.1 - last.1) as f64) / e)
+      }
+    } else {
+      None
+    }
+  }
+  pub fn total(&self) -> Option<u64> {
+    self.total
+  }
+  pub fn percent(&self) -> Option<f64> {
+    match (self.total,self.end) {
+      (Some(_),Some(_)) => Some(100.0),
+      (Some(0),None) => Some(0.0),
+      (Some(total),None) => Some((100.0 * (self.count as f64) / (total as f64)).min(100.0)),
+      _ => None,
+    }
+  }
+  // remaining time at the recent rate. counts can overshoot the expected total,
+  // for example when the scan table is stale, so the eta bottoms out at zero.
+  pub fn eta(&self) -> Option<std::time::Duration> {
+    if self.end.is_some() { return Some(std::time::Duration::from_secs(0)) }
+    let remaining = self.total?.saturating_sub(self.count) as f64;
+    self.rate()
+      .filter(|rate| rate.is_finite() && *rate > 0.0)
+      .map(|rate| std::time::Duration::from_secs_f64(remaining / rate))
+  }
+  pub fn to_json(&self) -> serde_json::Value {
+    serde_json::json!({
+      "label": self.label,
+      "count": self.count,
+      "rate": self.rate().filter(|r| r.is_finite()),
+      "elapsed": self.elapsed().map(|d| d.as_secs_f64()),
+      "started": self.is_started(),
+      "ended": self.is_ended(),
+      "total": self.total,
+      "percent": self.percent(),
+      "eta": self.total.and(self.eta()).map(|d| d.as_secs_f64()),
+      "errors": self.errors.iter().collect::<Vec<_>>(),
+    })
+  }
   pub fn tick(&mut self) {
     if self.end.is_some() { return }
     if let Some(start) = &self.start {
@@ -0,0 +1,179 @@
+use crate::{Error,IngestError,Progress};
+use async_std::sync::{Arc,Mutex,RwLock};
+use std::io::{Write,Seek};
+
+// what to do with an element that can't be encoded
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum ErrorPolicy {
+  Fail,
+  Skip,
+  SkipAndLog,
+}
+
+impl Default for ErrorPolicy {
+  fn default() -> Self { ErrorPolicy::Skip }
+}
+
+impl std::str::FromStr for ErrorPolicy {
+  type Err = Error;
+  fn from_str(s: &str) -> Result<Self,Error> {
+    match s {
+      "fail" => Ok(ErrorPolicy::Fail),
+      "skip" => Ok(ErrorPolicy::Skip),
+      "skip-and-log" | "skip_and_log" => Ok(ErrorPolicy::SkipAndLog),
+      x => Err(format!["unknown error policy {}. expected fail, skip or skip-and-log", x].into()),
+    }
+  }
+}
+
+impl std::fmt::Display for ErrorPolicy {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      ErrorPolicy::Fail => write![f, "fail"],
+      ErrorPolicy::Skip => write![f, "skip"],
+      ErrorPolicy::SkipAndLog => write![f, "skip-and-log"],
+    }
+  }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum RejectReason {
+  TagParse,
+  Encode,
+  TooFewRefs,
+  MissingMembers,
+  UnclosedRing,
+  StrayInner,
+}
+
+impl RejectReason {
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      RejectReason::TagParse => "tag-parse",
+      RejectReason::Encode => "encode",
+      RejectReason::TooFewRefs => "too-few-refs",
+      RejectReason::MissingMembers => "missing-members",
+      RejectReason::UnclosedRing => "unclosed-ring",
+      RejectReason::StrayInner => "stray-inner",
+    }
+  }
+}
+
+// an element that was tagged for the map but could not be encoded
+#[derive(Debug)]
+pub struct Reject {
+  pub reason: RejectReason,
+  pub source: Option<Error>,
+}
+
+impl Reject {
+  pub fn new(reason: RejectReason) -> Self {
+    Self { reason, source: None }
+  }
+  pub fn from_error(reason: RejectReason, source: Error) -> Self {
+    Self { reason, source: Some(source) }
+  }
+}
+
+impl std::fmt::Display for Reject {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match &self.source {
+      Some(source) => write![f, "{}: {}", self.reason.as_str(), source],
+      None => write![f, "{}", self.reason.as_str()],
+    }
+  }
+}
+
+impl std::error::Error for Reject {}
+
+// applies the error policy to rejected elements and writes them to the
+// rejects file as ndjson
+#[derive(Clone)]
+pub struct Rejects {
+  policy: ErrorPolicy,
+  label: &'static str,
+  progress: Arc<RwLock<Progress>>,
+  file: Option<Arc<Mutex<std::io::BufWriter<std::fs::File>>>>,
+}
+
+impl Rejects {
+  // the file is cut to len first, so a resumed ingest drops the records that were
+  // written after its checkpoint instead of writing them twice
+  pub fn open(
+    policy: ErrorPolicy, o_file: Option<&std::path::Path>, len: u64,
+    label: &'static str, progress: Arc<RwLock<Progress>>,
+  ) -> Result<Self,IngestError> {
+    let file = match o_file {
+      Some(file) => {
+        let io_error = |source| IngestError::Io { file: file.display().to_string(), source };
+        let mut h = std::fs::OpenOptions::new()
+          .create(true).write(true)
+          .open(file)
+          .map_err(io_error)?;
+        h.set_len(len).map_err(io_error)?;
+        h.seek(std::io::SeekFrom::End(0)).map_err(io_error)?;
+        Some(Arc::new(Mutex::new(std::io::BufWriter::new(h))))
+      },
+      None => None,
+    };
+    Ok(Self { policy, label, progress, file })
+  }
+
+  pub async fn push(
+    &self, element_type: &'static str, id: i64, tags: &[(&str,&str)], reject: Reject,
+  ) -> Result<(),IngestError> {
+    match self.policy {
+      ErrorPolicy::Fail => Err(IngestError::Element {
+        stage: element_type,
+        id,
+        source: Box::new(reject),
+      }),
+      ErrorPolicy::Skip => Ok(()),
+      ErrorPolicy::SkipAndLog => {
+        let err: Error = format!["{} {}: {}", element_type, id, reject].into();
+        self.progress.write().await.push_err(self.label, &err);
+        if let Some(file) = &self.file {
+          let record = serde_json::json!({
+            "type": element_type,
+            "id": id,
+            "reason": reject.reason.as_str(),
+            "error": reject.source.as_ref().map(|e| e.to_string()),
+            "tags": tags.iter()
+              .map(|(k,v)| (k.to_string(),serde_json::Value::from(*v)))
+              .collect::<serde_json::Map<_,_>>(),
+          });
+          let mut h = file.lock().await;
+          writeln![h, "{}", record].map_err(|e| IngestError::Write {
+            stage: "rejects",
+            source: e.into(),
+          })?;
+        }
+        Ok(())
+      },
+    }
+  }
+
+  // the length of the rejects file once everything pushed so far is written
+  pub async fn position(&self) -> Result<u64,IngestError> {
+    match &self.file {
+      Some(file) => {
+        let mut h = file.lock().await;
+        h.flush().and_then(|_| h.get_mut().stream_position()).map_err(|e| IngestError::Write {
+          stage: "rejects",
+          source: e.into(),
+        })
+      },
+      None => Ok(0),
+    }
+  }
+
+  pub async fn flush(&self) -> Result<(),IngestError> {
+    if let Some(file) = &self.file {
+      file.lock().await.flush().map_err(|e| IngestError::Write {
+        stage: "rejects",
+        source: e.into(),
+      })?;
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,155 @@
+use hashbrown::HashMap;
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Role { Outer, Inner }
+
+// a closed ring of node ids. the first and last ref are the same.
+// outer rings are counter-clockwise and inner rings are clockwise.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Ring {
+  pub role: Role,
+  pub refs: Vec<i64>,
+}
+
+#[derive(Debug)]
+pub enum RingError {
+  // a member way or the location of one of its nodes is not available
+  MissingWay { id: i64 },
+  MissingNode { id: i64 },
+  // the ring that starts with this way can't be closed with the other member ways
+  Unclosed { id: i64 },
+  // the inner ring that starts with this way isn't inside of any outer ring
+  StrayInner { id: i64 },
+  NoOuter,
+}
+
+impl std::fmt::Display for RingError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      RingError::MissingWay { id } => write![f, "member way {} not found", id],
+      RingError::MissingNode { id } => write![f, "location of node {} not found", id],
+      RingError::Unclosed { id } => write![f, "ring starting at way {} is not closed", id],
+      RingError::StrayInner { id } => {
+        write![f, "inner ring starting at way {} is not inside of any outer ring", id]
+      },
+      RingError::NoOuter => write![f, "no outer ring"],
+    }
+  }
+}
+
+impl std::error::Error for RingError {}
+
+// stitch the member ways of a multipolygon into closed rings.
+// the rings are returned with each outer followed by the inners it contains.
+pub fn assemble(
+  members: &[(i64,Role)], way_deps: &HashMap<i64,Vec<i64>>,
+  node_deps: &HashMap<i64,(f64,f64)>,
+) -> Result<Vec<Ring>,RingError> {
+  let mut outers = vec![];
+  let mut inners = vec![];
+  for role in [Role::Outer,Role::Inner].iter() {
+    let mut segments = vec![];
+    for (id,r) in members.iter() {
+      if r != role { continue }
+      let refs = way_deps.get(id).ok_or(RingError::MissingWay { id: *id })?;
+      if refs.len() >= 2 {
+        segments.push((*id,refs.clone()));
+      }
+    }
+    for (id,refs) in stitch(segments)? {
+      let points = refs.iter()
+        .map(|r| node_deps.get(r).copied().ok_or(RingError::MissingNode { id: *r }))
+        .collect::<Result<Vec<(f64,f64)>,RingError>>()?;
+      let area = signed_area(&points);
+      let ring = match role {
+        Role::Outer if area < 0.0 => (id,rev(refs),rev(points),-area),
+        Role::Inner if area > 0.0 => (id,rev(refs),rev(points),area),
+        _ => (id,refs,points,area.abs()),
+      };
+      match role {
+        Role::Outer => outers.push(ring),
+        Role::Inner => inners.push(ring),
+      }
+    }
+  }
+  if outers.is_empty() { return Err(RingError::NoOuter) }
+
+  // each inner belongs to the smallest outer that contains it
+  let mut holes: Vec<Vec<usize>> = vec![vec![];outers.len()];
+  for (i,(id,refs,points,_)) in inners.iter().enumerate() {
+    let mut o_outer: Option<(usize,f64)> = None;
+    for (j,(_,o_refs,o_points,o_area)) in outers.iter().enumerate() {
+      if o_outer.map(|(_,a)| a <= *o_area).unwrap_or(false) { continue }
+      // rings may share nodes, so test a point of the inner that isn't on the outer
+      let o_p = refs.iter().zip(points.iter())
+        .find(|(r,_)| !o_refs.contains(r))
+        .map(|(_,p)| *p);
+      if let Some(p) = o_p {
+        if contains(o_points, p) {
+          o_outer = Some((j,*o_area));
+        }
+      }
+    }
+    match o_outer {
+      Some((j,_)) => holes[j].push(i),
+      None => return Err(RingError::StrayInner { id: *id }),
+    }
+  }
+
+  let mut rings = vec![];
+  for ((_,refs,_,_),hs) in outers.into_iter().zip(holes.iter()) {
+    rings.push(Ring { role: Role::Outer, refs });
+    for i in hs.iter() {
+      rings.push(Ring { role: Role::Inner, refs: inners[*i].1.clone() });
+    }
+  }
+  Ok(rings)
+}
+
+// join ways end to end, reversing them where needed, until every chain is closed.
+// each ring is returned with the id of the way it starts with.
+fn stitch(mut segments: Vec<(i64,Vec<i64>)>) -> Result<Vec<(i64,Vec<i64>)>,RingError> {
+  let mut rings = vec![];
+  segments.reverse();
+  while let Some((id,mut refs)) = segments.pop() {
+    while refs.first() != refs.last() {
+      let end = *refs.last().unwrap();
+      let i = segments.iter()
+        .position(|(_,s)| s.first() == Some(&end) || s.last() == Some(&end))
+        .ok_or(RingError::Unclosed { id })?;
+      let (_,mut s) = segments.remove(i);
+      if s.first() != Some(&end) { s.reverse() }
+      refs.extend_from_slice(&s[1..]);
+    }
+    // fewer than 3 distinct nodes doesn't enclose anything
+    if refs.len() >= 4 {
+      rings.push((id,refs));
+    }
+  }
+  Ok(rings)
+}
+
+fn rev<T>(mut xs: Vec<T>) -> Vec<T> {
+  xs.reverse();
+  xs
+}
+
+// positive for counter-clockwise rings
+pub fn signed_area(points: &[(f64,f64)]) -> f64 {
+  let mut sum = 0.0;
+  for (a,b) in points.iter().zip(points.iter().skip(1)) {
+    sum += a.0 * b.1 - b.0 * a.1;
+  }
+  sum * 0.5
+}
+
+// even-odd test against a closed ring
+pub fn contains(points: &[(f64,f64)], p: (f64,f64)) -> bool {
+  let mut inside = false;
+  for (a,b) in points.iter().zip(points.iter().skip(1)) {
+    if (a.1 > p.1) != (b.1 > p.1) && p.0 < (b.0 - a.0) * (p.1 - a.1) / (b.1 - a.1) + a.0 {
+      inside = !inside;
+    }
+  }
+  inside
+}
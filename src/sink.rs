@@ -0,0 +1,76 @@
+use crate::{Error,DB,Row};
+use eyros::RA;
+use std::sync::{Arc,Mutex};
+
+// where ingest writes its rows. the writer calls write with batches of rows as they
+// are encoded, flush when what was written so far has to be durable because a
+// checkpoint is about to be saved, and finish once after the last batch.
+// the writer runs on the ingest task, so sinks don't need to be Send.
+#[async_trait::async_trait(?Send)]
+pub trait Sink {
+  async fn write(&mut self, rows: Vec<Row>) -> Result<(),Error>;
+  async fn flush(&mut self) -> Result<(),Error>;
+  async fn finish(&mut self) -> Result<(),Error> {
+    self.flush().await
+  }
+}
+
+// rows are inserted into the eyros db and flush syncs it
+#[async_trait::async_trait(?Send)]
+impl<S: RA> Sink for DB<S> {
+  async fn write(&mut self, rows: Vec<Row>) -> Result<(),Error> {
+    let batch = rows.into_iter()
+      .map(|(point,encoded)| eyros::Row::Insert(point, encoded))
+      .collect::<Vec<_>>();
+    self.batch(&batch).await?;
+    Ok(())
+  }
+  async fn flush(&mut self) -> Result<(),Error> {
+    self.sync().await?;
+    Ok(())
+  }
+}
+
+// keeps every row in memory, for tests and for callers that post-process the rows.
+// clones share the rows, so a clone can be read once ingest is done with the sink.
+#[derive(Debug,Clone,Default)]
+pub struct VecSink {
+  state: Arc<Mutex<VecSinkState>>,
+}
+
+#[derive(Debug,Clone,Default)]
+struct VecSinkState {
+  rows: Vec<Row>,
+  flushes: usize,
+  finished: bool,
+}
+
+impl VecSink {
+  pub fn rows(&self) -> Vec<Row> {
+    self.state.lock().unwrap().rows.clone()
+  }
+  pub fn flushes(&self) -> usize {
+    self.state.lock().unwrap().flushes
+  }
+  pub fn is_finished(&self) -> bool {
+    self.state.lock().unwrap().finished
+  }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Sink for VecSink {
+  async fn write(&mut self, rows: Vec<Row>) -> Result<(),Error> {
+    self.state.lock().unwrap().rows.extend(rows);
+    Ok(())
+  }
+  async fn flush(&mut self) -> Result<(),Error> {
+    self.state.lock().unwrap().flushes += 1;
+    Ok(())
+  }
+  async fn finish(&mut self) -> Result<(),Error> {
+    let mut state = self.state.lock().unwrap();
+    state.flushes += 1;
+    state.finished = true;
+    Ok(())
+  }
+}
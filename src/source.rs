@@ -0,0 +1,130 @@
+use std::io::{Read,Seek,Write};
+use std::sync::Arc;
+
+pub type SourceReader = Box<dyn Read+Seek+Send>;
+
+// where pbf data comes from. scan and ingest read blobs from several threads at once,
+// so a source hands out as many independent readers as they ask for.
+pub trait Source: Send+Sync {
+  // a new reader, with its own position
+  fn open(&self) -> Result<SourceReader,std::io::Error>;
+  // size of the pbf in bytes
+  fn size(&self) -> Result<u64,std::io::Error>;
+  // shown in errors
+  fn name(&self) -> String;
+}
+
+// a pbf file on disk, opened again for every reader
+#[derive(Debug,Clone)]
+pub struct FileSource {
+  pub path: std::path::PathBuf,
+}
+
+impl FileSource {
+  pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+    Self { path: path.into() }
+  }
+}
+
+impl Source for FileSource {
+  fn open(&self) -> Result<SourceReader,std::io::Error> {
+    Ok(Box::new(std::fs::File::open(&self.path)?))
+  }
+  fn size(&self) -> Result<u64,std::io::Error> {
+    Ok(std::fs::metadata(&self.path)?.len())
+  }
+  fn name(&self) -> String {
+    self.path.to_string_lossy().to_string()
+  }
+}
+
+// a pbf that is already in memory. readers share the bytes without copying them.
+#[derive(Debug,Clone)]
+pub struct BufferSource {
+  name: String,
+  data: Arc<[u8]>,
+}
+
+impl BufferSource {
+  pub fn new(name: &str, data: impl Into<Arc<[u8]>>) -> Self {
+    Self { name: name.to_string(), data: data.into() }
+  }
+}
+
+impl Source for BufferSource {
+  fn open(&self) -> Result<SourceReader,std::io::Error> {
+    Ok(Box::new(std::io::Cursor::new(self.data.clone())))
+  }
+  fn size(&self) -> Result<u64,std::io::Error> {
+    Ok(self.data.len() as u64)
+  }
+  fn name(&self) -> String {
+    self.name.clone()
+  }
+}
+
+// a pbf read once from a stream like stdin into a temp file, since scan and ingest
+// seek through it many times. the file is removed when the source is dropped.
+pub struct SpoolSource {
+  name: String,
+  file: FileSource,
+  _spool: tempfile::TempPath,
+}
+
+impl SpoolSource {
+  pub fn new<R: Read>(
+    name: &str, mut reader: R, tmpdir: &std::path::Path
+  ) -> Result<Self,std::io::Error> {
+    let mut file = tempfile::Builder::new()
+      .prefix("peermaps-ingest-")
+      .suffix(".pbf")
+      .tempfile_in(tmpdir)?;
+    std::io::copy(&mut reader, &mut file)?;
+    file.flush()?;
+    let spool = file.into_temp_path();
+    let file = FileSource::new(spool.to_path_buf());
+    Ok(Self { name: name.to_string(), file, _spool: spool })
+  }
+  // where the temp file is, for code that takes a path
+  pub fn path(&self) -> &std::path::Path {
+    &self.file.path
+  }
+}
+
+impl Source for SpoolSource {
+  fn open(&self) -> Result<SourceReader,std::io::Error> {
+    self.file.open()
+  }
+  fn size(&self) -> Result<u64,std::io::Error> {
+    self.file.size()
+  }
+  fn name(&self) -> String {
+    self.name.clone()
+  }
+}
+
+// a source from a function that opens readers, for object stores, decrypted streams
+// and other readers that this crate doesn't know about
+pub struct FnSource<F> {
+  name: String,
+  size: u64,
+  open: F,
+}
+
+impl<F> FnSource<F> where F: Fn() -> Result<SourceReader,std::io::Error>+Send+Sync {
+  pub fn new(name: &str, size: u64, open: F) -> Self {
+    Self { name: name.to_string(), size, open }
+  }
+}
+
+impl<F> Source for FnSource<F> where F: Fn() -> Result<SourceReader,std::io::Error>+Send+Sync {
+  fn open(&self) -> Result<SourceReader,std::io::Error> {
+    (self.open)()
+  }
+  fn size(&self) -> Result<u64,std::io::Error> {
+    Ok(self.size)
+  }
+  fn name(&self) -> String {
+    self.name.clone()
+  }
+}
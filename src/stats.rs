@@ -0,0 +1,200 @@
+use crate::{Error,P,V,DB,BBox,Progress,encode::element_id};
+use georender_pack::Feature;
+use std::collections::{BinaryHeap,BTreeMap,HashMap};
+use std::cmp::Reverse;
+use async_std::{sync::{Arc,RwLock},prelude::*};
+
+#[derive(Debug,Clone,Copy,Default,PartialEq)]
+pub struct Tally {
+  pub count: u64,
+  pub bytes: u64,
+}
+
+impl Tally {
+  fn add(&mut self, bytes: usize) {
+    self.count += 1;
+    self.bytes += bytes as u64;
+  }
+  fn to_json(self) -> serde_json::Value {
+    serde_json::json!({ "count": self.count, "bytes": self.bytes })
+  }
+}
+
+// one of the largest rows of a db
+#[derive(Debug,Clone,PartialEq,Eq,PartialOrd,Ord)]
+pub struct Large {
+  pub bytes: usize,
+  pub encoded_id: u64,
+  pub feature_type: u64,
+  pub kind: &'static str,
+}
+
+// counts and payload sizes of the rows of a db, to find out what takes up the space
+#[derive(Debug,Clone,Default)]
+pub struct Stats {
+  pub total: Tally,
+  // point, line and area, and unknown for rows that georender-pack can't decode
+  pub kinds: BTreeMap<&'static str,Tally>,
+  pub feature_types: BTreeMap<u64,Tally>,
+  pub bbox: Option<BBox>,
+  // largest rows first
+  pub largest: Vec<Large>,
+  top: usize,
+  heap: BinaryHeap<Reverse<Large>>,
+}
+
+impl Stats {
+  // keep the top largest rows
+  pub fn new(top: usize) -> Self {
+    Self { top, ..Self::default() }
+  }
+
+  pub fn add(&mut self, point: &P, value: &V) {
+    let bytes = value.len();
+    self.total.add(bytes);
+    let (kind,id,feature_type) = match georender_pack::decode(&value.data) {
+      Ok(Feature::Point(x)) => ("point", x.id, x.feature_type),
+      Ok(Feature::Line(x)) => ("line", x.id, x.feature_type),
+      Ok(Feature::Area(x)) => ("area", x.id, x.feature_type),
+      Err(_) => {
+        self.kinds.entry("unknown").or_default().add(bytes);
+        return;
+      },
+    };
+    self.kinds.entry(kind).or_default().add(bytes);
+    self.feature_types.entry(feature_type).or_default().add(bytes);
+    let ((west,east),(south,north)) = (span(&point.0), span(&point.1));
+    self.bbox = Some(match self.bbox {
+      Some(b) => BBox {
+        west: b.west.min(west), south: b.south.min(south),
+        east: b.east.max(east), north: b.north.max(north),
+      },
+      None => BBox { west, south, east, north },
+    });
+    if self.top == 0 { return }
+    self.heap.push(Reverse(Large { bytes, encoded_id: id, feature_type, kind }));
+    if self.heap.len() > self.top { self.heap.pop(); }
+  }
+
+  // sort the largest rows once every row has been added
+  pub fn finish(&mut self) {
+    let mut largest = std::mem::take(&mut self.heap).into_iter()
+      .map(|Reverse(x)| x)
+      .collect::<Vec<_>>();
+    largest.sort_unstable_by(|a,b| b.cmp(a));
+    self.largest = largest;
+  }
+
+  // feature types from the most bytes to the least
+  fn sorted_types(&self) -> Vec<(u64,Tally)> {
+    let mut types = self.feature_types.iter().map(|(t,x)| (*t,*x)).collect::<Vec<_>>();
+    types.sort_by(|a,b| b.1.bytes.cmp(&a.1.bytes).then(a.0.cmp(&b.0)));
+    types
+  }
+
+  pub fn to_json(&self) -> serde_json::Value {
+    let names = type_names();
+    serde_json::json!({
+      "count": self.total.count,
+      "bytes": self.total.bytes,
+      "bbox": self.bbox.map(|b| vec![b.west,b.south,b.east,b.north]),
+      "kinds": self.kinds.iter()
+        .map(|(k,x)| (k.to_string(),x.to_json()))
+        .collect::<serde_json::Map<String,serde_json::Value>>(),
+      "feature_types": self.sorted_types().iter().map(|(t,x)| serde_json::json!({
+        "id": t,
+        "name": names.get(t),
+        "count": x.count,
+        "bytes": x.bytes,
+      })).collect::<Vec<_>>(),
+      "largest": self.largest.iter().map(|x| {
+        let (element_type,id) = element_id(x.encoded_id);
+        serde_json::json!({
+          "type": element_type,
+          "id": id,
+          "feature_type": names.get(&x.feature_type),
+          "kind": x.kind,
+          "bytes": x.bytes,
+        })
+      }).collect::<Vec<_>>(),
+    })
+  }
+}
+
+impl std::fmt::Display for Stats {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let names = type_names();
+    let name = |t: &u64| names.get(t).cloned().unwrap_or_else(|| t.to_string());
+    writeln![f, "{:<32} {:>12} {:>16}", "", "count", "bytes"]?;
+    writeln![f, "{:<32} {:>12} {:>16}", "total", self.total.count, self.total.bytes]?;
+    if let Some(b) = &self.bbox {
+      writeln![f, "bbox {},{},{},{}", b.west, b.south, b.east, b.north]?;
+    }
+    writeln![f]?;
+    for (kind,x) in self.kinds.iter() {
+      writeln![f, "{:<32} {:>12} {:>16}", kind, x.count, x.bytes]?;
+    }
+    writeln![f]?;
+    for (t,x) in self.sorted_types().iter() {
+      writeln![f, "{:<32} {:>12} {:>16}", name(t), x.count, x.bytes]?;
+    }
+    if !self.largest.is_empty() {
+      writeln![f]?;
+      writeln![f, "largest"]?;
+      for x in self.largest.iter() {
+        let (element_type,id) = element_id(x.encoded_id);
+        writeln![
+          f, "{:<32} {:>12} {:>16}",
+          format!["{} {}", element_type, id],
+          format!["{} {}", x.kind, name(&x.feature_type)],
+          x.bytes,
+        ]?;
+      }
+    }
+    Ok(())
+  }
+}
+
+fn span(c: &eyros::Coord<f32>) -> (f64,f64) {
+  match c {
+    eyros::Coord::Scalar(x) => (*x as f64, *x as f64),
+    eyros::Coord::Interval(x0,x1) => (*x0 as f64, *x1 as f64),
+  }
+}
+
+// names from georender-pack's table by feature type id. ids with several names
+// get the first one in sort order.
+pub fn type_names() -> HashMap<u64,String> {
+  let mut names: HashMap<u64,String> = HashMap::new();
+  for (name,t) in georender_pack::osm_types::get_types().iter() {
+    let name = name.to_string();
+    match names.get(t) {
+      Some(n) if *n <= name => {},
+      _ => { names.insert(*t, name); },
+    }
+  }
+  names
+}
+
+// read every row of the db
+pub async fn collect<S: eyros::RA>(
+  progress: Arc<RwLock<Progress>>, db: &mut DB<S>, top: usize,
+) -> Result<Stats,Error> {
+  progress.write().await.start("stats");
+  let mut stats = Stats::new(top);
+  let mut stream = db.query(&((-180.0,-90.0),(180.0,90.0))).await?;
+  let mut n = 0;
+  while let Some(result) = stream.next().await {
+    let (point,value) = result?;
+    stats.add(&point, &value);
+    n += 1;
+    if n == 10_000 {
+      progress.write().await.add("stats", n);
+      n = 0;
+    }
+  }
+  progress.write().await.add("stats", n);
+  progress.write().await.end("stats");
+  stats.finish();
+  Ok(stats)
+}
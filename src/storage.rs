@@ -0,0 +1,85 @@
+use crate::{Error,DB};
+use async_std::sync::{Arc,Mutex};
+use random_access_memory::RandomAccessMemory;
+use random_access_storage::RandomAccess;
+use std::collections::HashMap;
+
+// a db that lives in memory, for tests and for callers that ingest small extracts
+// and serve them without touching the disk
+pub type MemDB = DB<MemoryFile>;
+
+// files of an eyros db kept in memory. clones share the same files, so a db that was
+// handed to ingest can be opened again from another clone once the ingest is done.
+#[derive(Debug,Clone,Default)]
+pub struct MemoryStorage {
+  files: Arc<Mutex<HashMap<String,MemoryFile>>>,
+}
+
+impl MemoryStorage {
+  pub async fn open_db(&self) -> Result<MemDB,Error> {
+    let db = eyros::Setup::from_storage(Box::new(self.clone())).build().await?;
+    Ok(db)
+  }
+  // names of the files that have been written so far
+  pub async fn files(&self) -> Vec<String> {
+    let mut names = self.files.lock().await.keys().cloned().collect::<Vec<_>>();
+    names.sort();
+    names
+  }
+}
+
+#[async_trait::async_trait]
+impl eyros::Storage<MemoryFile> for MemoryStorage {
+  async fn open(&mut self, name: &str) -> Result<MemoryFile,eyros::Error> {
+    let mut files = self.files.lock().await;
+    Ok(files.entry(name.to_string()).or_insert_with(MemoryFile::default).clone())
+  }
+  async fn remove(&mut self, name: &str) -> Result<(),eyros::Error> {
+    self.files.lock().await.remove(name);
+    Ok(())
+  }
+}
+
+// one file of a MemoryStorage. clones read and write the same bytes.
+#[derive(Debug,Clone)]
+pub struct MemoryFile {
+  ram: Arc<Mutex<RandomAccessMemory>>,
+}
+
+impl Default for MemoryFile {
+  fn default() -> Self {
+    Self { ram: Arc::new(Mutex::new(RandomAccessMemory::default())) }
+  }
+}
+
+#[async_trait::async_trait]
+impl RandomAccess for MemoryFile {
+  type Error = Error;
+  async fn write(&mut self, offset: u64, data: &[u8]) -> Result<(),Self::Error> {
+    self.ram.lock().await.write(offset, data).await.map_err(|e| e.into())
+  }
+  async fn read(&mut self, offset: u64, length: u64) -> Result<Vec<u8>,Self::Error> {
+    self.ram.lock().await.read(offset, length).await.map_err(|e| e.into())
+  }
+  async fn read_to_writer(
+    &mut self, offset: u64, length: u64,
+    buf: &mut (impl async_std::io::Write + Send),
+  ) -> Result<(),Self::Error> {
+    self.ram.lock().await.read_to_writer(offset, length, buf).await.map_err(|e| e.into())
+  }
+  async fn del(&mut self, offset: u64, length: u64) -> Result<(),Self::Error> {
+    self.ram.lock().await.del(offset, length).await.map_err(|e| e.into())
+  }
+  async fn truncate(&mut self, length: u64) -> Result<(),Self::Error> {
+    self.ram.lock().await.truncate(length).await.map_err(|e| e.into())
+  }
+  async fn len(&self) -> Result<u64,Self::Error> {
+    self.ram.lock().await.len().await.map_err(|e| e.into())
+  }
+  async fn is_empty(&mut self) -> Result<bool,Self::Error> {
+    self.ram.lock().await.is_empty().await.map_err(|e| e.into())
+  }
+  async fn sync_all(&mut self) -> Result<(),Self::Error> {
+    self.ram.lock().await.sync_all().await.map_err(|e| e.into())
+  }
+}
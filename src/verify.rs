@@ -0,0 +1,131 @@
+use crate::{Error,DB,Row,Sink,encode::{element_id,is_generated}};
+use hashbrown::HashMap;
+use eyros::{Value,RA};
+use async_std::{sync::Arc,prelude::*};
+
+#[derive(Debug,Clone,Copy,Default,PartialEq)]
+pub struct Counts {
+  pub node: u64,
+  pub way: u64,
+  pub relation: u64,
+}
+
+impl Counts {
+  fn add(&mut self, encoded_id: u64) {
+    match encoded_id % 3 {
+      0 => self.node += 1,
+      1 => self.way += 1,
+      _ => self.relation += 1,
+    }
+  }
+}
+
+// the difference between the elements of a pbf and the rows of the db ingested from it.
+// ids are encoded ids, which element_id turns back into a type and an osm id.
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct Report {
+  // elements of the pbf that ingest would write
+  pub expected: Counts,
+  // distinct elements in the db
+  pub found: Counts,
+  // rows made by ingest that have no element in the pbf, like coastline areas
+  pub generated: u64,
+  pub missing: Vec<u64>,
+  // ids with more rows than ingest writes for them, and how many rows. rows of an
+  // element split at the antimeridian or by clipping aren't counted twice.
+  pub duplicated: Vec<(u64,usize)>,
+  pub unexpected: Vec<u64>,
+}
+
+impl Report {
+  pub fn is_ok(&self) -> bool {
+    self.missing.is_empty() && self.duplicated.is_empty() && self.unexpected.is_empty()
+  }
+}
+
+impl std::fmt::Display for Report {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    writeln![f, "node: {} expected, {} found", self.expected.node, self.found.node]?;
+    writeln![f, "way: {} expected, {} found", self.expected.way, self.found.way]?;
+    writeln![f, "relation: {} expected, {} found", self.expected.relation, self.found.relation]?;
+    if self.generated > 0 {
+      writeln![f, "generated: {}", self.generated]?;
+    }
+    for id in self.missing.iter() {
+      let (t,id) = element_id(*id);
+      writeln![f, "missing {} {}", t, id]?;
+    }
+    for (id,n) in self.duplicated.iter() {
+      let (t,id) = element_id(*id);
+      writeln![f, "duplicated {} {} ({} rows)", t, id, n]?;
+    }
+    for id in self.unexpected.iter() {
+      let (t,id) = element_id(*id);
+      writeln![f, "unexpected {} {}", t, id]?;
+    }
+    if self.is_ok() {
+      writeln![f, "ok"]
+    } else {
+      writeln![f, "{} missing, {} duplicated, {} unexpected",
+        self.missing.len(), self.duplicated.len(), self.unexpected.len()]
+    }
+  }
+}
+
+// counts the rows that ingest writes for each id, without keeping the rows
+#[derive(Clone,Default)]
+pub struct IdSink {
+  counts: Arc<std::sync::Mutex<HashMap<u64,u32>>>,
+}
+
+impl IdSink {
+  pub fn take_counts(&self) -> HashMap<u64,u32> {
+    std::mem::take(&mut *self.counts.lock().unwrap())
+  }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Sink for IdSink {
+  async fn write(&mut self, rows: Vec<Row>) -> Result<(),Error> {
+    let mut counts = self.counts.lock().unwrap();
+    for (_,v) in rows.iter() {
+      *counts.entry(v.get_id()).or_insert(0) += 1;
+    }
+    Ok(())
+  }
+  async fn flush(&mut self) -> Result<(),Error> {
+    Ok(())
+  }
+}
+
+// query the whole db and compare its rows with the expected rows per id.
+// this holds two counts for every id in memory, so a planet needs several GB.
+pub async fn compare<S: RA>(
+  db: &mut DB<S>, expected: HashMap<u64,u32>,
+) -> Result<Report,Error> {
+  // (expected,found) rows for each id
+  let mut counts = expected.into_iter()
+    .map(|(id,n)| (id,(n,0)))
+    .collect::<HashMap<u64,(u32,u32)>>();
+  let mut stream = db.query(&((-180.0,-90.0),(180.0,90.0))).await?;
+  while let Some(result) = stream.next().await {
+    let (_,v) = result?;
+    counts.entry(v.get_id()).or_insert((0,0)).1 += 1;
+  }
+  let mut report = Report::default();
+  for (id,(n,found)) in counts.into_iter() {
+    if is_generated(id) {
+      report.generated += 1;
+      continue;
+    }
+    if n > 0 { report.expected.add(id) }
+    if found > 0 { report.found.add(id) }
+    if n > 0 && found == 0 { report.missing.push(id) }
+    if n == 0 { report.unexpected.push(id) }
+    if n > 0 && found > n { report.duplicated.push((id,found as usize)) }
+  }
+  report.missing.sort_unstable();
+  report.duplicated.sort_unstable();
+  report.unexpected.sort_unstable();
+  Ok(report)
+}
@@ -1,4 +1,12 @@
-use peermaps_ingest::{Ingest,IngestOptions,EDB};
+use peermaps_ingest::{
+  Ingest,IngestOptions,EDB,Changeset,Checkpoint,StageState,ErrorPolicy,
+  Boundary,Filter,FeatureTypes,feature_types,Coastline,coastline,FlatNodes,
+  MemoryBudget,BatchSizes,Duplicates,dedup,MemoryStorage,MemDB,
+  Source,SourceReader,FileSource,BufferSource,FnSource,SpoolSource,IngestError,DB,
+  verify::Counts,element_id,GENERATED_ID,stats,geojson,BBox,VecSink,Sink,Row,Progress,Info,
+};
+use std::sync::Arc;
+use desert::ToBytes;
 use async_std::prelude::*;
 use tempfile::Builder as Tmpfile;
 use eyros::{Coord as C};
@@ -17,13 +25,13 @@ async fn ingest() -> Result<(),Error> {
   let pbf_file = p.to_str().unwrap();
 
   let mut ingest = Ingest::new(&["scan","ingest"]);
-  let scan_table = ingest.scan(&pbf_file).await;
+  let scan_table = ingest.scan(&pbf_file).await?;
   let ingest_options = IngestOptions::default();
 
   ingest.ingest(
     eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
     &pbf_file, scan_table, &ingest_options
-  ).await;
+  ).await?;
   {
     let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
     let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
@@ -51,6 +59,13 @@ async fn ingest() -> Result<(),Error> {
     let ex_positions = vec![ 13.00,37.00, 13.01,37.01, 13.02,37.00 ];
     let ex_cells = earcutr::earcut(&ex_positions.iter()
       .map(|p| *p as f64).collect(), &vec![], 2);
+    // the inner ring of the lake is stored clockwise
+    let lake_positions = vec![
+      5.000, -10.000, 5.000, -10.010, 5.010, -10.010, 5.010, -10.000,
+      5.005, -10.003, 5.007, -10.003, 5.006, -10.004,
+    ];
+    let lake_cells = earcutr::earcut(&lake_positions.iter()
+      .map(|p| *p as f64).collect(), &vec![4], 2);
     assert_eq![
       results,
       vec![
@@ -64,11 +79,8 @@ async fn ingest() -> Result<(),Error> {
         ((C::Interval(5.000,5.010),C::Interval(-10.010,-10.000)), Feature::Area(Area {
           id: 700*3+2,
           feature_type: get_type("natural.water"),
-          positions: vec![
-            5.000, -10.000, 5.000, -10.010, 5.010, -10.010, 5.010, -10.000,
-            5.005, -10.003, 5.006, -10.004, 5.007, -10.003,
-          ],
-          cells: vec![0,1,4,5,4,1,3,0,4,6,5,1,3,4,6,6,1,2,2,3,6],
+          positions: lake_positions,
+          cells: lake_cells,
           labels: "\x0a=cool lake\x00".as_bytes().to_vec(),
         })),
         ((C::Scalar(13.02),C::Scalar(37.00)), Feature::Point(Point {
@@ -402,6 +414,1833 @@ async fn ingest() -> Result<(),Error> {
   Ok(())
 }
 
+#[async_std::test]
+async fn changeset() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest","changeset"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let ingest_options = IngestOptions::default();
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table.clone(), &ingest_options
+  ).await?;
+
+  let mut osc_file = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  osc_file.push("tests/data/0/changeset0.xml");
+  let changeset = Changeset::from_reader(
+    std::io::BufReader::new(std::fs::File::open(&osc_file)?)
+  )?;
+  ingest.changeset(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &changeset, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  results.sort_by_key(|(_,feature)| match feature {
+    Feature::Point(x) => x.id,
+    Feature::Line(x) => x.id,
+    Feature::Area(x) => x.id,
+  });
+  let ex_positions = vec![
+    4.999,  -9.999, 5.000, -10.010, 5.010, -10.010, 5.001, -10.001,
+    5.005, -10.003, 5.007, -10.003, 5.006, -10.004,
+  ];
+  let ex_cells = earcutr::earcut(&ex_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![4], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(4.999,5.010),C::Interval(-10.010,-9.999)), Feature::Area(Area {
+        id: 700*3+2,
+        feature_type: get_type("natural.water"),
+        positions: ex_positions,
+        cells: ex_cells,
+        labels: "\x0a=cool lake\x00".as_bytes().to_vec(),
+      })),
+      ((C::Scalar(13.02),C::Scalar(37.00)), Feature::Point(Point {
+        id: 1312*3+0,
+        feature_type: get_type("amenity.cafe"),
+        point: (13.02,37.00),
+        labels: vec![0],
+      })),
+      ((C::Scalar(13.03),C::Scalar(37.04)), Feature::Point(Point {
+        id: 2000*3+0,
+        feature_type: get_type("highway.bus_stop"),
+        point: (13.03,37.04),
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn ring_assembly() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest","changeset"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table.clone(), &ingest_options
+  ).await?;
+
+  // 5201 has an outer ring split over two ways that point the same direction
+  // and 5202 has an outer ring that doesn't close
+  let mut osc_file = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  osc_file.push("tests/data/0/rings.xml");
+  let changeset = Changeset::from_reader(
+    std::io::BufReader::new(std::fs::File::open(&osc_file)?)
+  )?;
+  let rejects_file = dir.path().join("rejects");
+  ingest_options.rejects_file = Some(rejects_file.clone());
+  ingest.changeset(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &changeset, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((19.0,19.0),(22.0,23.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  // outer ring counter-clockwise, inner ring clockwise
+  let ex_positions = vec![
+    20.0, 20.0, 21.0, 20.0, 21.0, 21.0, 20.0, 21.0,
+    20.2, 20.2, 20.5, 20.8, 20.8, 20.2,
+  ];
+  let ex_cells = earcutr::earcut(&ex_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![4], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(20.0,21.0),C::Interval(20.0,21.0)), Feature::Area(Area {
+        id: 5201*3+2,
+        feature_type: get_type("natural.water"),
+        positions: ex_positions,
+        cells: ex_cells,
+        labels: vec![0],
+      })),
+    ]
+  ];
+
+  let rejects = std::fs::read_to_string(&rejects_file)?;
+  let lines = rejects.lines().collect::<Vec<_>>();
+  assert_eq![lines.len(), 1];
+  assert![lines[0].contains(r#""id":5202"#)];
+  assert![lines[0].contains(r#""reason":"unclosed-ring""#)];
+  Ok(())
+}
+
+#[async_std::test]
+async fn multipolygon() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/multipolygon.pbf");
+  let pbf_file = p.to_str().unwrap();
+  let rejects_file = dir.path().join("rejects");
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.error_policy = ErrorPolicy::SkipAndLog;
+  ingest_options.rejects_file = Some(rejects_file.clone());
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+
+  // the outer ring of 7201 is split over three ways out of order with one reversed
+  let results = sink.rows().iter()
+    .map(|(pt,v)| Ok((pt.clone(),georender_pack::decode(&v.data)?)))
+    .collect::<Result<Vec<_>,Error>>()?;
+  let ex_positions = vec![
+    41.0, 41.0, 40.0, 41.0, 40.0, 40.0, 41.0, 40.0,
+    40.2, 40.2, 40.5, 40.8, 40.8, 40.2,
+  ];
+  let ex_cells = earcutr::earcut(&ex_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![4], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(40.0,41.0),C::Interval(40.0,41.0)), Feature::Area(Area {
+        id: 7201*3+2,
+        feature_type: get_type("natural.water"),
+        positions: ex_positions,
+        cells: ex_cells,
+        labels: vec![0],
+      })),
+    ]
+  ];
+
+  // the inner ring of 7202 is outside of its outer ring
+  let records = std::fs::read_to_string(&rejects_file)?.lines()
+    .map(|line| serde_json::from_str(line))
+    .collect::<Result<Vec<serde_json::Value>,_>>()?;
+  assert_eq![
+    records,
+    vec![
+      serde_json::json!({
+        "type": "relation",
+        "id": 7202,
+        "reason": "stray-inner",
+        "error": "inner ring starting at way 7106 is not inside of any outer ring",
+        "tags": { "type": "multipolygon", "natural": "water" },
+      }),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn rejects() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/rejects.pbf");
+  let pbf_file = p.to_str().unwrap();
+  let rejects_file = dir.path().join("rejects");
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.error_policy = ErrorPolicy::SkipAndLog;
+  ingest_options.rejects_file = Some(rejects_file.clone());
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table.clone(), &ingest_options).await?;
+  // only the cafe can be encoded
+  assert_eq![sink.rows().len(), 1];
+  let records = std::fs::read_to_string(&rejects_file)?.lines()
+    .map(|line| serde_json::from_str(line))
+    .collect::<Result<Vec<serde_json::Value>,_>>()?;
+  assert_eq![
+    records,
+    vec![
+      serde_json::json!({
+        "type": "way",
+        "id": 9810,
+        "reason": "too-few-refs",
+        "error": null,
+        "tags": { "highway": "residential", "name": "nowhere" },
+      }),
+      serde_json::json!({
+        "type": "relation",
+        "id": 9820,
+        "reason": "missing-members",
+        "error": null,
+        "tags": { "type": "route", "route": "bus" },
+      }),
+    ]
+  ];
+
+  // the way is rejected after the node checkpoint, and its flush fails. resuming
+  // from the node checkpoint drops its record so it is only written once.
+  let checkpoint_file = dir.path().join("checkpoint");
+  ingest_options.checkpoint_file = Some(checkpoint_file.clone());
+  let crashed = CrashSink::new(vec![], Some(1));
+  match ingest.ingest(crashed.clone(), &pbf_file, scan_table.clone(), &ingest_options).await {
+    Err(IngestError::Write { .. }) => {},
+    r => panic!["expected a write error, got {:?}", r],
+  }
+  let checkpoint = Checkpoint::load(&checkpoint_file)?;
+  assert_eq![checkpoint.way, StageState::Pending];
+  assert_eq![checkpoint.rejects, 0];
+  assert![std::fs::read_to_string(&rejects_file)?.lines().count() >= 1];
+  ingest_options.resume = true;
+  let resumed = CrashSink::new(crashed.rows(), None);
+  ingest.ingest(resumed.clone(), &pbf_file, scan_table.clone(), &ingest_options).await?;
+  let resumed_records = std::fs::read_to_string(&rejects_file)?.lines()
+    .map(|line| serde_json::from_str(line))
+    .collect::<Result<Vec<serde_json::Value>,_>>()?;
+  assert_eq![resumed_records, records];
+  assert_eq![Checkpoint::load(&checkpoint_file)?.rejects, std::fs::metadata(&rejects_file)?.len()];
+  ingest_options.resume = false;
+  ingest_options.checkpoint_file = None;
+
+  // the first element that can't be encoded stops the ingest
+  ingest_options.error_policy = ErrorPolicy::Fail;
+  ingest_options.rejects_file = None;
+  let r = ingest.ingest(VecSink::default(), &pbf_file, scan_table, &ingest_options).await;
+  match r {
+    Err(IngestError::Element { stage, id, .. }) => assert_eq![(stage,id), ("way",9810)],
+    r => panic!["expected an element error, got {:?}", r],
+  }
+  Ok(())
+}
+
+#[async_std::test]
+async fn route() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest","changeset"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let ingest_options = IngestOptions::default();
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table.clone(), &ingest_options
+  ).await?;
+
+  // the first way of the route points backwards and the platform is left out
+  let mut osc_file = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  osc_file.push("tests/data/0/route.xml");
+  let changeset = Changeset::from_reader(
+    std::io::BufReader::new(std::fs::File::open(&osc_file)?)
+  )?;
+  ingest.changeset(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &changeset, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((29.0,29.0),(32.0,32.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(30.0,31.0),C::Interval(30.0,30.5)), Feature::Line(Line {
+        id: 6201*3+2,
+        feature_type: get_type("route.bus"),
+        positions: vec![ 30.0, 30.0, 30.5, 30.5, 31.0, 30.0 ],
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn route_gap() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/route_gap.pbf");
+  let pbf_file = p.to_str().unwrap();
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let rejects_file = dir.path().join("rejects");
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.error_policy = ErrorPolicy::SkipAndLog;
+  ingest_options.rejects_file = Some(rejects_file.clone());
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+
+  // the third way doesn't join the first two, so it becomes a separate line.
+  // the last piece of 8201 can't be drawn and is left out, but 8202 has nothing else.
+  let records = std::fs::read_to_string(&rejects_file)?.lines()
+    .map(|line| serde_json::from_str(line))
+    .collect::<Result<Vec<serde_json::Value>,_>>()?;
+  assert_eq![records.len(), 1];
+  assert_eq![records[0]["id"], 8202];
+  assert_eq![records[0]["reason"], "too-few-refs"];
+  let results = sink.rows().iter()
+    .map(|(pt,v)| Ok((pt.clone(),georender_pack::decode(&v.data)?)))
+    .collect::<Result<Vec<_>,Error>>()?;
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(50.0,51.0),C::Interval(50.0,50.5)), Feature::Line(Line {
+        id: 8201*3+2,
+        feature_type: get_type("route.bus"),
+        positions: vec![ 50.0, 50.0, 50.5, 50.5, 51.0, 50.0 ],
+        labels: vec![0],
+      })),
+      ((C::Interval(52.0,52.5),C::Interval(50.0,50.5)), Feature::Line(Line {
+        id: 8201*3+2,
+        feature_type: get_type("route.bus"),
+        positions: vec![ 52.0, 50.0, 52.5, 50.5 ],
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn nested_relations() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/nested.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest","changeset"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.error_policy = ErrorPolicy::Fail;
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table.clone(), &ingest_options).await?;
+  // 11210 is a super-relation of two routes, 11220 is in a cycle with 11221
+  // and the way of 11230 inside of 11231 and 11232 is two levels down
+  assert_eq![
+    lines(&sink.rows()),
+    vec![
+      (11210, vec![ 60.0, 60.0, 60.5, 60.5, 61.0, 60.0 ]),
+      (11220, vec![ 62.0, 60.0, 62.5, 60.5 ]),
+      (11230, vec![ 63.0, 60.0, 63.5, 60.5 ]),
+      (11230, vec![ 64.0, 60.0, 64.5, 60.5 ]),
+    ]
+  ];
+
+  ingest_options.relation_depth = 1;
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table.clone(), &ingest_options).await?;
+  assert_eq![
+    lines(&sink.rows()),
+    vec![
+      (11210, vec![ 60.0, 60.0, 60.5, 60.5, 61.0, 60.0 ]),
+      (11220, vec![ 62.0, 60.0, 62.5, 60.5 ]),
+      (11230, vec![ 63.0, 60.0, 63.5, 60.5 ]),
+    ]
+  ];
+
+  // moving a node of a way in a sub-relation re-encodes the super-relation
+  ingest_options.relation_depth = 8;
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table.clone(), &ingest_options
+  ).await?;
+  let mut osc_file = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  osc_file.push("tests/data/0/nested_change.xml");
+  let changeset = Changeset::from_reader(
+    std::io::BufReader::new(std::fs::File::open(&osc_file)?)
+  )?;
+  ingest.changeset(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &changeset, &ingest_options
+  ).await?;
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((59.0,59.0),(61.5,61.5))).await?;
+  let mut rows = vec![];
+  while let Some(result) = stream.next().await {
+    rows.push(result?);
+  }
+  assert_eq![
+    lines(&rows),
+    vec![
+      (11210, vec![ 60.0, 60.0, 60.5, 60.5, 61.0, 61.0 ]),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn bbox_clip() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  // cuts the west corner off of the park and leaves out the lake
+  ingest_options.bbox = Some("13.005,36.0,14.0,38.0".parse()?);
+  ingest_options.clip = true;
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  results.sort_by_key(|(_,feature)| match feature {
+    Feature::Point(x) => x.id,
+    Feature::Line(x) => x.id,
+    Feature::Area(x) => x.id,
+  });
+  let ex_positions = vec![ 13.005,37.00, 13.005,37.005, 13.01,37.01, 13.02,37.00 ];
+  let ex_cells = earcutr::earcut(&ex_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(13.005,13.02),C::Interval(37.00,37.01)), Feature::Area(Area {
+        id: 555*3+1,
+        feature_type: get_type("leisure.park"),
+        positions: ex_positions,
+        cells: ex_cells,
+        labels: "\x0e=triangle park\x00".as_bytes().to_vec(),
+      })),
+      ((C::Scalar(13.02),C::Scalar(37.00)), Feature::Point(Point {
+        id: 1312*3+0,
+        feature_type: get_type("amenity.cafe"),
+        point: (13.02,37.00),
+        labels: vec![0],
+      })),
+      ((C::Scalar(13.03),C::Scalar(37.03)), Feature::Point(Point {
+        id: 2000*3+0,
+        feature_type: get_type("amenity.bus_station"),
+        point: (13.03,37.03),
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn bbox_clip_line() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/clip.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.bbox = Some("69.0,69.0,71.0,71.0".parse()?);
+  ingest_options.clip = true;
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+
+  // the road leaves the box and comes back, so each part inside of it is a row
+  let rows = sink.rows().into_iter()
+    .filter(|(_,v)| matches![georender_pack::decode(&v.data), Ok(Feature::Line(_))])
+    .collect::<Vec<_>>();
+  assert_eq![
+    lines(&rows),
+    vec![
+      (12101, vec![ 70.0, 70.0, 71.0, 70.0 ]),
+      (12101, vec![ 71.0, 70.5, 70.0, 70.5 ]),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn boundary_clip() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/clip.pbf");
+  let pbf_file = p.to_str().unwrap();
+  let mut boundary_file = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  boundary_file.push("tests/data/0/clip.geojson");
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.boundary = Some(Boundary::from_file(&boundary_file)?);
+  ingest_options.clip = true;
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+
+  let (line_rows,area_rows): (Vec<Row>,Vec<Row>) = sink.rows().into_iter()
+    .partition(|(_,v)| matches![georender_pack::decode(&v.data), Ok(Feature::Line(_))]);
+  // the road leaves the boundary and comes back, so each part inside of it is a row
+  assert_eq![
+    lines(&line_rows),
+    vec![
+      (12101, vec![ 70.0, 70.0, 71.0, 70.0 ]),
+      (12101, vec![ 71.0, 70.5, 70.0, 70.5 ]),
+    ]
+  ];
+  // the east side of the park is cut off at the edge of the boundary
+  assert_eq![area_rows.len(), 1];
+  let (pt,v) = &area_rows[0];
+  assert_eq![pt, &(C::Interval(70.5,71.0),C::Interval(69.5,69.75))];
+  match georender_pack::decode(&v.data)? {
+    Feature::Area(area) => {
+      assert_eq![area.id, 12102*3+1];
+      let mut points = area.positions.chunks(2)
+        .map(|p| (p[0],p[1]))
+        .collect::<Vec<_>>();
+      points.sort_by(|a,b| a.partial_cmp(b).unwrap());
+      assert_eq![points, vec![(70.5,69.5),(70.5,69.75),(71.0,69.5),(71.0,69.75)]];
+    },
+    feature => panic!["expected an area, got {:?}", feature],
+  }
+  Ok(())
+}
+
+#[async_std::test]
+async fn boundary_geojson() -> Result<(),Error> {
+  let boundary = Boundary::from_geojson(r#"{
+    "type": "FeatureCollection",
+    "features": [{
+      "type": "Feature",
+      "properties": {},
+      "geometry": {
+        "type": "MultiPolygon",
+        "coordinates": [
+          [
+            [[0.0,0.0],[4.0,0.0],[4.0,4.0],[0.0,4.0],[0.0,0.0]],
+            [[1.0,1.0],[1.0,3.0],[3.0,3.0],[3.0,1.0],[1.0,1.0]]
+          ],
+          [
+            [[10.0,10.0],[12.0,10.0],[12.0,12.0],[10.0,12.0],[10.0,10.0]]
+          ]
+        ]
+      }
+    }]
+  }"#)?;
+  assert_eq![boundary.polygons().0.len(), 2];
+  assert_eq![boundary.polygons().0[0].interiors().len(), 1];
+  assert_eq![*boundary.bbox(), BBox { west: 0.0, south: 0.0, east: 12.0, north: 12.0 }];
+  assert![boundary.contains((0.5,0.5))];
+  assert![!boundary.contains((2.0,2.0))]; // in the hole
+  assert![boundary.contains((11.0,11.0))];
+  assert![!boundary.contains((6.0,6.0))];
+
+  let polygon = Boundary::from_geojson(r#"{
+    "type": "Polygon",
+    "coordinates": [[[0.0,0.0],[1.0,0.0],[1.0,1.0],[0.0,0.0]]]
+  }"#)?;
+  assert![polygon.contains((0.75,0.25))];
+  assert![!polygon.contains((0.25,0.75))];
+  assert![Boundary::from_geojson(r#"{"type":"Point","coordinates":[0.0,0.0]}"#).is_err()];
+  assert![Boundary::from_geojson(r#"{"type":"Polygon","coordinates":[]}"#).is_err()];
+  Ok(())
+}
+
+#[async_std::test]
+async fn boundary() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+  let mut poly_file = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  poly_file.push("tests/data/0/boundary.poly");
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  // keeps the park and the cafe. the bus station is in a hole and the lake is outside.
+  ingest_options.boundary = Some(Boundary::from_file(&poly_file)?);
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  results.sort_by_key(|(_,feature)| match feature {
+    Feature::Point(x) => x.id,
+    Feature::Line(x) => x.id,
+    Feature::Area(x) => x.id,
+  });
+  let ex_positions = vec![ 13.00,37.00, 13.01,37.01, 13.02,37.00 ];
+  let ex_cells = earcutr::earcut(&ex_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(13.00,13.02),C::Interval(37.00,37.01)), Feature::Area(Area {
+        id: 555*3+1,
+        feature_type: get_type("leisure.park"),
+        positions: ex_positions,
+        cells: ex_cells,
+        labels: "\x0e=triangle park\x00".as_bytes().to_vec(),
+      })),
+      ((C::Scalar(13.02),C::Scalar(37.00)), Feature::Point(Point {
+        id: 1312*3+0,
+        feature_type: get_type("amenity.cafe"),
+        point: (13.02,37.00),
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn filter() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  // drops the bus station and the park but keeps the lake relation
+  ingest_options.node_filter = Some("amenity=* and not amenity=bus_station|parking".parse()?);
+  ingest_options.way_filter = Some("not (leisure=park or disused:*)".parse()?);
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  results.sort_by_key(|(_,feature)| match feature {
+    Feature::Point(x) => x.id,
+    Feature::Line(x) => x.id,
+    Feature::Area(x) => x.id,
+  });
+  let lake_positions = vec![
+    5.000, -10.000, 5.000, -10.010, 5.010, -10.010, 5.010, -10.000,
+    5.005, -10.003, 5.007, -10.003, 5.006, -10.004,
+  ];
+  let lake_cells = earcutr::earcut(&lake_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![4], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(5.000,5.010),C::Interval(-10.010,-10.000)), Feature::Area(Area {
+        id: 700*3+2,
+        feature_type: get_type("natural.water"),
+        positions: lake_positions,
+        cells: lake_cells,
+        labels: "\x0a=cool lake\x00".as_bytes().to_vec(),
+      })),
+      ((C::Scalar(13.02),C::Scalar(37.00)), Feature::Point(Point {
+        id: 1312*3+0,
+        feature_type: get_type("amenity.cafe"),
+        point: (13.02,37.00),
+        labels: vec![0],
+      })),
+    ]
+  ];
+
+  let filter: Filter = r#"highway=primary|secondary and name!="main st""#.parse()?;
+  assert![filter.matches(&[("highway","primary"),("name","x")])];
+  assert![!filter.matches(&[("highway","primary"),("name","main st")])];
+  assert![!filter.matches(&[("highway","tertiary")])];
+  assert!["highway=".parse::<Filter>().is_err()];
+  assert!["(highway".parse::<Filter>().is_err()];
+  Ok(())
+}
+
+#[async_std::test]
+async fn feature_types() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  // only the cafe matches a rule and it is drawn as a restaurant
+  let feature_types = FeatureTypes::from_json(r#"{
+    "mode": "replace",
+    "rules": [
+      { "tags": "amenity=*", "type": "amenity.cafe" },
+      { "tags": "amenity=cafe", "type": "amenity.restaurant", "priority": 1 },
+      { "tags": "amenity=bus_station", "type": "place.other", "priority": 1 }
+    ]
+  }"#)?;
+  let mut ingest = Ingest::from_feature_types(&["scan","ingest"], feature_types);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let ingest_options = IngestOptions::default();
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((12.0,36.0),(14.0,38.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  assert_eq![
+    results,
+    vec![
+      ((C::Scalar(13.02),C::Scalar(37.00)), Feature::Point(Point {
+        id: 1312*3+0,
+        feature_type: get_type("amenity.restaurant"),
+        point: (13.02,37.00),
+        labels: vec![0],
+      })),
+    ]
+  ];
+
+  let r = FeatureTypes::from_json(r#"{ "rules": [ { "tags": "a=b", "type": "not.a_type" } ] }"#);
+  assert![r.unwrap_err().to_string().contains("not.a_type")];
+  // numeric ids must be in georender-pack's table too
+  let park = get_type("leisure.park");
+  let r = FeatureTypes::from_json(&format![
+    r#"{{ "rules": [ {{ "tags": "a=b", "type": {} }} ] }}"#, park
+  ]);
+  assert_eq![r?.rules[0].feature_type, park];
+  let r = FeatureTypes::from_json(r#"{ "rules": [ { "tags": "a=b", "type": 99999999 } ] }"#);
+  assert![r.unwrap_err().to_string().contains("99999999")];
+
+  // the same check for single types like --coastline_type
+  assert_eq![feature_types::parse_type("leisure.park")?, park];
+  assert_eq![feature_types::parse_type(&park.to_string())?, park];
+  assert![feature_types::parse_type("99999999").unwrap_err().to_string().contains("99999999")];
+  assert![feature_types::parse_type("leisure.nope").is_err()];
+  Ok(())
+}
+
+#[async_std::test]
+async fn coastline_water() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/coastline.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.bbox = Some("0,0,10,10".parse()?);
+  ingest_options.coastline = Some(Coastline::new(coastline::Fill::Water));
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((-1.0,-1.0),(11.0,11.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    let feature = georender_pack::decode(&v.data)?;
+    if let Feature::Area(area) = &feature {
+      if area.id == GENERATED_ID { results.push((pt,feature)) }
+    }
+  }
+  // the coastline runs east with the land to the north. the island is a hole in the water.
+  let ex_positions = vec![
+    10.0, 5.0, 5.0, 5.0, 0.0, 5.0, 0.0, 0.0, 10.0, 0.0,
+    2.0, 1.0, 2.0, 2.0, 3.0, 2.0, 3.0, 1.0,
+  ];
+  let ex_cells = earcutr::earcut(&ex_positions.iter()
+    .map(|p| *p as f64).collect(), &vec![5], 2);
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(0.0,10.0),C::Interval(0.0,5.0)), Feature::Area(Area {
+        id: GENERATED_ID,
+        feature_type: get_type("natural.water"),
+        positions: ex_positions,
+        cells: ex_cells,
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn coastline_land() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/coastline_land.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  // without a bbox the coastline is closed against the bounds of every node
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.coastline = Some(Coastline::new(coastline::Fill::Land));
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+
+  let mut ids = vec![];
+  let mut results = vec![];
+  for (pt,v) in sink.rows().iter() {
+    match georender_pack::decode(&v.data)? {
+      Feature::Area(area) => {
+        ids.push(element_id(area.id));
+        results.push((pt.clone(), area.feature_type, area.positions, area.cells));
+      },
+      feature => panic!["expected an area, got {:?}", feature],
+    }
+  }
+  ids.sort();
+  assert_eq![ids, vec![("generated",0),("generated",1)]];
+  results.sort_by(|a,b| a.2.partial_cmp(&b.2).unwrap());
+  // the land is north of the coastline, which runs east. the island is land too.
+  let land_positions = vec![ 0.0, 5.0, 5.0, 5.0, 10.0, 5.0, 10.0, 10.0, 0.0, 10.0 ];
+  let island_positions = vec![ 2.0, 1.0, 3.0, 1.0, 3.0, 2.0, 2.0, 2.0 ];
+  let cells = |positions: &[f32]| earcutr::earcut(&positions.iter()
+    .map(|p| *p as f64).collect(), &vec![], 2);
+  assert_eq![
+    results,
+    vec![
+      (
+        (C::Interval(0.0,10.0),C::Interval(5.0,10.0)), get_type("place.island"),
+        land_positions.clone(), cells(&land_positions),
+      ),
+      (
+        (C::Interval(2.0,3.0),C::Interval(1.0,2.0)), get_type("place.island"),
+        island_positions.clone(), cells(&island_positions),
+      ),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn antimeridian() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/antimeridian.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let ingest_options = IngestOptions::default();
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((-180.0,-90.0),(180.0,90.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    let id = match georender_pack::decode(&v.data)? {
+      Feature::Point(x) => x.id,
+      Feature::Line(x) => x.id,
+      Feature::Area(x) => x.id,
+    };
+    results.push((pt,id));
+  }
+  results.sort_by(|a,b| {
+    let west = |pt: &(C<f32>,C<f32>)| match pt.0 {
+      C::Interval(x,_) => x,
+      C::Scalar(x) => x,
+    };
+    a.1.cmp(&b.1).then(west(&a.0).partial_cmp(&west(&b.0)).unwrap())
+  });
+  // each side of the antimeridian gets its own row with the same id
+  assert_eq![
+    results,
+    vec![
+      ((C::Interval(-180.0,-179.0),C::Interval(10.0,10.0)), 9501*3+1),
+      ((C::Interval(179.0,180.0),C::Interval(10.0,10.0)), 9501*3+1),
+      ((C::Interval(-180.0,-179.0),C::Interval(-11.0,-10.0)), 9502*3+1),
+      ((C::Interval(179.0,180.0),C::Interval(-11.0,-10.0)), 9502*3+1),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn flat_nodes() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+  let flat_nodes_file = dir.path().join("nodes.flat");
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+
+  // without the node stage the locations have to be there already
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.flat_nodes_file = Some(flat_nodes_file.clone());
+  ingest_options.ingest_node = false;
+  match ingest.ingest(VecSink::default(), &pbf_file, scan_table.clone(), &ingest_options).await {
+    Err(IngestError::FlatNodes { file, .. }) => assert_eq![file, flat_nodes_file],
+    r => panic!["expected a flat nodes error, got {:?}", r],
+  }
+
+  let mut results = vec![];
+  for o_file in [None,Some(flat_nodes_file.clone())].iter() {
+    let edb_dir = dir.path().join(if o_file.is_some() { "edb-flat" } else { "edb" });
+    let mut ingest_options = IngestOptions::default();
+    ingest_options.flat_nodes_file = o_file.clone();
+    ingest.ingest(
+      eyros::open_from_path2(&edb_dir).await?,
+      &pbf_file, scan_table.clone(), &ingest_options
+    ).await?;
+    let mut db: EDB = eyros::open_from_path2(&edb_dir).await?;
+    let mut stream = db.query(&((-180.0,-90.0),(180.0,90.0))).await?;
+    let mut features = vec![];
+    while let Some(result) = stream.next().await {
+      let (pt,v) = result?;
+      features.push(format!["{:?}", (pt,georender_pack::decode(&v.data)?)]);
+    }
+    features.sort();
+    results.push(features);
+  }
+  assert_eq![results[0].len(), 4];
+  assert_eq![results[0], results[1]];
+
+  let flat_nodes = FlatNodes::open(&flat_nodes_file)?;
+  let (lon,lat) = flat_nodes.get(1312).unwrap();
+  assert![(lon - 13.02).abs() < 1e-6 && (lat - 37.00).abs() < 1e-6];
+  assert_eq![flat_nodes.get(1_000_000), None];
+
+  // once they are, ways can be encoded from the file alone
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.flat_nodes_file = Some(flat_nodes_file.clone());
+  ingest_options.ingest_node = false;
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+  assert_eq![sink.rows().len(), 2];
+  Ok(())
+}
+
+#[async_std::test]
+async fn memory_budget() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  assert_eq!["24G".parse::<MemoryBudget>()?.bytes, 24 << 30];
+  assert_eq!["512MiB".parse::<MemoryBudget>()?.bytes, 512 << 20];
+  assert_eq!["1.5k".parse::<MemoryBudget>()?.bytes, 1536];
+  assert_eq!["24G".parse::<MemoryBudget>()?.to_string(), "24G"];
+  assert!["G".parse::<MemoryBudget>().is_err()];
+  assert!["-1G".parse::<MemoryBudget>().is_err()];
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  assert_eq![BatchSizes::new(&ingest_options, &scan_table).way_batch_size, 10_000_000];
+  ingest_options.memory_budget = Some("1M".parse()?);
+  let small = BatchSizes::new(&ingest_options, &scan_table);
+  ingest_options.memory_budget = Some("64G".parse()?);
+  let large = BatchSizes::new(&ingest_options, &scan_table);
+  assert![small.way_batch_size < large.way_batch_size];
+  assert![small.relation_batch_size < large.relation_batch_size];
+  assert![small.channel_size <= large.channel_size];
+
+  // the batches of a tiny budget still make the same db
+  ingest_options.memory_budget = Some("1M".parse()?);
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut count = 0;
+  while let Some(result) = stream.next().await {
+    result?;
+    count += 1;
+  }
+  assert_eq![count, 4];
+  Ok(())
+}
+
+#[async_std::test]
+async fn memory() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest","optimize"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let ingest_options = IngestOptions::default();
+  let storage = MemoryStorage::default();
+  ingest.ingest(storage.open_db().await?, &pbf_file, scan_table, &ingest_options).await?;
+  assert![!storage.files().await.is_empty()];
+
+  // optimize from one memory db into another
+  let out_storage = MemoryStorage::default();
+  ingest.optimize(storage.open_db().await?, out_storage.open_db().await?, (2,2)).await?;
+  for storage in [storage,out_storage].iter() {
+    let mut db: MemDB = storage.open_db().await?;
+    let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+    let mut count = 0;
+    while let Some(result) = stream.next().await {
+      result?;
+      count += 1;
+    }
+    assert_eq![count, 4];
+  }
+  // the input db isn't read twice to count its rows, so there is no optimize total
+  let optimize = ingest.progress.read().await.info["optimize"].to_json();
+  assert![optimize["count"].as_u64().unwrap() >= 4];
+  assert![optimize["total"].is_null()];
+  Ok(())
+}
+
+#[async_std::test]
+async fn progress_eta() -> Result<(),Error> {
+  let mut info = Info::new("ingest");
+  assert![!info.to_string().contains('%')];
+  info.set_total(200);
+  assert_eq![info.percent(), Some(0.0)];
+  assert![info.to_string().ends_with("  0.0% eta --:--:--")];
+  info.start();
+  info.add(50);
+  info.tick();
+  assert_eq![info.percent(), Some(25.0)];
+  assert![info.eta().is_some()];
+  assert![info.to_string().contains(" 25.0% eta ")];
+  // counts past the total stay at 100%
+  info.add(250);
+  assert_eq![info.percent(), Some(100.0)];
+  info.end();
+  assert_eq![info.eta(), Some(std::time::Duration::from_secs(0))];
+  assert![info.to_string().ends_with("100.0% eta 00:00:00")];
+  Ok(())
+}
+
+#[async_std::test]
+async fn source() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let data = std::fs::read(&p)?;
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let file_table = ingest.scan(p.to_str().unwrap()).await?.to_bytes()?;
+  let buffer = BufferSource::new("ingest.pbf", data.clone());
+  let scan_table = ingest.scan_source(&buffer).await?;
+  assert_eq![scan_table.to_bytes()?, file_table];
+  let storage = MemoryStorage::default();
+  ingest.ingest_source(
+    storage.open_db().await?, Arc::new(buffer), scan_table, &IngestOptions::default()
+  ).await?;
+  let mut db: MemDB = storage.open_db().await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut count = 0;
+  while let Some(result) = stream.next().await {
+    result?;
+    count += 1;
+  }
+  assert_eq![count, 4];
+
+  // readers from a function
+  let data: Arc<[u8]> = data.into();
+  let size = data.len() as u64;
+  let fn_source = FnSource::new("fn", size, move || {
+    let r: SourceReader = Box::new(std::io::Cursor::new(data.clone()));
+    Ok(r)
+  });
+  assert_eq![fn_source.size()?, size];
+  assert_eq![fn_source.name(), "fn"];
+  assert_eq![ingest.scan_source(&fn_source).await?.to_bytes()?, file_table];
+
+  // a source that can't be opened fails with its name
+  let missing = FnSource::new("missing", 0, || {
+    Err(std::io::Error::new(std::io::ErrorKind::NotFound, "gone"))
+  });
+  match ingest.scan_source(&missing).await {
+    Err(IngestError::Io { file, .. }) => assert_eq![file, "missing"],
+    r => panic!["expected an io error, got {:?}", r.map(|_| ())],
+  }
+  Ok(())
+}
+
+#[async_std::test]
+async fn truncated() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let data = std::fs::read(&p)?;
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan_source(&BufferSource::new("ingest.pbf", data.clone())).await?;
+  // the pbf ends in the middle of the way blob, after the nodes
+  let (way_offset,byte_len,_) = *scan_table.way_interval_offsets.values().next().unwrap();
+  let truncated = BufferSource::new(
+    "truncated.pbf", data[..way_offset as usize + byte_len/2].to_vec()
+  );
+  let r = ingest.ingest_source(
+    VecSink::default(), Arc::new(truncated), scan_table, &IngestOptions::default()
+  ).await;
+  match r {
+    Err(IngestError::Blob { stage, offset, .. }) => {
+      assert_eq![stage, "way"];
+      assert_eq![offset, way_offset];
+    },
+    r => panic!["expected a blob error, got {:?}", r],
+  }
+  Ok(())
+}
+
+#[async_std::test]
+async fn stdin() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let storage = MemoryStorage::default();
+  ingest.ingest(
+    storage.open_db().await?, &pbf_file, scan_table, &IngestOptions::default()
+  ).await?;
+  let expected = all_rows(&mut storage.open_db().await?).await?;
+  assert_eq![expected.len(), 4];
+
+  // a reader that can't seek is spooled to a temp file, like stdin
+  let spool = SpoolSource::new("stdin", std::fs::File::open(&p)?, dir.path())?;
+  let spool_file = spool.path().to_path_buf();
+  assert_eq![spool.name(), "stdin"];
+  assert_eq![spool.size()?, std::fs::metadata(&p)?.len()];
+  let pbf: Arc<dyn Source> = Arc::new(spool);
+  let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+  let spool_storage = MemoryStorage::default();
+  ingest.ingest_source(
+    spool_storage.open_db().await?, pbf, scan_table, &IngestOptions::default()
+  ).await?;
+  assert_eq![all_rows(&mut spool_storage.open_db().await?).await?, expected];
+  // the temp file is gone once ingest drops the source
+  assert![!spool_file.exists()];
+
+  // -f - reads the pbf from stdin
+  let edb_dir = dir.path().join("edb");
+  let status = std::process::Command::new(env!("CARGO_BIN_EXE_peermaps-ingest"))
+    .args(&["ingest", "--no-monitor", "-f", "-", "-e"])
+    .arg(&edb_dir)
+    .stdin(std::fs::File::open(&p)?)
+    .status()?;
+  assert![status.success()];
+  let mut db: EDB = eyros::open_from_path2(&edb_dir).await?;
+  assert_eq![all_rows(&mut db).await?, expected];
+  Ok(())
+}
+
+#[async_std::test]
+async fn verify() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf: Arc<dyn Source> = Arc::new(FileSource::new(&p));
+
+  let mut ingest = Ingest::new(&["scan","ingest","verify"]);
+  let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+  let ingest_options = IngestOptions::default();
+  let storage = MemoryStorage::default();
+  ingest.ingest_source(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  let report = ingest.verify(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  assert_eq![report.expected, Counts { node: 2, way: 1, relation: 1 }];
+  assert_eq![report.found, report.expected];
+  assert![report.is_ok()];
+
+  // a db without relations is missing the lake
+  let mut partial_options = IngestOptions::default();
+  partial_options.ingest_relation = false;
+  let partial = MemoryStorage::default();
+  ingest.ingest_source(
+    partial.open_db().await?, pbf.clone(), scan_table.clone(), &partial_options
+  ).await?;
+  let report = ingest.verify(
+    partial.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  assert_eq![report.missing, vec![700*3+2]];
+  assert_eq![element_id(report.missing[0]), ("relation",700)];
+  assert![report.duplicated.is_empty()];
+  assert![report.unexpected.is_empty()];
+  assert![!report.is_ok()];
+
+  // ingesting twice without a checkpoint writes every row twice
+  ingest.ingest_source(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  let report = ingest.verify(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  assert_eq![
+    report.duplicated,
+    vec![(555*3+1,2), (700*3+2,2), (1312*3+0,2), (2000*3+0,2)]
+  ];
+  assert![report.missing.is_empty()];
+  assert![!report.is_ok()];
+
+  // a stale row with other content counts too, like the cafe from other rules
+  let feature_types = FeatureTypes::from_json(r#"{
+    "rules": [ { "tags": "amenity=cafe", "type": "amenity.restaurant" } ]
+  }"#)?;
+  let mut other_ingest = Ingest::from_feature_types(&["scan","ingest"], feature_types);
+  let stale = MemoryStorage::default();
+  ingest.ingest_source(
+    stale.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  other_ingest.ingest_source(
+    stale.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  let report = ingest.verify(
+    stale.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  assert_eq![
+    report.duplicated,
+    vec![(555*3+1,2), (700*3+2,2), (1312*3+0,2), (2000*3+0,2)]
+  ];
+
+  // the rows of another pbf are unexpected
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/merge_a.pbf");
+  let other: Arc<dyn Source> = Arc::new(FileSource::new(&p));
+  let other_table = ingest.scan_source(other.as_ref()).await?;
+  ingest.ingest_source(
+    partial.open_db().await?, other, other_table, &ingest_options
+  ).await?;
+  let report = ingest.verify(
+    partial.open_db().await?, pbf, scan_table, &ingest_options
+  ).await?;
+  assert_eq![report.unexpected, vec![9703*3+0, 9710*3+1]];
+  assert_eq![
+    report.unexpected.iter().map(|id| element_id(*id)).collect::<Vec<_>>(),
+    vec![("node",9703), ("way",9710)]
+  ];
+  assert_eq![report.missing, vec![700*3+2]];
+  Ok(())
+}
+
+#[async_std::test]
+async fn verify_dropped() -> Result<(),Error> {
+  let mut ingest = Ingest::new(&["scan","ingest","verify"]);
+
+  // the bbox drops the lake on purpose, so it isn't missing with the same bbox
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf: Arc<dyn Source> = Arc::new(FileSource::new(&p));
+  let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.bbox = Some("12,36,14,38".parse()?);
+  let storage = MemoryStorage::default();
+  ingest.ingest_source(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  let report = ingest.verify(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  assert_eq![report.expected, Counts { node: 2, way: 1, relation: 0 }];
+  assert![report.is_ok()];
+  let report = ingest.verify(
+    storage.open_db().await?, pbf, scan_table, &IngestOptions::default()
+  ).await?;
+  assert_eq![report.missing, vec![700*3+2]];
+
+  // elements the error policy skipped aren't missing, with or without a rejects file
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/rejects.pbf");
+  let pbf: Arc<dyn Source> = Arc::new(FileSource::new(&p));
+  let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+  let storage = MemoryStorage::default();
+  ingest.ingest_source(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &IngestOptions::default()
+  ).await?;
+  let mut verify_options = IngestOptions::default();
+  verify_options.error_policy = ErrorPolicy::Fail;
+  let report = ingest.verify(
+    storage.open_db().await?, pbf, scan_table, &verify_options
+  ).await?;
+  assert_eq![report.expected, Counts { node: 1, way: 0, relation: 0 }];
+  assert![report.is_ok()];
+
+  // coastline areas are counted as generated instead of unexpected
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/coastline_land.pbf");
+  let pbf: Arc<dyn Source> = Arc::new(FileSource::new(&p));
+  let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.coastline = Some(Coastline::new(coastline::Fill::Land));
+  let storage = MemoryStorage::default();
+  ingest.ingest_source(
+    storage.open_db().await?, pbf.clone(), scan_table.clone(), &ingest_options
+  ).await?;
+  let report = ingest.verify(
+    storage.open_db().await?, pbf, scan_table, &ingest_options
+  ).await?;
+  assert_eq![report.generated, 2];
+  assert![report.unexpected.is_empty()];
+  assert![report.is_ok()];
+  assert![report.to_string().contains("generated: 2")];
+  Ok(())
+}
+
+#[async_std::test]
+async fn stats() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let storage = MemoryStorage::default();
+  ingest.ingest(
+    storage.open_db().await?, &pbf_file, scan_table, &IngestOptions::default()
+  ).await?;
+
+  let progress = ingest.progress.clone();
+  let stats = stats::collect(progress, &mut storage.open_db().await?, 3).await?;
+  assert_eq![stats.total.count, 4];
+  assert_eq![stats.kinds.get("point").map(|x| x.count), Some(2)];
+  assert_eq![stats.kinds.get("area").map(|x| x.count), Some(2)];
+  assert_eq![stats.kinds.get("line"), None];
+  assert_eq![stats.feature_types.get(&get_type("amenity.cafe")).map(|x| x.count), Some(1)];
+  assert_eq![
+    stats.kinds.values().map(|x| x.bytes).sum::<u64>(),
+    stats.total.bytes
+  ];
+  let bbox = stats.bbox.unwrap();
+  assert![(bbox.west - 5.0).abs() < 1e-4 && (bbox.south + 10.01).abs() < 1e-4];
+  assert![(bbox.east - 13.03).abs() < 1e-4 && (bbox.north - 37.03).abs() < 1e-4];
+  assert_eq![stats.largest.len(), 3];
+  assert![stats.largest.windows(2).all(|w| w[0].bytes >= w[1].bytes)];
+
+  let json = stats.to_json();
+  assert_eq![json["count"], 4];
+  assert_eq![json["kinds"]["point"]["count"], 2];
+  assert_eq![json["largest"].as_array().map(|x| x.len()), Some(3)];
+  Ok(())
+}
+
+#[async_std::test]
+async fn query_geojson() -> Result<(),Error> {
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let storage = MemoryStorage::default();
+  ingest.ingest(
+    storage.open_db().await?, &pbf_file, scan_table, &IngestOptions::default()
+  ).await?;
+
+  let world: BBox = "-180,-90,180,90".parse()?;
+  let mut out = vec![];
+  let mut db = storage.open_db().await?;
+  let n = geojson::write(&mut db, &world, geojson::Format::NdJson, &mut out).await?;
+  assert_eq![n, 4];
+  let mut features = std::str::from_utf8(&out)?.lines()
+    .map(|line| serde_json::from_str(line))
+    .collect::<Result<Vec<serde_json::Value>,_>>()?;
+  features.sort_by_key(|f| f["id"].as_str().unwrap().to_string());
+  let ids = features.iter().map(|f| f["id"].as_str().unwrap()).collect::<Vec<_>>();
+  assert_eq![ids, vec!["node/1312","node/2000","relation/700","way/555"]];
+
+  let cafe = &features[0];
+  assert_eq![cafe["geometry"]["type"], "Point"];
+  assert_eq![cafe["properties"]["feature_type"], "amenity.cafe"];
+  assert_eq![cafe["properties"]["osm_type"], "node"];
+  assert_eq![cafe["properties"]["osm_id"], 1312];
+
+  // the lake is an outer ring with a hole, rebuilt from its triangles
+  let lake = &features[2];
+  assert_eq![lake["geometry"]["type"], "MultiPolygon"];
+  assert_eq![lake["properties"]["labels"]["name"], "cool lake"];
+  let polygons = lake["geometry"]["coordinates"].as_array().unwrap();
+  assert_eq![polygons.len(), 1];
+  let rings = polygons[0].as_array().unwrap();
+  assert_eq![rings.iter().map(|r| r.as_array().unwrap().len()).collect::<Vec<_>>(), vec![5,4]];
+  // the outer ring is counter-clockwise and the hole clockwise
+  let signed_area = |ring: &serde_json::Value| {
+    let points = ring.as_array().unwrap().iter()
+      .map(|p| (p[0].as_f64().unwrap(), p[1].as_f64().unwrap()))
+      .collect::<Vec<_>>();
+    points.windows(2).map(|w| w[0].0 * w[1].1 - w[1].0 * w[0].1).sum::<f64>()
+  };
+  assert![signed_area(&rings[0]) > 0.0];
+  assert![signed_area(&rings[1]) < 0.0];
+
+  let park = &features[3];
+  assert_eq![park["properties"]["labels"]["name"], "triangle park"];
+  assert_eq![park["properties"]["feature_type"], "leisure.park"];
+  assert_eq![park["geometry"]["coordinates"][0][0].as_array().map(|r| r.len()), Some(4)];
+
+  // a collection of the features in a box around the lake
+  let bbox: BBox = "4.9,-10.1,5.1,-9.9".parse()?;
+  let mut out = vec![];
+  let n = geojson::write(&mut db, &bbox, geojson::Format::GeoJson, &mut out).await?;
+  assert_eq![n, 1];
+  let collection: serde_json::Value = serde_json::from_slice(&out)?;
+  assert_eq![collection["type"], "FeatureCollection"];
+  assert_eq![collection["features"][0]["id"], "relation/700"];
+
+  // a row that can't be decoded is an error, but the collection is still closed
+  let bad_storage = MemoryStorage::default();
+  let mut bad_db = bad_storage.open_db().await?;
+  let bad_row: Row = ((C::Scalar(5.0),C::Scalar(-10.0)), vec![9,0,0,5].into());
+  bad_db.write(vec![bad_row]).await?;
+  bad_db.flush().await?;
+  let mut out = vec![];
+  let mut bad_db = bad_storage.open_db().await?;
+  assert![geojson::write(&mut bad_db, &bbox, geojson::Format::GeoJson, &mut out).await.is_err()];
+  let collection: serde_json::Value = serde_json::from_slice(&out)?;
+  assert_eq![collection["features"], serde_json::json!([])];
+  Ok(())
+}
+
+#[async_std::test]
+async fn sink() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.checkpoint_file = Some(dir.path().join("checkpoint"));
+  let sink = VecSink::default();
+  ingest.ingest(sink.clone(), &pbf_file, scan_table, &ingest_options).await?;
+  assert![sink.is_finished()];
+  // a flush for each checkpoint and one at the end
+  assert![sink.flushes() > 1];
+  let mut ids = sink.rows().iter()
+    .map(|(_,v)| match georender_pack::decode(&v.data) {
+      Ok(Feature::Point(x)) => x.id,
+      Ok(Feature::Line(x)) => x.id,
+      Ok(Feature::Area(x)) => x.id,
+      Err(e) => panic!["{}", e],
+    })
+    .collect::<Vec<_>>();
+  ids.sort_unstable();
+  assert_eq![ids, vec![555*3+1, 700*3+2, 1312*3+0, 2000*3+0]];
+  assert_eq![Checkpoint::load(&dir.path().join("checkpoint"))?.rows, 4];
+  Ok(())
+}
+
+#[async_std::test]
+async fn merge() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut sources = vec![];
+  let mut ingest = Ingest::new(&["scan","dedup","ingest"]);
+  for name in ["merge_a.pbf","merge_b.pbf"].iter() {
+    let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("tests/data/0");
+    p.push(name);
+    let pbf: Arc<dyn Source> = Arc::new(FileSource::new(p));
+    let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+    sources.push((pbf,scan_table));
+  }
+  let duplicates = ingest.dedup(&sources).await?;
+  // the cafe has a newer version in b. the road is the same in both so a keeps it
+  assert_eq![duplicates.node.get(&9703), Some(&1)];
+  assert_eq![duplicates.node.get(&9702), Some(&0)];
+  assert_eq![duplicates.way.get(&9710), Some(&0)];
+  assert_eq![duplicates.node.get(&9701), None];
+  assert![Duplicates::default().keep("node", 9703, 1)];
+  // splitting the ids between more workers finds the same copies
+  let tables = sources.iter().map(|(_,table)| table).collect::<Vec<_>>();
+  let progress = Arc::new(async_std::sync::RwLock::new(Progress::new(&["dedup"])));
+  let parallel = dedup::find_duplicates(progress, &tables, |i| {
+    let pbf = &sources[i].0;
+    let h = pbf.open().map_err(|source| IngestError::Io { file: pbf.name(), source })?;
+    let parser = osmpbf_parser::Parser::new(Box::new(h));
+    Ok(osmpbf_parser::Scan::from_table(parser, tables[i].clone()))
+  }, 8).await?;
+  assert_eq![parallel, duplicates];
+  ingest.ingest_files(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    sources, duplicates, &IngestOptions::default()
+  ).await?;
+
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((0.0,0.0),(2.0,2.0))).await?;
+  let mut results = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    results.push((pt,georender_pack::decode(&v.data)?));
+  }
+  results.sort_by_key(|(_,feature)| match feature {
+    Feature::Point(x) => x.id,
+    Feature::Line(x) => x.id,
+    Feature::Area(x) => x.id,
+  });
+  assert_eq![
+    results,
+    vec![
+      ((C::Scalar(1.6),C::Scalar(1.6)), Feature::Point(Point {
+        id: 9703*3+0,
+        feature_type: get_type("amenity.cafe"),
+        point: (1.6,1.6),
+        labels: vec![0],
+      })),
+      ((C::Interval(1.0,1.1),C::Interval(1.0,1.0)), Feature::Line(Line {
+        id: 9710*3+1,
+        feature_type: get_type("highway.residential"),
+        positions: vec![ 1.0, 1.0, 1.1, 1.0 ],
+        labels: vec![0],
+      })),
+      // way 9711 is only in b but its first node is only in a
+      ((C::Interval(1.0,1.2),C::Interval(1.0,1.2)), Feature::Line(Line {
+        id: 9711*3+1,
+        feature_type: get_type("highway.service"),
+        positions: vec![ 1.0, 1.0, 1.2, 1.2 ],
+        labels: vec![0],
+      })),
+    ]
+  ];
+  Ok(())
+}
+
+#[async_std::test]
+async fn resume() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let mut edb_dir = std::path::PathBuf::from(&dir.path());
+  edb_dir.push("edb");
+  let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+  p.push("tests/data/0/ingest.pbf");
+  let pbf_file = p.to_str().unwrap();
+
+  let mut ingest = Ingest::new(&["scan","ingest"]);
+  let scan_table = ingest.scan(&pbf_file).await?;
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.checkpoint_file = Some(dir.path().join("checkpoint"));
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table.clone(), &ingest_options
+  ).await?;
+  assert_eq![
+    Checkpoint::load(&dir.path().join("checkpoint"))?,
+    Checkpoint {
+      node: StageState::Done, way: StageState::Done, relation: StageState::Done,
+      coastline: StageState::Pending, rows: 4, rejects: 0,
+      sources: vec![pbf_file.to_string()],
+    }
+  ];
+
+  // an unknown stage is an error instead of a crash
+  let bad_file = dir.path().join("bad-checkpoint");
+  std::fs::write(&bad_file, "node=done\nbogus=done\n")?;
+  assert![Checkpoint::load(&bad_file).unwrap_err().to_string().contains("bogus")];
+
+  // every stage is done, so resuming must not insert anything again
+  ingest_options.resume = true;
+  ingest.ingest(
+    eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?,
+    &pbf_file, scan_table, &ingest_options
+  ).await?;
+  let mut db: EDB = eyros::open_from_path2(&std::path::Path::new(&edb_dir)).await?;
+  let mut stream = db.query(&((3.0,-15.0),(15.0,45.0))).await?;
+  let mut count = 0;
+  while let Some(result) = stream.next().await {
+    result?;
+    count += 1;
+  }
+  assert_eq![count, 4];
+  Ok(())
+}
+
+#[async_std::test]
+async fn resume_partial() -> Result<(),Error> {
+  let dir = Tmpfile::new().prefix("peermaps-ingest").tempdir()?;
+  let checkpoint_file = dir.path().join("checkpoint");
+  let mut sources = vec![];
+  let mut ingest = Ingest::new(&["scan","dedup","ingest"]);
+  for name in ["merge_a.pbf","merge_b.pbf"].iter() {
+    let mut p = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    p.push("tests/data/0");
+    p.push(name);
+    let pbf: Arc<dyn Source> = Arc::new(FileSource::new(p));
+    let scan_table = ingest.scan_source(pbf.as_ref()).await?;
+    sources.push((pbf,scan_table));
+  }
+  let duplicates = ingest.dedup(&sources).await?;
+  let full = VecSink::default();
+  ingest.ingest_files(
+    full.clone(), sources.clone(), duplicates.clone(), &IngestOptions::default()
+  ).await?;
+
+  // flushes come after the nodes of each pbf and then after the ways of each pbf.
+  // the one after the ways of b fails, so the last checkpoint is in the middle of
+  // the way stage, at the start of b.
+  let mut ingest_options = IngestOptions::default();
+  ingest_options.checkpoint_file = Some(checkpoint_file.clone());
+  let crashed = CrashSink::new(vec![], Some(3));
+  match ingest.ingest_files(
+    crashed.clone(), sources.clone(), duplicates.clone(), &ingest_options
+  ).await {
+    Err(IngestError::Write { .. }) => {},
+    r => panic!["expected a write error, got {:?}", r],
+  }
+  let checkpoint = Checkpoint::load(&checkpoint_file)?;
+  assert_eq![checkpoint.node, StageState::Done];
+  assert_eq![checkpoint.way, StageState::Offset(sources[0].0.size()?)];
+  assert_eq![checkpoint.relation, StageState::Pending];
+  assert_eq![checkpoint.rows, crashed.rows().len() as u64];
+
+  assert_eq![
+    checkpoint.sources,
+    sources.iter().map(|(pbf,_)| pbf.name()).collect::<Vec<_>>()
+  ];
+
+  // offsets span both pbfs, so a different list of inputs can't resume
+  ingest_options.resume = true;
+  let reordered = vec![sources[1].clone(), sources[0].clone()];
+  match ingest.ingest_files(
+    VecSink::default(), reordered, duplicates.clone(), &ingest_options
+  ).await {
+    Err(IngestError::Checkpoint { .. }) => {},
+    r => panic!["expected a checkpoint error, got {:?}", r],
+  }
+
+  // resuming on top of the flushed rows writes the ways of b and nothing twice
+  let resumed = CrashSink::new(crashed.rows(), None);
+  ingest.ingest_files(resumed.clone(), sources, duplicates, &ingest_options).await?;
+  assert![resumed.rows().len() > crashed.rows().len()];
+  assert_eq![decode_rows(&resumed.rows()), decode_rows(&full.rows())];
+  Ok(())
+}
+
+// keeps rows once they are flushed and fails every flush after the first n,
+// like an ingest that dies before it can save a checkpoint
+#[derive(Clone)]
+struct CrashSink {
+  state: Arc<std::sync::Mutex<CrashState>>,
+}
+
+struct CrashState {
+  pending: Vec<Row>,
+  rows: Vec<Row>,
+  flushes_left: Option<usize>,
+}
+
+impl CrashSink {
+  fn new(rows: Vec<Row>, flushes_left: Option<usize>) -> Self {
+    let state = CrashState { pending: vec![], rows, flushes_left };
+    Self { state: Arc::new(std::sync::Mutex::new(state)) }
+  }
+  fn rows(&self) -> Vec<Row> {
+    self.state.lock().unwrap().rows.clone()
+  }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Sink for CrashSink {
+  async fn write(&mut self, rows: Vec<Row>) -> Result<(),Error> {
+    self.state.lock().unwrap().pending.extend(rows);
+    Ok(())
+  }
+  async fn flush(&mut self) -> Result<(),Error> {
+    let mut state = self.state.lock().unwrap();
+    match state.flushes_left {
+      Some(0) => return Err("crashed".into()),
+      Some(n) => state.flushes_left = Some(n-1),
+      None => {},
+    }
+    let pending = std::mem::take(&mut state.pending);
+    state.rows.extend(pending);
+    Ok(())
+  }
+}
+
+// the osm id and positions of rows that are all lines, sorted
+fn lines(rows: &[Row]) -> Vec<(u64,Vec<f32>)> {
+  let mut lines = rows.iter()
+    .map(|(_,v)| match georender_pack::decode(&v.data).unwrap() {
+      Feature::Line(line) => (line.id/3, line.positions),
+      feature => panic!["expected a line, got {:?}", feature],
+    })
+    .collect::<Vec<_>>();
+  lines.sort_by(|a,b| a.partial_cmp(b).unwrap());
+  lines
+}
+
+// decoded rows, sorted so that sinks can be compared
+fn decode_rows(rows: &[Row]) -> Vec<String> {
+  let mut rows = rows.iter()
+    .map(|(pt,v)| format!["{:?}", (pt,georender_pack::decode(&v.data).unwrap())])
+    .collect::<Vec<_>>();
+  rows.sort();
+  rows
+}
+
+// every row of a db, decoded and sorted so that dbs can be compared
+async fn all_rows<S: eyros::RA>(db: &mut DB<S>) -> Result<Vec<String>,Error> {
+  let mut stream = db.query(&((-180.0,-90.0),(180.0,90.0))).await?;
+  let mut rows = vec![];
+  while let Some(result) = stream.next().await {
+    let (pt,v) = result?;
+    rows.push(format!["{:?}", (pt,georender_pack::decode(&v.data)?)]);
+  }
+  rows.sort();
+  Ok(rows)
+}
+
+#[async_std::test]
+async fn progress_output() -> Result<(),Error> {
+  let mut progress = Progress::new(&["scan","ingest"]);
+  let mut ended = std::collections::HashSet::new();
+  assert![progress.plain_lines(&mut ended).is_empty()];
+  progress.start("scan");
+  progress.add("scan", 1500);
+  let err: Error = "bad blob".into();
+  progress.push_err("scan", &err);
+  progress.tick();
+  let lines = progress.plain_lines(&mut ended);
+  assert_eq![lines.len(), 1];
+  assert![lines[0].starts_with("[scan ")];
+  assert![lines[0].contains("1_500")];
+  assert![!lines[0].contains('\x1b')];
+
+  // an ended stage is printed once more
+  progress.end("scan");
+  progress.start("ingest");
+  let lines = progress.plain_lines(&mut ended);
+  assert_eq![lines.len(), 2];
+  let lines = progress.plain_lines(&mut ended);
+  assert_eq![lines.len(), 1];
+  assert![lines[0].starts_with("[ingest ")];
+
+  let records = progress.json_lines().iter()
+    .map(|line| serde_json::from_str(line))
+    .collect::<Result<Vec<serde_json::Value>,_>>()?;
+  assert_eq![records.len(), 2];
+  let scan = &records[0];
+  let mut keys = scan.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+  keys.sort();
+  assert_eq![
+    keys,
+    vec!["count","elapsed","ended","errors","eta","label","percent","rate","started","total"]
+  ];
+  assert_eq![scan["label"], "scan"];
+  assert_eq![scan["count"], 1500];
+  assert_eq![scan["started"], true];
+  assert_eq![scan["ended"], true];
+  assert_eq![scan["errors"], serde_json::json!(["bad blob"])];
+  assert![scan["elapsed"].is_f64()];
+  assert![scan["total"].is_null() && scan["percent"].is_null() && scan["eta"].is_null()];
+  let ingest = &records[1];
+  assert_eq![ingest["label"], "ingest"];
+  assert_eq![ingest["count"], 0];
+  assert_eq![ingest["ended"], false];
+  assert_eq![ingest["errors"], serde_json::json!([])];
+  Ok(())
+}
+
 fn get_type(key: &str) -> u64 {
   *georender_pack::osm_types::get_types().get(key).unwrap()
 }